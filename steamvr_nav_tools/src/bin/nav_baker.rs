@@ -1,8 +1,11 @@
+use std::process::exit;
 use steamvr_nav_tools::nav_parser::{bake, parser};
 
 fn main() {
     println!("Reading nav data...");
-    let mut nav_file = parser::open("battleship_bay.nav").unwrap();
+    let Some(mut nav_file) = parser::open("battleship_bay.nav") else {
+        exit(1);
+    };
     nav_file.compress_area_ids();
 
     println!("Baking nav data for TypeScript...");
@@ -38,6 +38,11 @@ pub fn bake_nav_data_for_typescript(nav_file: NavFile) -> String {
             }
         }
 
+        // Attributes are optional in newNavArea, so areas without any stay as small as they were.
+        if nav_area.attributes != 0 {
+            connections_output.push_str(&format!(",{}", nav_area.attributes));
+        }
+
         output.push_str(&format!("p(a({},{},{}))\n", nav_area.id, polygon_output, connections_output));
     }
 
@@ -0,0 +1,294 @@
+use std::collections::{BTreeSet, HashMap};
+use bsi_tools_lib::math::Vector3;
+use crate::nav_parser::nav::{NavArea, NavAreaConnectionData, NavFile, MAGIC_PREFIX, NAV_DIRECTIONS, STEAM_VR_HOME_NAV_SUB_VERSION, STEAM_VR_HOME_NAV_VERSION};
+
+/// Grid offsets for each direction, in the same order as `NavArea::connections`.
+/// Same as Source, north is -Y and east is +X.
+const DIRECTION_OFFSETS: [(i64, i64); NAV_DIRECTIONS] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+#[derive(Debug, Clone)]
+pub struct GridSettings {
+    /// Distance between grid points. The nav builder uses 16 units.
+    pub grid_size: f64,
+    /// Largest height difference between cells merged into the same area.
+    pub max_height_difference: f64,
+    /// Largest height difference between neighbouring cells that still connects them.
+    pub step_height: f64,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        GridSettings {
+            grid_size: 16.0,
+            max_height_difference: 1.0,
+            step_height: 18.0,
+        }
+    }
+}
+
+/// A walkable point on the grid, and the nav area attributes it should end up with.
+#[derive(Debug, Clone)]
+pub struct GridPoint {
+    pub position: Vector3,
+    pub attributes: u32,
+}
+
+impl GridPoint {
+    pub fn new(position: Vector3, attributes: u32) -> Self {
+        GridPoint { position, attributes }
+    }
+}
+
+/// A walkable grid point, with the area it was merged into.
+struct GridCell {
+    x: i64,
+    y: i64,
+    z: f64,
+    attributes: u32,
+    area: Option<usize>,
+}
+
+/// A rectangle of grid cells, inclusive on both ends.
+struct GridRect {
+    min_x: i64,
+    min_y: i64,
+    max_x: i64,
+    max_y: i64,
+    z: f64,
+    attributes: u32,
+}
+
+struct Grid<'a> {
+    cells: Vec<GridCell>,
+    // Cell indices for every grid column. Columns can hold more than one floor.
+    columns: HashMap<(i64, i64), Vec<usize>>,
+    settings: &'a GridSettings,
+}
+
+impl<'a> Grid<'a> {
+    fn new(points: &[GridPoint], settings: &'a GridSettings) -> Self {
+        let mut grid = Grid {
+            cells: Vec::new(),
+            columns: HashMap::new(),
+            settings,
+        };
+
+        for point in points {
+            let position = &point.position;
+            let x = (position.x / settings.grid_size).round() as i64;
+            let y = (position.y / settings.grid_size).round() as i64;
+
+            // Points on the same floor of a column are duplicates.
+            if grid.find(x, y, position.z, settings.max_height_difference).is_some() {
+                continue;
+            }
+
+            grid.columns.entry((x, y)).or_default().push(grid.cells.len());
+            grid.cells.push(GridCell { x, y, z: position.z, attributes: point.attributes, area: None });
+        }
+
+        grid
+    }
+
+    /// Finds the cell in a column closest to the given height, if it's within the tolerance.
+    fn find(&self, x: i64, y: i64, z: f64, tolerance: f64) -> Option<usize> {
+        self.columns.get(&(x, y))?
+            .iter()
+            .copied()
+            .filter(|index| (self.cells[*index].z - z).abs() <= tolerance)
+            .min_by(|a, b| (self.cells[*a].z - z).abs().total_cmp(&(self.cells[*b].z - z).abs()))
+    }
+
+    /// Finds a cell that hasn't been merged into an area yet and is on the same plane as `z`, with the same attributes.
+    fn find_free(&self, x: i64, y: i64, z: f64, attributes: u32) -> Option<usize> {
+        self.find(x, y, z, self.settings.max_height_difference)
+            .filter(|index| self.cells[*index].area.is_none() && self.cells[*index].attributes == attributes)
+    }
+
+    /// Grows a rectangle from a cell, first along X and then along Y, as long as every cell is free, level and alike.
+    fn grow_rect(&mut self, start: usize, area: usize) -> GridRect {
+        let (min_x, min_y, z) = (self.cells[start].x, self.cells[start].y, self.cells[start].z);
+        let attributes = self.cells[start].attributes;
+
+        let mut max_x = min_x;
+        while self.find_free(max_x + 1, min_y, z, attributes).is_some() {
+            max_x += 1;
+        }
+
+        let mut max_y = min_y;
+        while (min_x..=max_x).all(|x| self.find_free(x, max_y + 1, z, attributes).is_some()) {
+            max_y += 1;
+        }
+
+        let mut z_total = 0.0;
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let index = self.find_free(x, y, z, attributes).unwrap();
+                self.cells[index].area = Some(area);
+                z_total += self.cells[index].z;
+            }
+        }
+
+        let cell_count = ((max_x - min_x + 1) * (max_y - min_y + 1)) as f64;
+        GridRect { min_x, min_y, max_x, max_y, z: z_total / cell_count, attributes }
+    }
+}
+
+impl GridRect {
+    /// Corners of the rectangle. Edge `i` runs from vertex `i` to vertex `i + 1` and faces direction `i`.
+    fn polygon(&self, grid_size: f64) -> Vec<Vector3> {
+        let half = grid_size / 2.0;
+        let (min_x, min_y) = (self.min_x as f64 * grid_size - half, self.min_y as f64 * grid_size - half);
+        let (max_x, max_y) = (self.max_x as f64 * grid_size + half, self.max_y as f64 * grid_size + half);
+
+        vec![
+            Vector3::new(min_x, min_y, self.z),
+            Vector3::new(max_x, min_y, self.z),
+            Vector3::new(max_x, max_y, self.z),
+            Vector3::new(min_x, max_y, self.z),
+        ]
+    }
+
+    /// Cells along the edge facing the given direction.
+    fn edge_cells(&self, direction: usize) -> Vec<(i64, i64)> {
+        match direction {
+            0 => (self.min_x..=self.max_x).map(|x| (x, self.min_y)).collect(),
+            1 => (self.min_y..=self.max_y).map(|y| (self.max_x, y)).collect(),
+            2 => (self.min_x..=self.max_x).map(|x| (x, self.max_y)).collect(),
+            _ => (self.min_y..=self.max_y).map(|y| (self.min_x, y)).collect(),
+        }
+    }
+}
+
+/// Merges walkable grid points (like the ones placed by the VTunnel nav builder) into rectangular nav areas.
+/// Rectangles are grown greedily, so the result isn't the fewest areas possible, but every area is convex.
+/// Only points with the same attributes are merged, so every area has the attributes of all its points.
+/// Areas are connected wherever two of their cells are next to each other and within a step of each other.
+/// Connections point at the edge of the other area that faces back, which is the opposite direction.
+pub fn nav_file_from_grid_points(points: &[GridPoint], settings: &GridSettings) -> NavFile {
+    let mut grid = Grid::new(points, settings);
+
+    // Go in a fixed order so the same points always give the same areas.
+    let mut order: Vec<usize> = (0..grid.cells.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&grid.cells[*a], &grid.cells[*b]);
+        (a.y, a.x).cmp(&(b.y, b.x)).then(a.z.total_cmp(&b.z))
+    });
+
+    let mut rects = Vec::new();
+    for index in order {
+        if grid.cells[index].area.is_none() {
+            let rect = grid.grow_rect(index, rects.len());
+            rects.push(rect);
+        }
+    }
+
+    let mut nav_areas = Vec::with_capacity(rects.len());
+    for (area_index, rect) in rects.iter().enumerate() {
+        let mut nav_area = NavArea {
+            id: area_index as u32 + 1,
+            attributes: rect.attributes,
+            polygon: rect.polygon(settings.grid_size),
+            ..Default::default()
+        };
+
+        for (direction, (offset_x, offset_y)) in DIRECTION_OFFSETS.iter().enumerate() {
+            let mut connected_areas = BTreeSet::new();
+            for (x, y) in rect.edge_cells(direction) {
+                let cell = grid.columns[&(x, y)].iter().copied().find(|index| grid.cells[*index].area == Some(area_index)).unwrap();
+                let neighbour = grid.find(x + offset_x, y + offset_y, grid.cells[cell].z, settings.step_height);
+                if let Some(other_area) = neighbour.and_then(|neighbour| grid.cells[neighbour].area) {
+                    if other_area != area_index {
+                        connected_areas.insert(other_area);
+                    }
+                }
+            }
+
+            nav_area.connections[direction] = connected_areas
+                .into_iter()
+                .map(|other_area| NavAreaConnectionData {
+                    area_id: other_area as u32 + 1,
+                    edge_index: ((direction + NAV_DIRECTIONS / 2) % NAV_DIRECTIONS) as u32,
+                })
+                .collect();
+        }
+
+        nav_areas.push(nav_area);
+    }
+
+    NavFile {
+        magic: MAGIC_PREFIX,
+        version: STEAM_VR_HOME_NAV_VERSION,
+        sub_version: STEAM_VR_HOME_NAV_SUB_VERSION,
+        is_analyzed: 0,
+        place_count: 0,
+        has_unnamed_areas: 1,
+        nav_areas,
+        trailing_data: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_parser::grid::{nav_file_from_grid_points, GridPoint, GridSettings};
+    use crate::nav_parser::nav::{NavFile, NAV_MESH_JUMP};
+
+    fn points(cells: &[(i64, i64, f64)]) -> Vec<GridPoint> {
+        cells.iter().map(|(x, y, z)| GridPoint::new(Vector3::new(*x as f64 * 16.0, *y as f64 * 16.0, *z), 0)).collect()
+    }
+
+    fn connected_ids(nav_file: &NavFile, area: usize, direction: usize) -> Vec<u32> {
+        nav_file.nav_areas[area].connections[direction].iter().map(|connection| connection.area_id).collect()
+    }
+
+    #[test]
+    fn test_grid_to_nav_areas() {
+        // A 3x2 floor with a ledge on its east side that's too tall to step onto, and a ramp step on its south side.
+        let mut cells = Vec::new();
+        for y in 0..2 {
+            for x in 0..3 {
+                cells.push((x, y, 0.0));
+            }
+            cells.push((3, y, 40.0));
+        }
+        cells.push((1, 2, 12.0));
+        // Duplicate point on the same floor.
+        cells.push((0, 0, 0.5));
+
+        let nav_file = nav_file_from_grid_points(&points(&cells), &GridSettings::default());
+        assert_eq!(nav_file.nav_areas.len(), 3);
+
+        let floor = &nav_file.nav_areas[0];
+        assert_eq!(floor.id, 1);
+        assert_eq!(floor.polygon, vec![
+            Vector3::new(-8.0, -8.0, 0.0),
+            Vector3::new(40.0, -8.0, 0.0),
+            Vector3::new(40.0, 24.0, 0.0),
+            Vector3::new(-8.0, 24.0, 0.0),
+        ]);
+
+        // The ledge is its own area with no way up, the step connects both ways.
+        let ledge = nav_file.nav_areas.iter().position(|area| area.polygon[0].z == 40.0).unwrap();
+        let step = nav_file.nav_areas.iter().position(|area| area.polygon[0].z == 12.0).unwrap();
+        assert!(connected_ids(&nav_file, 0, 1).is_empty());
+        assert!(connected_ids(&nav_file, ledge, 3).is_empty());
+        assert_eq!(connected_ids(&nav_file, 0, 2), vec![step as u32 + 1]);
+        assert_eq!(connected_ids(&nav_file, step, 0), vec![1]);
+        assert_eq!(nav_file.nav_areas[step].connections[0][0].edge_index, 2);
+    }
+
+    #[test]
+    fn test_grid_attributes() {
+        // A 4x1 strip where the middle two points need a jump.
+        let mut points = points(&[(0, 0, 0.0), (1, 0, 0.0), (2, 0, 0.0), (3, 0, 0.0)]);
+        points[1].attributes = NAV_MESH_JUMP;
+        points[2].attributes = NAV_MESH_JUMP;
+
+        let nav_file = nav_file_from_grid_points(&points, &GridSettings::default());
+        let attributes: Vec<u32> = nav_file.nav_areas.iter().map(|area| area.attributes).collect();
+        assert_eq!(attributes, vec![0, NAV_MESH_JUMP, 0]);
+        assert_eq!(connected_ids(&nav_file, 1, 1), vec![3]);
+        assert_eq!(connected_ids(&nav_file, 1, 3), vec![1]);
+    }
+}
@@ -1,4 +1,7 @@
 mod read;
+mod write;
 pub mod nav;
 pub mod parser;
-pub mod bake;
\ No newline at end of file
+pub mod bake;
+pub mod grid;
+pub mod writer;
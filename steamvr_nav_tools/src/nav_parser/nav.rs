@@ -10,6 +10,16 @@ pub const STEAM_VR_HOME_NAV_VERSION: u32 = 30;
 /// Nav file subversion for SteamVR Home.
 pub const STEAM_VR_HOME_NAV_SUB_VERSION: u32 = 0;
 
+/// Nav area attribute: have to crouch to get through. Same bit as Source.
+pub const NAV_MESH_CROUCH: u32 = 0x0001;
+
+/// Nav area attribute: have to jump to get up. Same bit as Source.
+pub const NAV_MESH_JUMP: u32 = 0x0002;
+
+/// Nav area attribute: Elizabeth shouldn't go here.
+/// Repurposed from Source's NAV_MESH_NO_HOSTAGES, which nothing in SteamVR Home uses.
+pub const NAV_MESH_NO_LIZ: u32 = 0x0800;
+
 /// Number of directions that a nav area can connect to.
 /// Clockwise: NORTH, EAST, SOUTH, WEST
 pub const NAV_DIRECTIONS: usize = 4;
@@ -23,10 +33,14 @@ pub struct NavFile {
     pub magic: u32,
     pub version: u32,
     pub sub_version: u32,
-    pub is_analyzed: bool,
+    /// 1 if the nav mesh has been analyzed. Kept as the byte in the file so it writes back unchanged.
+    pub is_analyzed: u8,
     pub place_count: u16,
-    pub has_unnamed_areas: bool,
+    /// 1 if some areas have no place name. Also kept as the byte in the file.
+    pub has_unnamed_areas: u8,
     pub nav_areas: Vec<NavArea>,
+    /// Everything after the last area, kept as is so the file can be written back unchanged.
+    pub trailing_data: Vec<u8>,
 }
 
 impl NavFile {
@@ -66,10 +80,16 @@ pub struct NavArea {
     pub id: u32,
     /// Attributes of the area. Might be able to repurpose these for custom attributes.
     pub attributes: u32,
+    /// Unknown data after the attributes, usually empty.
+    pub unknown_after_attributes: [u8; 5],
     pub polygon: Vec<Vector3>,
+    /// Unknown data after the polygon, also usually empty.
+    pub unknown_after_polygon: [u8; 4],
     /// Connections to other areas.
     /// Array index goes clockwise: NORTH, EAST, SOUTH, WEST
     pub connections: [Vec<NavAreaConnectionData>; NAV_DIRECTIONS],
+    /// Unknown data at the end of the area. Probably ladders.
+    pub unknown_after_connections: [u8; 13],
 }
 
 #[derive(Debug, Default)]
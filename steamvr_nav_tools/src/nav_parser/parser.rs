@@ -1,7 +1,6 @@
 use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
+use std::io::{BufReader, Read};
 use std::path::Path;
-use std::process::exit;
 use bsi_tools_lib::math::Vector3;
 use crate::nav_parser::nav::{MAGIC_PREFIX, NavArea, NavAreaConnectionData, NavFile, STEAM_VR_HOME_NAV_SUB_VERSION, STEAM_VR_HOME_NAV_VERSION};
 use crate::nav_parser::read::*;
@@ -10,10 +9,13 @@ pub fn open(path: &str) -> Option<NavFile> {
     let mut nav_file = NavFile::default();
 
     let nav_file_path = Path::new(path);
-    let file = File::open(&nav_file_path).unwrap_or_else(|err| {
-        eprintln!("Failed to open nav file: {}", err);
-        exit(1);
-    });
+    let file = match File::open(&nav_file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to open nav file: {}", err);
+            return None;
+        }
+    };
     let mut reader = BufReader::new(file);
 
     nav_file.magic = read_u32(&mut reader).unwrap();
@@ -34,9 +36,9 @@ pub fn open(path: &str) -> Option<NavFile> {
         return None;
     }
 
-    nav_file.is_analyzed = read_u8(&mut reader).unwrap() == 1;
+    nav_file.is_analyzed = read_u8(&mut reader).unwrap();
     nav_file.place_count = read_u16(&mut reader).unwrap();
-    nav_file.has_unnamed_areas = read_u8(&mut reader).unwrap() == 1;
+    nav_file.has_unnamed_areas = read_u8(&mut reader).unwrap();
 
     let area_count = read_u32(&mut reader).unwrap();
     nav_file.nav_areas.reserve(area_count as usize);
@@ -45,7 +47,7 @@ pub fn open(path: &str) -> Option<NavFile> {
         let mut nav_area = NavArea::default();
         nav_area.id = read_u32(&mut reader).unwrap();
         nav_area.attributes = read_u32(&mut reader).unwrap();
-        reader.seek(SeekFrom::Current(5)).unwrap(); // Skip unknown data (it's usually empty)
+        reader.read_exact(&mut nav_area.unknown_after_attributes).unwrap(); // Unknown data (it's usually empty)
 
         let polygon_count = read_u32(&mut reader).unwrap();
         for _ in 0..polygon_count {
@@ -55,7 +57,7 @@ pub fn open(path: &str) -> Option<NavFile> {
             nav_area.polygon.push(Vector3::new(x, y, z));
         }
 
-        reader.seek(SeekFrom::Current(4)).unwrap(); // Skip unknown data (it's also usually empty)
+        reader.read_exact(&mut nav_area.unknown_after_polygon).unwrap(); // Unknown data (it's also usually empty)
 
         for connections in nav_area.connections.iter_mut() {
             let connection_count = read_u32(&mut reader).unwrap();
@@ -69,17 +71,18 @@ pub fn open(path: &str) -> Option<NavFile> {
             }
         }
 
-        nav_file.nav_areas.push(nav_area);
-
-        // Skip unknown data
+        // Unknown data
         // I think this contains info for ladders and such, but I don't care about that.
         // If your nav has ladders it will likely break the parser when it tries to read the next area.
         // Based on the nav text format, there's still two directions for ladders (UP/DOWN).
         // Nobody is climbing ladders in SteamVR Home though...
-        reader.seek(SeekFrom::Current(13)).unwrap();
+        reader.read_exact(&mut nav_area.unknown_after_connections).unwrap();
+
+        nav_file.nav_areas.push(nav_area);
     }
 
-    // There's some other junk at the end of this file which I don't care about.
+    // There's some other junk at the end of this file which I don't care about, but the writer needs it.
+    reader.read_to_end(&mut nav_file.trailing_data).unwrap();
 
     Some(nav_file)
 }
@@ -98,9 +101,9 @@ mod tests {
         assert_eq!(nav_file.magic, MAGIC_PREFIX);
         assert_eq!(nav_file.version, STEAM_VR_HOME_NAV_VERSION);
         assert_eq!(nav_file.sub_version, STEAM_VR_HOME_NAV_SUB_VERSION);
-        assert_eq!(nav_file.is_analyzed, true);
+        assert_eq!(nav_file.is_analyzed, 1);
         assert_eq!(nav_file.place_count, 0);
-        assert_eq!(nav_file.has_unnamed_areas, true);
+        assert_eq!(nav_file.has_unnamed_areas, 1);
         assert_eq!(nav_file.nav_areas.len(), 2105);
 
         // If you can read to the end of the file, you probably did alright
@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use crate::nav_parser::nav::NavFile;
+use crate::nav_parser::write::*;
+
+/// Writes the nav file to the given path, in the same format `parser::open` reads.
+pub fn save(path: &str, nav_file: &NavFile) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_nav_file(&mut writer, nav_file)?;
+    writer.flush()
+}
+
+/// Serializes the nav file.
+/// Unknown data the parser kept is written back as is, so a parsed file writes back byte for byte.
+/// Positions are stored as f32, so they lose whatever precision they had past that.
+pub fn write_nav_file<W: Write>(writer: &mut W, nav_file: &NavFile) -> io::Result<()> {
+    write_u32(writer, nav_file.magic)?;
+    write_u32(writer, nav_file.version)?;
+    write_u32(writer, nav_file.sub_version)?;
+
+    write_u8(writer, nav_file.is_analyzed)?;
+    write_u16(writer, nav_file.place_count)?;
+    write_u8(writer, nav_file.has_unnamed_areas)?;
+
+    write_u32(writer, nav_file.nav_areas.len() as u32)?;
+    for nav_area in nav_file.nav_areas.iter() {
+        write_u32(writer, nav_area.id)?;
+        write_u32(writer, nav_area.attributes)?;
+        writer.write_all(&nav_area.unknown_after_attributes)?;
+
+        write_u32(writer, nav_area.polygon.len() as u32)?;
+        for vertex in nav_area.polygon.iter() {
+            write_f32(writer, vertex.x as f32)?;
+            write_f32(writer, vertex.y as f32)?;
+            write_f32(writer, vertex.z as f32)?;
+        }
+
+        writer.write_all(&nav_area.unknown_after_polygon)?;
+
+        for connections in nav_area.connections.iter() {
+            write_u32(writer, connections.len() as u32)?;
+            for connection in connections.iter() {
+                write_u32(writer, connection.area_id)?;
+                write_u32(writer, connection.edge_index)?;
+            }
+        }
+
+        writer.write_all(&nav_area.unknown_after_connections)?;
+    }
+
+    writer.write_all(&nav_file.trailing_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_parser::grid::{nav_file_from_grid_points, GridPoint, GridSettings};
+    use crate::nav_parser::nav::NAV_MESH_CROUCH;
+    use crate::nav_parser::parser::open;
+    use super::*;
+
+    fn to_bytes(nav_file: &NavFile) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_nav_file(&mut bytes, nav_file).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let points: Vec<GridPoint> = (0..3).map(|x| GridPoint::new(Vector3::new(x as f64 * 16.0, 0.0, 0.25), NAV_MESH_CROUCH * (x % 2))).collect();
+        let mut nav_file = nav_file_from_grid_points(&points, &GridSettings::default());
+        nav_file.is_analyzed = 1;
+        nav_file.nav_areas[1].unknown_after_attributes = [1, 2, 3, 4, 5];
+        nav_file.nav_areas[2].unknown_after_connections[12] = 0xFF;
+        nav_file.trailing_data = vec![0xDE, 0xAD, 0xBE, 0xEF];
+
+        let path = std::env::temp_dir().join(format!("steamvr_nav_tools_writer_test_{}.nav", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &nav_file).unwrap();
+        let read_back = open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(read_back.is_analyzed, 1);
+        assert_eq!(read_back.nav_areas.len(), 3);
+        assert_eq!(read_back.nav_areas[1].attributes, NAV_MESH_CROUCH);
+        assert_eq!(read_back.nav_areas[1].polygon, nav_file.nav_areas[1].polygon);
+        assert_eq!(read_back.nav_areas[0].connections[1][0].area_id, 2);
+        assert_eq!(read_back.trailing_data, nav_file.trailing_data);
+        assert_eq!(to_bytes(&read_back), to_bytes(&nav_file));
+    }
+
+    #[test]
+    fn test_round_trip_fixture() {
+        // Hand-written: two connected areas, an is_analyzed byte that isn't 0 or 1, unknown bytes and trailing data.
+        let path = "test_data/two_areas.nav";
+        let nav_file = open(path).unwrap();
+        assert_eq!(nav_file.is_analyzed, 2);
+        assert_eq!(nav_file.nav_areas.len(), 2);
+        assert_eq!(nav_file.nav_areas[1].polygon[1], Vector3::new(32.5, 0.0, 0.25));
+        assert_eq!(to_bytes(&nav_file), std::fs::read(path).unwrap());
+    }
+
+    // Run with `cargo test -- --ignored` once the file is there.
+    #[test]
+    #[ignore = "needs battleship_bay.nav from SteamVR Home copied into steamvr_nav_tools/, *.nav files aren't checked in"]
+    fn test_round_trip_battleship_bay() {
+        let nav_file = open("battleship_bay.nav").unwrap();
+        assert_eq!(to_bytes(&nav_file), std::fs::read("battleship_bay.nav").unwrap());
+    }
+}
@@ -12,6 +12,10 @@ export enum AreaCorner {
     NorthEast = 3
 }
 
+export const NAV_MESH_CROUCH = 0x0001;
+export const NAV_MESH_JUMP = 0x0002;
+export const NAV_MESH_NO_LIZ = 0x0800;
+
 export function directionToEdgeIndex(dir: NavDir): number {
     switch (dir) {
         case NavDir.North:
@@ -52,7 +56,7 @@ export class NavArea {
     // Area ID
     private id: number;
 
-    // Area Attributes. Not used for now.
+    // Area Attributes. Same bits as NAV_MESH_* in steamvr_nav_tools/src/nav_parser/nav.rs
     private attributes: number;
 
     // The shape of the area. For now most areas have 4 vertices that form a quad.
@@ -66,9 +70,9 @@ export class NavArea {
 
     private center: Vector;
 
-    public constructor(id: number, polygon: Vector[], connections: [NavConnection[], NavConnection[], NavConnection[], NavConnection[]]) {
+    public constructor(id: number, polygon: Vector[], connections: [NavConnection[], NavConnection[], NavConnection[], NavConnection[]], attributes: number = 0) {
         this.id = id;
-        this.attributes = 0;
+        this.attributes = attributes;
         this.polygon = polygon;
         this.connections = connections;
         this.center = Vector();
@@ -208,6 +212,7 @@ export const newNavArea = (
     connections1: [number, number][] = [],
     connections2: [number, number][] = [],
     connections3: [number, number][] = [],
+    attributes: number = 0,
 ) => new NavArea(
     areaID,
     [
@@ -221,5 +226,6 @@ export const newNavArea = (
         connections1.map(c => ({area: c[0] as any as NavArea, edgeIndex: c[1]} as NavConnection)),
         connections2.map(c => ({area: c[0] as any as NavArea, edgeIndex: c[1]} as NavConnection)),
         connections3.map(c => ({area: c[0] as any as NavArea, edgeIndex: c[1]} as NavConnection)),
-    ]
+    ],
+    attributes
 );
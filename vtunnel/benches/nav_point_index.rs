@@ -0,0 +1,70 @@
+use bsi_tools_lib::math::Vector3;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use vtunnel::nav_builder::nav_builder::{NavPoint, NavPointStore, NavType};
+use vtunnel::nav_builder::nav_index::NavPointIndex;
+
+/// Square grid of nav points, 16 units apart, about the size of battleship_bay's nav builder database.
+fn nav_points(width: i64) -> Vec<NavPoint> {
+    let mut nav_points = Vec::new();
+    for x in 0..width {
+        for y in 0..width {
+            let position = Vector3::new(x as f64 * 16.0, y as f64 * 16.0, (x / 10) as f64 * 8.0);
+            nav_points.push(NavPoint { id: nav_points.len() as u64, position, nav_type: NavType::Walkable, layer: NavPointStore::DEFAULT_LAYER.to_string() });
+        }
+    }
+    nav_points
+}
+
+/// What NavPointStore did before the index.
+fn scan_nearest<'a>(nav_points: &'a [NavPoint], position: &Vector3, max_distance: f64) -> Option<&'a NavPoint> {
+    let mut nearest_nav_point = None;
+    let mut nearest_distance = f64::MAX;
+    for nav_point in nav_points {
+        let distance = nav_point.position.distance(position);
+        if distance < nearest_distance {
+            nearest_distance = distance;
+            nearest_nav_point = Some(nav_point);
+        }
+    }
+    nearest_nav_point.filter(|_| nearest_distance <= max_distance)
+}
+
+fn scan_within_radius<'a>(nav_points: &'a [NavPoint], position: &Vector3, radius: f64) -> Vec<&'a NavPoint> {
+    let mut nav_points: Vec<&NavPoint> = nav_points.iter().collect();
+    nav_points.sort_by(|a, b| a.position.distance(position).total_cmp(&b.position.distance(position)));
+    nav_points.into_iter().filter(|nav_point| nav_point.position.distance(position) < radius).collect()
+}
+
+fn bench_nav_point_index(c: &mut Criterion) {
+    let position = Vector3::new(403.0, 517.0, 20.0);
+
+    for width in [50, 150] {
+        let nav_points = nav_points(width);
+        let mut index = NavPointIndex::new();
+        for nav_point in &nav_points {
+            index.insert(nav_point.clone());
+        }
+        let count = nav_points.len();
+
+        let mut group = c.benchmark_group("nearest");
+        group.bench_with_input(BenchmarkId::new("scan", count), &nav_points, |b, nav_points| {
+            b.iter(|| scan_nearest(nav_points, black_box(&position), 16.0))
+        });
+        group.bench_with_input(BenchmarkId::new("index", count), &index, |b, index| {
+            b.iter(|| index.nearest(black_box(&position), 16.0))
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group("within_radius");
+        group.bench_with_input(BenchmarkId::new("scan", count), &nav_points, |b, nav_points| {
+            b.iter(|| scan_within_radius(nav_points, black_box(&position), 256.0))
+        });
+        group.bench_with_input(BenchmarkId::new("index", count), &index, |b, index| {
+            b.iter(|| index.within_radius(black_box(&position), 256.0))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_nav_point_index);
+criterion_main!(benches);
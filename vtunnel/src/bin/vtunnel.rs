@@ -1,269 +1,242 @@
-use std::sync::{Arc};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use clap::Parser;
 use tokio::net::TcpStream;
-use tokio_stream::StreamExt;
-use tokio_util::codec::{Framed};
-use futures::SinkExt;
-use tokio::sync::{mpsc, Mutex};
-use vtunnel::game::elizabeth::Elizabeth;
-use vtunnel::game::gadget::GadgetTool;
-use vtunnel::game::player::{Player, PlayerInput};
-use vtunnel::nav_builder::nav_builder::NavBuilderProgram;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time::Instant;
+use vtunnel::config::{Cli, LogLevel, VTunnelConfig};
+use vtunnel::connection::{run_connection, spawn_message_handlers, ConsoleState};
+use vtunnel::game::state::GameState;
+use vtunnel::recording::{format_packet, read_recording_file, PacketDirection, SessionRecorder};
 use vtunnel::vconsole;
 use vtunnel::vconsole::Packet;
-use vtunnel::vtunnel::{VTunnelDeserializable, VTunnelMessage, VTunnelSerializable};
-use vtunnel::vtunnel_emitter::VTunnelEmitter;
+use vtunnel::vtunnel::VTunnelMessage;
+use vtunnel::vtunnel_emitter::EmitterHandle;
 
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let stream = TcpStream::connect("127.0.0.1:29009").await?;
-    let framed = Framed::new(stream, vconsole::PacketCodec);
-    let (inbox_sender, mut inbox_receiver) = mpsc::channel(1000);
-    let (inbox_reply_sender, mut inbox_reply_receiver) = mpsc::channel(1000);
-    let (outbox_sender, mut outbox_receiver) = mpsc::channel(1000);
-
-    let framed_sender = Arc::new(Mutex::new(framed));
-    let framed_receiver = Arc::clone(&framed_sender);
-
-    // Request the client to request a handshake on connect (for when server starts after client is running)
-    let handshake_vmsg = VTunnelMessage::new("vtunnel_request_handshake".to_string());
-    outbox_sender.send(vconsole::VTunnelMessagePacket::new(handshake_vmsg).to_packet()).await.unwrap();
-
-
-    let emitter = Arc::new(VTunnelEmitter::new(outbox_sender));
-    let emitter_receiver = Arc::clone(&emitter);
-    let mut console_state = ConsoleState::new(inbox_sender, inbox_reply_sender);
-    let emitter_static: &'static VTunnelEmitter = unsafe { // No no no no no no no no no no no no no
-        &*Arc::into_raw(emitter.clone())
+async fn main() {
+    let cli = Cli::parse();
+    let config = match VTunnelConfig::load(&cli) {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
     };
-    let mut game_state = GameState::new(emitter_static).await;
 
-    tokio::spawn(async move {
-        while let Some(packet) = outbox_receiver.recv().await {
-            let mut framed = framed_sender.lock().await;
-            framed.send(packet).await.unwrap();
+    if let Some(replay_path) = &cli.replay {
+        if let Err(err) = replay(config, replay_path, cli.replay_speed, cli.replay_output.as_deref()).await {
+            eprintln!("Replay failed: {}", err);
+            std::process::exit(1);
         }
-    });
+        return;
+    }
 
-    tokio::spawn(async move {
-        while let Some(vmsg) = inbox_receiver.recv().await {
-            game_state.handle_vmsg(vmsg).await;
-        }
-    });
+    let recorder = match &config.record {
+        Some(path) => match SessionRecorder::create(path) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("Failed to create recording {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-    tokio::spawn(async move {
-        while let Some(vmsg) = inbox_reply_receiver.recv().await {
-            emitter_receiver.handle_reply(vmsg).await;
-        }
-    });
+    let (inbox_sender, inbox_receiver) = mpsc::channel(1000);
+    let (inbox_reply_sender, inbox_reply_receiver) = mpsc::channel(1000);
+    let (outbox_sender, outbox_receiver) = mpsc::channel(1000);
 
-    loop {
-        let mut framed = framed_receiver.lock().await;
-        let packet = framed.next().await;
+    let emitter = EmitterHandle::with_timeout(outbox_sender, config.request_timeout());
+    let game_state = match GameState::new(emitter.clone(), &config).await {
+        Ok(game_state) => game_state,
+        Err(err) => {
+            eprintln!("Failed to open nav builder database {}: {}", config.database.display(), err);
+            std::process::exit(1);
+        }
+    };
+    spawn_message_handlers(game_state, emitter.clone(), inbox_receiver, inbox_reply_receiver);
 
-        match packet {
-            Some(Ok(packet)) => {
-                console_state.handle_packet(packet).await;
-            }
-            Some(Err(err)) => {
-                eprintln!("Error decoding packet: {:?}", err);
-                break;
-            }
-            None => {
-                println!("Connection closed by the server");
-                break;
-            }
+    tokio::select! {
+        _ = supervise_connection(&config, &emitter, inbox_sender, inbox_reply_sender, outbox_receiver, recorder) => {}
+        _ = tokio::signal::ctrl_c() => {
+            println!("Shutting down...");
         }
     }
 
-    Ok(())
+    emitter.shutdown().await;
 }
 
-struct ConsoleState {
+/**
+ * Keeps a VConsole connection alive, reconnecting with backoff whenever it drops.
+ */
+async fn supervise_connection(
+    config: &Arc<VTunnelConfig>,
+    emitter: &EmitterHandle,
     inbox_sender: mpsc::Sender<VTunnelMessage>,
     inbox_reply_sender: mpsc::Sender<VTunnelMessage>,
+    outbox_receiver: mpsc::Receiver<Packet>,
+    recorder: Option<SessionRecorder<BufWriter<File>>>,
+) {
+    // Each connection's writer task borrows these while it runs.
+    let outbox_receiver = Arc::new(Mutex::new(outbox_receiver));
+    let recorder = Arc::new(std::sync::Mutex::new(recorder));
+
+    // Game state outlives connections. VConsole goes away whenever SteamVR Home restarts or the addon reloads.
+    let mut reconnect_delay = RECONNECT_DELAY_MIN;
+    loop {
+        match TcpStream::connect(&config.address).await {
+            Ok(stream) => {
+                if config.log_level >= LogLevel::Normal {
+                    println!("Connected to VConsole at {}", config.address);
+                }
+                reconnect_delay = RECONNECT_DELAY_MIN;
 
-    program_args: String,
-    addon_name: String,
-    channels: Vec<String>,
-    convars: Vec<String>,
-    config_vars: Vec<String>,
-    packets_received: usize,
-}
-
-impl ConsoleState {
-    pub fn new(inbox_sender: mpsc::Sender<VTunnelMessage>, inbox_reply_sender: mpsc::Sender<VTunnelMessage>) -> ConsoleState {
-        ConsoleState {
-            inbox_sender,
-            inbox_reply_sender,
-
-            program_args: String::new(),
-            addon_name: String::new(),
-            channels: vec![],
-            convars: vec![],
-            config_vars: vec![],
-            packets_received: 0,
-        }
-    }
+                // Anything queued while disconnected was meant for the old session.
+                while outbox_receiver.lock().await.try_recv().is_ok() {}
 
-    pub fn print_state(&self) {
-        println!("Program args: {}", self.program_args);
-        println!("Addon: {}", self.addon_name);
-        println!("Channels: {:?}", self.channels.len());
-        println!("Convars: {:?}", self.convars.len());
-        println!("Config vars: {:?}", self.config_vars.len());
-        println!("Packet count: {}", self.packets_received);
-    }
+                let mut console_state = ConsoleState::new(Arc::clone(config), inbox_sender.clone(), inbox_reply_sender.clone());
+                match run_connection(stream, &outbox_receiver, &mut console_state, &recorder).await {
+                    Ok(_) => {
+                        if config.log_level >= LogLevel::Normal {
+                            println!("Connection closed by the server");
+                        }
+                    }
+                    Err(err) => eprintln!("VConsole connection error: {:?}", err),
+                }
+                if inbox_sender.is_closed() || inbox_reply_sender.is_closed() {
+                    eprintln!("Message handler stopped, not reconnecting");
+                    return;
+                }
 
-    pub async fn handle_packet(&mut self, packet: Packet) {
-        if self.packets_received == 4000 {
-            self.print_state();
-        }
+                if let Some(Err(err)) = recorder.lock().unwrap().as_mut().map(|recorder| recorder.flush()) {
+                    eprintln!("Failed to write recording: {}", err);
+                }
 
-        match packet.packet_type {
-            vconsole::PacketType::PRINT => {
-                let print_data = packet.get_print_data();
-                let vmsg = vtunnel::vtunnel::parse_vtunnel_message(&print_data);
-                if vmsg.is_some() {
-                    let vmsg = vmsg.unwrap();
-                    if vmsg.id == 0 {
-                        self.inbox_sender.send(vmsg).await.unwrap();
-                    } else {
-                        self.inbox_reply_sender.send(vmsg).await.unwrap();
-                    }
-                } else {
-                    println!("{}", print_data);
+                emitter.cancel_pending_requests().await;
+                if inbox_sender.send(VTunnelMessage::new("vtunnel_disconnected".to_string())).await.is_err() {
+                    eprintln!("Message handler stopped, not reconnecting");
+                    return;
                 }
             }
-            vconsole::PacketType::APP_INFO => {
-                self.program_args = packet.get_app_info_executable();
-            }
-            vconsole::PacketType::ADDON => {
-                self.addon_name = packet.get_addon_name();
-            }
-            vconsole::PacketType::CHANNEL => {
-                self.channels = packet.get_channel_data();
-            }
-            vconsole::PacketType::CONVAR => {
-                self.convars.push(packet.get_convar_name());
-            }
-            vconsole::PacketType::CONFIG_VAR => {
-                self.config_vars.push(packet.get_config_var_name());
-            }
-            _ => {
-                println!("Unknown packet {}: size: {}", packet.packet_type.to_string(), packet.data.len());
+            Err(err) => {
+                eprintln!("Failed to connect to VConsole at {}: {}", config.address, err);
             }
         }
 
-        self.packets_received += 1;
+        if config.log_level >= LogLevel::Normal {
+            println!("Reconnecting in {:?}...", reconnect_delay);
+        }
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(RECONNECT_DELAY_MAX);
     }
 }
 
-struct GameState {
-    session_id: u32,
-    session_ready: bool,
-
-    emitter: &'static VTunnelEmitter,
-    last_server_time: f64,
-    server_time: f64,
-    liz: Elizabeth,
-    player: Player,
-    gadget_tool: GadgetTool,
-}
-
-impl GameState {
-    pub async fn new(emitter: &'static VTunnelEmitter) -> GameState {
-        let gadget_tool = GadgetTool::new(NavBuilderProgram::new(emitter).await);
-
-        GameState {
-            session_id: 0,
-            session_ready: false,
+/**
+ * Feeds a recording back through ConsoleState and GameState as if the game were sending it.
+ * Packets we send are captured and compared against the ones in the recording.
+ * The nav builder works on a temporary copy of the database, so replayed edits never touch the real one.
+ */
+async fn replay(config: Arc<VTunnelConfig>, path: &Path, speed: f64, output: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let database_copy = std::env::temp_dir().join(format!("vtunnel_replay_{}.db", std::process::id()));
+    if config.database.exists() {
+        std::fs::copy(&config.database, &database_copy)?;
+    }
+    let config = Arc::new(VTunnelConfig { database: database_copy.clone(), ..(*config).clone() });
 
-            emitter,
-            last_server_time: 0.0,
-            server_time: 0.0,
-            liz: Elizabeth::new(),
-            player: Player::new(),
-            gadget_tool,
+    let result = replay_session(config, path, speed, output).await;
+    if let Err(err) = std::fs::remove_file(&database_copy) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Failed to remove {}: {}", database_copy.display(), err);
         }
     }
+    result
+}
 
-    pub fn print_state(&self) {
-        println!("Server time: {}", self.server_time);
-        println!("Liz: {:?}", self.liz);
-        println!("Player: {:?}", self.player);
-    }
+async fn replay_session(config: Arc<VTunnelConfig>, path: &Path, speed: f64, output: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let recording = read_recording_file(path)?;
 
-    pub async fn handle_vmsg(&mut self, vmsg: VTunnelMessage) {
-        let msg_name = vmsg.name.as_str();
+    let (inbox_sender, inbox_receiver) = mpsc::channel(1000);
+    let (inbox_reply_sender, inbox_reply_receiver) = mpsc::channel(1000);
+    let (outbox_sender, mut outbox_receiver) = mpsc::channel(1000);
 
-        // Handle when client requests handshake (for when client starts after server is running)
-        if msg_name == "vtunnel_request_handshake" {
-            self.session_ready = false;
-            self.session_id = rand::random::<u32>();
+    let emitter = EmitterHandle::with_timeout(outbox_sender, config.request_timeout());
+    let mut game_state = GameState::new(emitter.clone(), &config).await?;
 
-            let handshake_response = self.emitter.send_request::<VTunnelHandshake>(VTunnelHandshake::new(self.session_id)).await;
-            match handshake_response {
-                Ok(_) => {
-                    self.session_ready = true;
-                    let mut connected_vmsg = VTunnelMessage::new("vtunnel_connected".to_string());
-                    connected_vmsg.set_id(self.session_id as u64);
-                    self.emitter.send_vmsg(connected_vmsg).await;
-                }
-                Err(err) => {
-                    println!("VTunnel handshake failed... {} {:?}", self.session_id, err);
+    // Reuse the recorded session ids, otherwise the recorded handshake replies won't match our requests.
+    for recorded in recording.iter().filter(|recorded| recorded.direction == PacketDirection::Outgoing) {
+        if let vconsole::VConsolePacket::Command(command) = &recorded.packet {
+            for encoded_msg in command.vtunnel_messages() {
+                if let Ok(vmsg) = vtunnel::vtunnel::parse_vtunnel_message(encoded_msg) {
+                    if vmsg.name == "vtunnel_handshake" {
+                        game_state.preset_session_ids.push_back(vmsg.id as u32);
+                    }
                 }
             }
-            return;
         }
+    }
+    spawn_message_handlers(game_state, emitter.clone(), inbox_receiver, inbox_reply_receiver);
 
-        // Block all messages until client is ready
-        if !self.session_ready {
-            return;
+    let captured = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let (captured_count_sender, mut captured_count) = watch::channel(0usize);
+    let capture = Arc::clone(&captured);
+    tokio::spawn(async move {
+        while let Some(packet) = outbox_receiver.recv().await {
+            let mut captured = capture.lock().unwrap();
+            captured.push(format_packet(&vconsole::VConsolePacket::from_packet(packet)));
+            captured_count_sender.send_replace(captured.len());
         }
+    });
 
-        match msg_name {
-            "liz_state" => self.liz.apply_vtunnel_message(&vmsg),
-            "player_state" => self.player.apply_vtunnel_message(&vmsg),
-            "player_input" => {
-                let mut input = PlayerInput::new();
-                input.apply_vtunnel_message(&vmsg);
-                self.gadget_tool.input(input).await;
-            }
-            "gadget_activated" => {
-                self.gadget_tool.activate().await;
-            }
-            "gadget_deactivated" => {
-                self.gadget_tool.deactivate().await;
-            }
-            "world_state" => self.server_time = vmsg.data[0].get_float().unwrap_or_default(),
-            _ => {}
-        }
+    // Same as connecting for real.
+    emitter.send_vmsg(VTunnelMessage::new("vtunnel_request_handshake".to_string())).await;
+
+    let mut console_state = ConsoleState::new(Arc::clone(&config), inbox_sender, inbox_reply_sender);
+    let mut expected = Vec::new();
+    let start = Instant::now();
+    for recorded in recording {
+        match recorded.direction {
+            PacketDirection::Outgoing => expected.push(format_packet(&recorded.packet)),
+            PacketDirection::Incoming => {
+                if speed > 0.0 {
+                    tokio::time::sleep_until(start + recorded.timestamp.div_f64(speed)).await;
+                }
 
-        if self.server_time - self.last_server_time > 1.0 {
-            // self.print_state();
-            self.last_server_time = self.server_time;
+                // Wait for our side to catch up so replies don't arrive before their requests are sent.
+                let expected_count = expected.len();
+                let _ = tokio::time::timeout(config.request_timeout(), captured_count.wait_for(|count| *count >= expected_count)).await;
+                console_state.handle_packet(recorded.packet).await?;
+            }
         }
     }
-}
 
-#[derive(Debug)]
-pub struct VTunnelHandshake {
-    pub session_id: u32,
-}
+    let expected_count = expected.len();
+    let _ = tokio::time::timeout(config.request_timeout(), captured_count.wait_for(|count| *count >= expected_count)).await;
+    emitter.shutdown().await;
 
-impl VTunnelHandshake {
-    pub fn new(session_id: u32) -> VTunnelHandshake {
-        VTunnelHandshake {
-            session_id,
+    let captured = captured.lock().unwrap().clone();
+    match output {
+        Some(output) => {
+            let mut file = BufWriter::new(File::create(output)?);
+            for line in &captured {
+                writeln!(file, "{}", line)?;
+            }
+            file.flush()?;
         }
+        None => captured.iter().for_each(|line| println!("OUT {}", line)),
     }
-}
 
-impl VTunnelSerializable for VTunnelHandshake {
-    fn serialize(&self) -> VTunnelMessage {
-        let mut vmsg = VTunnelMessage::new("vtunnel_handshake".to_string());
-        vmsg.set_id(self.session_id as u64);
-        vmsg
+    match captured.iter().zip(expected.iter()).position(|(captured, expected)| captured != expected) {
+        Some(index) => println!("Outgoing packet {} differs from the recording:\n  recorded: {}\n  replayed: {}", index, expected[index], captured[index]),
+        None if captured.len() != expected.len() => println!("Replay sent {} packets, recording has {}", captured.len(), expected.len()),
+        None => println!("Replay matches the recording ({} packets sent)", captured.len()),
     }
+
+    Ok(())
 }
@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+use clap::Parser;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use vtunnel::mock_server::{run_mock_server, MockScene};
+
+/// Stands in for SteamVR Home's VConsole server so vtunnel can be run without the game.
+#[derive(Debug, Parser)]
+#[command(name = "vtunnel_mock")]
+struct Args {
+    /// Address to listen on.
+    #[arg(short, long, default_value = "127.0.0.1:29009")]
+    address: String,
+
+    /// TOML scene description. Defaults to a flat floor at z = 0.
+    #[arg(short, long)]
+    scene: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let scene = match &args.scene {
+        Some(path) => MockScene::from_file(path)?,
+        None => MockScene::default(),
+    };
+
+    let listener = TcpListener::bind(&args.address).await?;
+    println!("Mock VConsole listening on {} with {} solids", args.address, scene.solids.len());
+
+    // Nothing injects game messages from the command line yet, keep the sender alive so the channel stays open.
+    let (_injected_sender, injected) = mpsc::channel(16);
+    run_mock_server(listener, scene, injected).await?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use clap::Parser;
+use steamvr_nav_tools::nav_parser::bake;
+use steamvr_nav_tools::nav_parser::grid::{nav_file_from_grid_points, GridPoint, GridSettings};
+use vtunnel::nav_builder::nav_builder::{round_to_grid, NavPointStore};
+
+/// Bakes the nav builder's walkable points into the same TypeScript nav data nav_baker makes from a .nav file.
+#[derive(Debug, Parser)]
+#[command(name = "vtunnel_nav_bake")]
+struct Args {
+    /// Path to the nav builder SQLite database.
+    #[arg(long, default_value = "nav_builder.db")]
+    database: PathBuf,
+
+    /// Map (addon name) to bake. Defaults to the only map in the database.
+    #[arg(long)]
+    map: Option<String>,
+
+    /// Layers to bake, can be given more than once. Defaults to every layer.
+    /// Where layers overlap, the one listed first wins.
+    #[arg(long)]
+    layer: Vec<String>,
+
+    /// Where to write the baked TypeScript.
+    #[arg(short, long, default_value = "battleship_bay_nav.ts")]
+    output: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    println!("Reading nav points...");
+    let store = NavPointStore::open_map(&args.database, args.map.as_deref())?;
+    let layers = if args.layer.is_empty() { store.layers() } else { args.layer };
+
+    let mut taken = HashSet::new();
+    let mut walkable = Vec::new();
+    for layer in &layers {
+        let on_layer = store.nav_points().iter().filter(|nav_point| &nav_point.layer == layer && nav_point.nav_type.is_walkable());
+        for nav_point in on_layer {
+            if taken.insert(round_to_grid(&nav_point.position)) {
+                walkable.push(GridPoint::new(nav_point.position.clone(), nav_point.nav_type.nav_area_attributes()));
+            }
+        }
+    }
+    println!("Baking layers {:?} of map \"{}\"", layers, store.map_name());
+
+    println!("Merging {} walkable nav points into nav areas...", walkable.len());
+    let settings = GridSettings { grid_size: NavPointStore::NAV_POINT_GRID_SIZE, ..GridSettings::default() };
+    let mut nav_file = nav_file_from_grid_points(&walkable, &settings);
+    nav_file.compress_area_ids();
+
+    println!("Baking {} nav areas for TypeScript...", nav_file.nav_areas.len());
+    let baked_data = bake::bake_nav_data_for_typescript(nav_file);
+
+    println!("Writing nav data to {}...", args.output.display());
+    std::fs::write(&args.output, baked_data)?;
+    Ok(())
+}
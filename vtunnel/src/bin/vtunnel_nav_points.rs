@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand};
+use vtunnel::nav_builder::nav_builder::{NavPoint, NavPointStore};
+use vtunnel::nav_builder::nav_io::{export_nav_points, import_nav_points, parse_nav_points, NavPointFormat};
+
+/// Imports and exports the nav builder's nav points, so they can be shared and kept in git.
+#[derive(Debug, Parser)]
+#[command(name = "vtunnel_nav_points")]
+struct Args {
+    /// Path to the nav builder SQLite database.
+    #[arg(long, default_value = "nav_builder.db")]
+    database: PathBuf,
+
+    /// Map (addon name) to use. Defaults to the only map in the database.
+    #[arg(long)]
+    map: Option<String>,
+
+    /// Layer to export from or import into.
+    #[arg(long, default_value = NavPointStore::DEFAULT_LAYER)]
+    layer: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Writes every nav point to a file.
+    Export {
+        file: PathBuf,
+        /// Defaults to the file extension.
+        #[arg(short, long, value_enum)]
+        format: Option<NavPointFormat>,
+    },
+    /// Adds the nav points in a file, skipping any that are on a spot that already has one.
+    Import {
+        file: PathBuf,
+        /// Defaults to the file extension.
+        #[arg(short, long, value_enum)]
+        format: Option<NavPointFormat>,
+    },
+    /// Moves the nav points from before maps were tracked, and their undo history, to --map.
+    Adopt,
+}
+
+/// Imports are recorded under this user in the undo history.
+const IMPORT_USER_ID: u64 = 0;
+
+fn format_for(path: &Path, format: Option<NavPointFormat>) -> Result<NavPointFormat, String> {
+    format
+        .or_else(|| NavPointFormat::from_path(path))
+        .ok_or_else(|| format!("can't tell the format of {}, pass --format", path.display()))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    if matches!(args.command, Command::Adopt) && args.map.is_none() {
+        return Err("adopt needs --map to move the nav points to".into());
+    }
+    let mut store = NavPointStore::open_map(&args.database, args.map.as_deref())?;
+
+    match args.command {
+        Command::Export { file, format } => {
+            let format = format_for(&file, format)?;
+            let nav_points: Vec<NavPoint> = store.nav_points().iter().filter(|nav_point| nav_point.layer == args.layer).cloned().collect();
+            std::fs::write(&file, export_nav_points(&nav_points, format))?;
+            println!("Exported {} nav points from layer {} to {}", nav_points.len(), args.layer, file.display());
+        }
+        Command::Import { file, format } => {
+            let format = format_for(&file, format)?;
+            let nav_points = parse_nav_points(&std::fs::read_to_string(&file)?, format)?;
+            let group = store.begin_edit_group(IMPORT_USER_ID);
+            let summary = import_nav_points(&mut store, group, &args.layer, &nav_points)?;
+            println!("Imported {} nav points from {} into layer {}, skipped {} already taken", summary.added, file.display(), args.layer, summary.skipped);
+        }
+        Command::Adopt => {
+            let map_name = store.map_name().to_string();
+            let adopted = store.adopt_unnamed(&map_name)?;
+            println!("Moved {} nav points from before maps were tracked to {}", adopted, map_name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use vtunnel::schema;
+
+fn main() {
+    println!("Generating VTunnel message schemas for TypeScript...");
+    let schemas = schema::registry();
+    let generated = schema::generate_typescript(&schemas);
+
+    println!("Writing {} message schemas to file...", schemas.len());
+    std::fs::write("VTunnelMessages.ts", generated).unwrap();
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+pub const DEFAULT_CONFIG_PATH: &str = "vtunnel.toml";
+
+/**
+ * Gadget programs that can be loaded by name from the config.
+ */
+pub const KNOWN_PROGRAMS: &[&str] = &["nav_builder", "measure"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    // Only errors.
+    Quiet,
+    // Connection status, echoed console output and periodic state dumps.
+    Normal,
+    // Everything, including unknown packets.
+    Verbose,
+}
+
+/**
+ * Command line arguments for the vtunnel binary.
+ * Anything passed here overrides the config file.
+ */
+#[derive(Debug, Parser)]
+#[command(name = "vtunnel", about = "Bridges VConsole to the game's scripts", long_about = None)]
+pub struct Cli {
+    /// Path to a TOML config file. Defaults to vtunnel.toml if it exists.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// VConsole address to connect to.
+    #[arg(short, long)]
+    pub address: Option<String>,
+
+    /// Path to the nav builder SQLite database.
+    #[arg(long)]
+    pub database: Option<PathBuf>,
+
+    /// How long to wait for a reply to a request, in milliseconds.
+    #[arg(long)]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Log verbosity.
+    #[arg(long, value_enum)]
+    pub log_level: Option<LogLevel>,
+
+    /// Gadget program to load. Can be repeated.
+    #[arg(long = "program")]
+    pub programs: Vec<String>,
+
+    /// How far the nav builder's auto fill spreads from where it starts, in units.
+    #[arg(long)]
+    pub fill_radius: Option<f64>,
+
+    /// Only echo console output from these channels (comma separated, case insensitive).
+    #[arg(long, value_delimiter = ',')]
+    pub filter: Vec<String>,
+
+    /// Print console state every N packets. 0 disables it.
+    #[arg(long)]
+    pub print_state_every: Option<usize>,
+
+    /// Record every packet sent and received to this file.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
+    /// Replay a recording instead of connecting to VConsole.
+    /// The nav builder uses a temporary copy of the database, the real one is left alone.
+    #[arg(long, conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+
+    /// Replay speed multiplier. 0 replays as fast as possible.
+    #[arg(long, default_value_t = 1.0, requires = "replay")]
+    pub replay_speed: f64,
+
+    /// Write the packets sent during a replay to this file, one per line. Defaults to stdout.
+    #[arg(long, requires = "replay")]
+    pub replay_output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VTunnelConfig {
+    pub address: String,
+    pub database: PathBuf,
+    pub request_timeout_ms: u64,
+    pub log_level: LogLevel,
+    pub programs: Vec<String>,
+    pub fill_radius: f64,
+    pub filter: Vec<String>,
+    pub print_state_every: usize,
+    pub record: Option<PathBuf>,
+}
+
+impl Default for VTunnelConfig {
+    fn default() -> Self {
+        VTunnelConfig {
+            address: "127.0.0.1:29009".to_string(),
+            database: PathBuf::from("nav_builder.db"),
+            request_timeout_ms: 5000,
+            log_level: LogLevel::Normal,
+            programs: vec!["nav_builder".to_string()],
+            fill_radius: 512.0,
+            filter: vec![],
+            print_state_every: 4000,
+            record: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, source: std::io::Error },
+    Parse { path: PathBuf, source: toml::de::Error },
+    UnknownProgram(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "failed to read config {}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "failed to parse config {}: {}", path.display(), source),
+            ConfigError::UnknownProgram(name) => write!(f, "unknown gadget program \"{}\", expected one of {:?}", name, KNOWN_PROGRAMS),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            ConfigError::UnknownProgram(_) => None,
+        }
+    }
+}
+
+impl VTunnelConfig {
+    /**
+     * Builds the config from defaults, then the config file, then the command line.
+     * A missing default config file is fine, a missing explicit one is not.
+     */
+    pub fn load(cli: &Cli) -> Result<VTunnelConfig, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => VTunnelConfig::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => VTunnelConfig::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => VTunnelConfig::default(),
+        };
+
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<VTunnelConfig, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io { path: path.to_path_buf(), source })?;
+        VTunnelConfig::from_toml(&contents).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })
+    }
+
+    pub fn from_toml(contents: &str) -> Result<VTunnelConfig, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(address) = &cli.address {
+            self.address = address.clone();
+        }
+        if let Some(database) = &cli.database {
+            self.database = database.clone();
+        }
+        if let Some(request_timeout_ms) = cli.request_timeout_ms {
+            self.request_timeout_ms = request_timeout_ms;
+        }
+        if let Some(log_level) = cli.log_level {
+            self.log_level = log_level;
+        }
+        if !cli.programs.is_empty() {
+            self.programs = cli.programs.clone();
+        }
+        if let Some(fill_radius) = cli.fill_radius {
+            self.fill_radius = fill_radius;
+        }
+        if !cli.filter.is_empty() {
+            self.filter = cli.filter.clone();
+        }
+        if let Some(print_state_every) = cli.print_state_every {
+            self.print_state_every = print_state_every;
+        }
+        if let Some(record) = &cli.record {
+            self.record = Some(record.clone());
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.programs.iter().find(|name| !KNOWN_PROGRAMS.contains(&name.as_str())) {
+            Some(name) => Err(ConfigError::UnknownProgram(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms)
+    }
+
+    /**
+     * Whether console output from a channel should be echoed to stdout.
+     * Output from channels we haven't been told the name of yet is always shown.
+     */
+    pub fn echoes_channel(&self, channel: Option<&str>) -> bool {
+        match channel {
+            Some(channel) if !self.filter.is_empty() => self.filter.iter().any(|filter| filter.eq_ignore_ascii_case(channel)),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use clap::Parser;
+    use crate::config::{Cli, ConfigError, LogLevel, VTunnelConfig};
+
+    #[test]
+    fn test_config_precedence() {
+        let mut config = VTunnelConfig::from_toml(r#"
+            address = "192.168.1.20:29009"
+            request_timeout_ms = 10000
+            log_level = "verbose"
+            filter = ["VScript"]
+        "#).unwrap();
+        assert_eq!(config.database, PathBuf::from("nav_builder.db"));
+        assert_eq!(config.log_level, LogLevel::Verbose);
+
+        let cli = Cli::parse_from(["vtunnel", "--address", "127.0.0.1:29000", "--filter", "VScript,Server", "--database", "test.db"]);
+        config.apply_cli(&cli);
+        assert_eq!(config.address, "127.0.0.1:29000");
+        assert_eq!(config.database, PathBuf::from("test.db"));
+        assert_eq!(config.request_timeout_ms, 10000);
+        assert_eq!(config.filter, vec!["VScript", "Server"]);
+
+        assert!(config.echoes_channel(Some("vscript")));
+        assert!(!config.echoes_channel(Some("RenderSystem")));
+        assert!(config.echoes_channel(None));
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(VTunnelConfig::from_toml("adress = \"oops\"").is_err());
+
+        let cli = Cli::parse_from(["vtunnel", "--program", "flood_fill"]);
+        let mut config = VTunnelConfig::default();
+        config.apply_cli(&cli);
+        assert!(matches!(config.validate(), Err(ConfigError::UnknownProgram(name)) if name == "flood_fill"));
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use futures::SinkExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+use crate::config::{LogLevel, VTunnelConfig};
+use crate::game::state::GameState;
+use crate::recording::{PacketDirection, SessionRecorder};
+use crate::vconsole::{Packet, RawPacketCodec, VConsolePacket, VTunnelMessagePacket};
+use crate::vtunnel::{parse_vtunnel_message, VTunnelMessage, VTunnelParseError};
+use crate::vtunnel_emitter::EmitterHandle;
+
+// Shared between the reading side of a connection and its writer task, `None` when not recording.
+pub type SharedRecorder = Arc<std::sync::Mutex<Option<SessionRecorder<BufWriter<File>>>>>;
+
+/**
+ * Hands inbound messages to the game state, and replies to the emitter that's waiting on them.
+ */
+pub fn spawn_message_handlers(
+    mut game_state: GameState,
+    emitter: EmitterHandle,
+    mut inbox_receiver: mpsc::Receiver<VTunnelMessage>,
+    mut inbox_reply_receiver: mpsc::Receiver<VTunnelMessage>,
+) {
+    tokio::spawn(async move {
+        while let Some(vmsg) = inbox_receiver.recv().await {
+            game_state.handle_vmsg(vmsg).await;
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(vmsg) = inbox_reply_receiver.recv().await {
+            emitter.handle_reply(vmsg).await;
+        }
+    });
+}
+
+/**
+ * Pumps packets between VConsole and the rest of the program until the connection closes.
+ * Writing runs in its own task, so a full inbox holding up reading can't stop the outbox from draining.
+ */
+pub async fn run_connection(
+    stream: TcpStream,
+    outbox_receiver: &Arc<Mutex<mpsc::Receiver<Packet>>>,
+    console_state: &mut ConsoleState,
+    recorder: &SharedRecorder,
+) -> Result<(), std::io::Error> {
+    let (mut framed_sink, mut framed_stream) = futures::StreamExt::split(Framed::new(stream, RawPacketCodec));
+
+    // Request the client to request a handshake on connect (for when server starts after client is running)
+    let handshake_vmsg = VTunnelMessage::new("vtunnel_request_handshake".to_string());
+    let handshake_packet = VTunnelMessagePacket::new(handshake_vmsg).to_packet();
+    record_packet(recorder, PacketDirection::Outgoing, &handshake_packet)?;
+    framed_sink.send(handshake_packet).await?;
+
+    let (outbox_receiver, writer_recorder) = (Arc::clone(outbox_receiver), Arc::clone(recorder));
+    let mut writer = tokio::spawn(async move {
+        let mut outbox_receiver = outbox_receiver.lock().await;
+        while let Some(packet) = outbox_receiver.recv().await {
+            record_packet(&writer_recorder, PacketDirection::Outgoing, &packet)?;
+            framed_sink.send(packet).await?;
+        }
+        Ok::<(), std::io::Error>(())
+    });
+
+    let result = loop {
+        tokio::select! {
+            packet = framed_stream.next() => {
+                match packet {
+                    Some(Ok(packet)) => {
+                        if let Err(err) = record_packet(recorder, PacketDirection::Incoming, &packet) {
+                            break Err(err);
+                        }
+                        if let Err(err) = console_state.handle_packet(VConsolePacket::from_packet(packet)).await {
+                            break Err(err);
+                        }
+                    }
+                    Some(Err(err)) => break Err(err),
+                    None => break Ok(()),
+                }
+            }
+            written = &mut writer => {
+                // The writer is done and has let go of the outbox already.
+                return written.unwrap_or_else(|err| Err(std::io::Error::other(err)));
+            }
+        }
+    };
+
+    // Stops the writer and gives the outbox back for the next connection.
+    writer.abort();
+    let _ = writer.await;
+    result
+}
+
+fn record_packet(recorder: &SharedRecorder, direction: PacketDirection, packet: &Packet) -> Result<(), std::io::Error> {
+    match recorder.lock().unwrap().as_mut() {
+        Some(recorder) => recorder.record(direction, packet),
+        None => Ok(()),
+    }
+}
+
+/**
+ * What VConsole has told us about the game. VTunnel messages printed by the script are passed on to the message handlers.
+ */
+pub struct ConsoleState {
+    config: Arc<VTunnelConfig>,
+    inbox_sender: mpsc::Sender<VTunnelMessage>,
+    inbox_reply_sender: mpsc::Sender<VTunnelMessage>,
+
+    program_args: String,
+    addon_name: String,
+    channels: HashMap<u32, String>,
+    convars: Vec<String>,
+    config_vars: Vec<String>,
+    packets_received: usize,
+}
+
+impl ConsoleState {
+    pub fn new(config: Arc<VTunnelConfig>, inbox_sender: mpsc::Sender<VTunnelMessage>, inbox_reply_sender: mpsc::Sender<VTunnelMessage>) -> ConsoleState {
+        ConsoleState {
+            config,
+            inbox_sender,
+            inbox_reply_sender,
+
+            program_args: String::new(),
+            addon_name: String::new(),
+            channels: HashMap::new(),
+            convars: vec![],
+            config_vars: vec![],
+            packets_received: 0,
+        }
+    }
+
+    pub fn print_state(&self) {
+        println!("Program args: {}", self.program_args);
+        println!("Addon: {}", self.addon_name);
+        println!("Channels: {:?}", self.channels.len());
+        println!("Convars: {:?}", self.convars.len());
+        println!("Config vars: {:?}", self.config_vars.len());
+        println!("Packet count: {}", self.packets_received);
+    }
+
+    /**
+     * Fails only when the message handlers have stopped, at which point nothing more can be done with the connection.
+     */
+    pub async fn handle_packet(&mut self, packet: VConsolePacket) -> Result<(), std::io::Error> {
+        let print_state_every = self.config.print_state_every;
+        if self.packets_received > 0 && self.packets_received.is_multiple_of(print_state_every) && self.config.log_level >= LogLevel::Normal {
+            self.print_state();
+        }
+
+        match packet {
+            VConsolePacket::Print(print) => {
+                let print_data = print.text();
+                match parse_vtunnel_message(print_data) {
+                    Ok(vmsg) => {
+                        let sent = if vmsg.id == 0 {
+                            self.inbox_sender.send(vmsg).await
+                        } else {
+                            self.inbox_reply_sender.send(vmsg).await
+                        };
+                        sent.map_err(|_| Self::handler_stopped())?;
+                    }
+                    Err(VTunnelParseError::BadPrefix) => {
+                        let channel = self.channels.get(&print.channel_id).map(|channel| channel.as_str());
+                        if self.config.log_level >= LogLevel::Normal && self.config.echoes_channel(channel) {
+                            println!("{}", print_data);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Skipping malformed VTunnel message ({}): {}", err, print_data);
+                    }
+                }
+            }
+            VConsolePacket::AppInfo(app_info) => {
+                self.program_args = app_info.executable;
+            }
+            VConsolePacket::Addon(addon) => {
+                // The addon is the map, nav points are kept per addon.
+                let mut addon_vmsg = VTunnelMessage::new("vtunnel_addon".to_string());
+                addon_vmsg.add_string(addon.name.clone());
+                self.inbox_sender.send(addon_vmsg).await.map_err(|_| Self::handler_stopped())?;
+                self.addon_name = addon.name;
+            }
+            VConsolePacket::Channel(channel) => {
+                self.channels = channel.channels.into_iter().map(|channel| (channel.id, channel.name)).collect();
+            }
+            VConsolePacket::Convar(convar) => {
+                self.convars.push(convar.name);
+            }
+            VConsolePacket::ConfigVar(config_var) => {
+                self.config_vars.push(config_var.name);
+            }
+            VConsolePacket::PipeTest(_) | VConsolePacket::Focus(_) | VConsolePacket::Command(_) => {}
+            VConsolePacket::Unknown(packet) => {
+                if self.config.log_level >= LogLevel::Verbose {
+                    println!("Unknown packet {}: size: {}", packet.packet_type, packet.data.len());
+                }
+            }
+        }
+
+        self.packets_received += 1;
+        Ok(())
+    }
+
+    fn handler_stopped() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::BrokenPipe, "message handler stopped")
+    }
+}
@@ -0,0 +1,326 @@
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use futures::FutureExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::schema::VTunnelSchema;
+use crate::vtunnel::{VTunnelDeserializable, VTunnelMessage, VTunnelMessageError};
+
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult = Result<(), HandlerError>;
+
+// Returns false once the subscriber's queue is gone, so it can be dropped.
+type Subscriber<T> = Box<dyn Fn(T) -> bool + Send>;
+type Channels = HashMap<TypeId, Box<dyn Any + Send>>;
+type Decoder = Box<dyn Fn(&VTunnelMessage, &mut Channels) -> Result<usize, VTunnelMessageError> + Send + Sync>;
+
+/**
+ * Routes inbound VTunnel messages to typed subscribers.
+ *
+ * Every subscriber has its own unbounded queue, so events are never dropped however far behind it falls.
+ * `on` and `on_event` run every handler in its own task. Handlers see events of one type in the order
+ * they were published, but there's no ordering between different handlers. A handler that returns an
+ * error or panics is logged and keeps receiving events, without affecting any other handler.
+ *
+ * When events of different types have to be handled in the order they were published, `forward` and
+ * `forward_event` send them to a queue shared between those types instead.
+ */
+/**
+ * What happened to a published message.
+ */
+#[derive(Debug, Default)]
+pub struct Published {
+    // How many handlers will receive it.
+    pub receivers: usize,
+    // One for every subscribed type the message couldn't be decoded as.
+    pub errors: Vec<VTunnelMessageError>,
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    inner: Arc<Mutex<EventBusInner>>,
+}
+
+struct EventBusInner {
+    // Vec<Subscriber<T>> for every event type, keyed on the TypeId of T.
+    channels: Channels,
+    // Message name -> decoders for every type subscribed under that name.
+    decoders: HashMap<&'static str, HashMap<TypeId, Decoder>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            inner: Arc::new(Mutex::new(EventBusInner {
+                channels: HashMap::new(),
+                decoders: HashMap::new(),
+            })),
+        }
+    }
+
+    fn subscribers<T: Send + 'static>(channels: &mut Channels) -> &mut Vec<Subscriber<T>> {
+        channels
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Vec::<Subscriber<T>>::new()))
+            .downcast_mut::<Vec<Subscriber<T>>>()
+            .unwrap()
+    }
+
+    fn send<T: Clone + Send + 'static>(channels: &mut Channels, event: T) -> usize {
+        let subscribers = Self::subscribers::<T>(channels);
+        subscribers.retain(|subscriber| subscriber(event.clone()));
+        subscribers.len()
+    }
+
+    fn add_decoder<T>(inner: &mut EventBusInner)
+    where
+        T: VTunnelSchema + VTunnelDeserializable + Default + Clone + Send + 'static,
+    {
+        inner.decoders
+            .entry(T::schema().name)
+            .or_default()
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(|vmsg, channels| {
+                let mut event = T::default();
+                event.apply_vtunnel_message(vmsg)?;
+                Ok(Self::send(channels, event))
+            }));
+    }
+
+    /**
+     * Calls the handler with every VTunnel message named after `T`'s schema.
+     */
+    pub fn on<T, F, Fut>(&self, handler: F) -> JoinHandle<()>
+    where
+        T: VTunnelSchema + VTunnelDeserializable + Default + Clone + Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send,
+    {
+        Self::add_decoder::<T>(&mut self.inner.lock().unwrap());
+        self.on_event(handler)
+    }
+
+    /**
+     * Calls the handler with every event of type `T` passed to `emit`.
+     * Used for events that don't come from the game, like players joining.
+     */
+    pub fn on_event<T, F, Fut>(&self, handler: F) -> JoinHandle<()>
+    where
+        T: Clone + Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        self.forward_event(sender, |event: T| event);
+
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                match AssertUnwindSafe(handler(event)).catch_unwind().await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => eprintln!("{} handler failed: {}", type_name::<T>(), err),
+                    Err(_) => eprintln!("{} handler panicked", type_name::<T>()),
+                }
+            }
+        })
+    }
+
+    /**
+     * Sends every VTunnel message named after `T`'s schema to the queue, wrapped with `wrap`.
+     */
+    pub fn forward<T, E, W>(&self, sender: mpsc::UnboundedSender<E>, wrap: W)
+    where
+        T: VTunnelSchema + VTunnelDeserializable + Default + Clone + Send + 'static,
+        E: Send + 'static,
+        W: Fn(T) -> E + Send + 'static,
+    {
+        Self::add_decoder::<T>(&mut self.inner.lock().unwrap());
+        self.forward_event(sender, wrap);
+    }
+
+    /**
+     * Sends every event of type `T` passed to `emit` to the queue, wrapped with `wrap`.
+     */
+    pub fn forward_event<T, E, W>(&self, sender: mpsc::UnboundedSender<E>, wrap: W)
+    where
+        T: Clone + Send + 'static,
+        E: Send + 'static,
+        W: Fn(T) -> E + Send + 'static,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        Self::subscribers::<T>(&mut inner.channels).push(Box::new(move |event| sender.send(wrap(event)).is_ok()));
+    }
+
+    /**
+     * Sends an event to every `on_event::<T>` handler. Returns how many handlers will receive it.
+     */
+    pub fn emit<T: Clone + Send + 'static>(&self, event: T) -> usize {
+        Self::send(&mut self.inner.lock().unwrap().channels, event)
+    }
+
+    /**
+     * Decodes a message for every type subscribed under its name and sends it to their handlers.
+     * A type the message doesn't decode as is skipped, the other types still get it.
+     * Messages nobody subscribed to are dropped.
+     */
+    pub fn publish(&self, vmsg: &VTunnelMessage) -> Published {
+        let mut inner = self.inner.lock().unwrap();
+        let EventBusInner { channels, decoders } = &mut *inner;
+        let mut published = Published::default();
+        let Some(decoders) = decoders.get(vmsg.name.as_str()) else {
+            return published;
+        };
+
+        for decoder in decoders.values() {
+            match decoder(vmsg, channels) {
+                Ok(receivers) => published.receivers += receivers,
+                Err(err) => published.errors.push(err),
+            }
+        }
+        published
+    }
+
+    /**
+     * Drops every subscriber. Handlers finish the events already sent to them and then exit.
+     */
+    pub fn close(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.decoders.clear();
+        inner.channels.clear();
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+    use crate::event_bus::EventBus;
+    use crate::game::elizabeth::Elizabeth;
+    use crate::game::player::PlayerInput;
+    use crate::vtunnel::{VTunnelDataType, VTunnelMessage, VTunnelMessageError, VTunnelSerializable};
+
+    #[tokio::test]
+    async fn test_fan_out_and_isolation() {
+        let bus = EventBus::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let log = received.clone();
+        let first = bus.on(move |input: PlayerInput| {
+            let log = log.clone();
+            async move {
+                log.lock().unwrap().push(format!("first {}", input.user_id));
+                Ok(())
+            }
+        });
+        let log = received.clone();
+        let second = bus.on(move |input: PlayerInput| {
+            let log = log.clone();
+            async move {
+                if input.user_id == 2 {
+                    return Err("user 2 isn't allowed".into());
+                }
+                if input.user_id == 3 {
+                    panic!("user 3 isn't allowed either");
+                }
+                log.lock().unwrap().push(format!("second {}", input.user_id));
+                Ok(())
+            }
+        });
+        let log = received.clone();
+        let events = bus.on_event(move |event: &'static str| {
+            let log = log.clone();
+            async move {
+                log.lock().unwrap().push(event.to_string());
+                Ok(())
+            }
+        });
+
+        for user_id in 1..=4 {
+            let input = PlayerInput { user_id, ..PlayerInput::new() };
+            assert_eq!(bus.publish(&input.serialize()).receivers, 2);
+        }
+        assert_eq!(bus.emit("joined"), 1);
+
+        // Nobody is listening for these.
+        assert_eq!(bus.publish(&Elizabeth::new().serialize()).receivers, 0);
+        assert_eq!(bus.emit(5u32), 0);
+        // Subscribed, but the fields don't match.
+        assert_eq!(bus.publish(&VTunnelMessage::new("player_input".to_string())).errors.len(), 1);
+
+        bus.close();
+        for handle in [first, second, events] {
+            handle.await.unwrap();
+        }
+
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        assert_eq!(received, vec!["first 1", "first 2", "first 3", "first 4", "joined", "second 1", "second 4"]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_keeps_order() {
+        let bus = EventBus::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        bus.forward(sender.clone(), |input: PlayerInput| format!("input {}", input.user_id));
+        bus.forward(sender.clone(), |liz: Elizabeth| format!("liz {}", liz.current_state));
+        bus.forward_event(sender, |event: &'static str| event.to_string());
+
+        // More than a bounded channel would hold before anything reads it.
+        let mut expected = Vec::new();
+        for user_id in 0..2000 {
+            let input = PlayerInput { user_id, ..PlayerInput::new() };
+            assert_eq!(bus.publish(&input.serialize()).receivers, 1);
+            expected.push(format!("input {}", user_id));
+            if user_id % 100 == 0 {
+                let liz = Elizabeth { current_state: format!("state{}", user_id), ..Elizabeth::new() };
+                assert_eq!(bus.publish(&liz.serialize()).receivers, 1);
+                expected.push(format!("liz {}", liz.current_state));
+                assert_eq!(bus.emit("joined"), 1);
+                expected.push("joined".to_string());
+            }
+        }
+        bus.close();
+
+        let mut received = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            received.push(event);
+        }
+        assert_eq!(received, expected);
+    }
+
+    // Shares its name with PlayerInput, but expects text where the user id is.
+    #[derive(Debug, Default, Clone, VTunnelMessage)]
+    #[vtunnel(name = "player_input", crate = "crate")]
+    struct NamedInput {
+        user_name: String,
+    }
+
+    #[tokio::test]
+    async fn test_publish_after_failed_decode() {
+        let bus = EventBus::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        bus.forward(sender.clone(), |input: PlayerInput| format!("input {}", input.user_id));
+        bus.forward(sender, |input: NamedInput| format!("named {}", input.user_name));
+
+        let published = bus.publish(&PlayerInput { user_id: 7, ..PlayerInput::new() }.serialize());
+        assert_eq!(published.receivers, 1);
+        assert_eq!(published.errors, vec![VTunnelMessageError::WrongType {
+            message: "player_input".to_string(),
+            field: "user_name",
+            index: 0,
+            expected: VTunnelDataType::String,
+        }]);
+        bus.close();
+
+        assert_eq!(receiver.recv().await, Some("input 7".to_string()));
+        assert_eq!(receiver.recv().await, None);
+    }
+}
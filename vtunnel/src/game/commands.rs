@@ -1,6 +1,8 @@
 use bsi_tools_lib::math::Vector3;
-use crate::vtunnel::{VTunnelMessage, VTunnelSerializable};
+use crate::vtunnel::VTunnelMessage;
 
+#[derive(Debug, VTunnelMessage)]
+#[vtunnel(name = "draw_debug_sphere", crate = "crate")]
 pub struct DrawDebugSphere {
     pub position: Vector3,
     pub color: Vector3,
@@ -10,15 +12,12 @@ pub struct DrawDebugSphere {
     pub duration_seconds: f64,
 }
 
-impl VTunnelSerializable for DrawDebugSphere {
-    fn serialize(&self) -> VTunnelMessage {
-        let mut vmsg = VTunnelMessage::new("draw_debug_sphere".to_string());
-        vmsg.add_vector3(self.position.clone());
-        vmsg.add_vector3(self.color.clone());
-        vmsg.add_float(self.color_alpha.clone());
-        vmsg.add_float(self.radius.clone());
-        vmsg.add_bool(self.z_test.clone());
-        vmsg.add_float(self.duration_seconds.clone());
-        vmsg
-    }
+#[derive(Debug, VTunnelMessage)]
+#[vtunnel(name = "draw_debug_line", crate = "crate")]
+pub struct DrawDebugLine {
+    pub start_position: Vector3,
+    pub end_position: Vector3,
+    pub color: Vector3,
+    pub z_test: bool,
+    pub duration_seconds: f64,
 }
@@ -1,7 +1,8 @@
 use bsi_tools_lib::math::Vector3;
-use crate::vtunnel::{VTunnelDeserializable, VTunnelMessage};
+use crate::vtunnel::VTunnelMessage;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, VTunnelMessage)]
+#[vtunnel(name = "liz_state", crate = "crate")]
 pub struct Elizabeth {
     pub position: Vector3,
     pub rotation: Vector3,
@@ -21,10 +22,8 @@ impl Elizabeth {
     }
 }
 
-impl VTunnelDeserializable for Elizabeth {
-    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage) {
-        self.position = msg.data[0].get_vector3().unwrap();
-        self.rotation = msg.data[1].get_vector3().unwrap();
-        self.current_state = msg.data[2].get_string().unwrap().to_string();
+impl Default for Elizabeth {
+    fn default() -> Self {
+        Self::new()
     }
 }
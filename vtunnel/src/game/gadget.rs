@@ -1,14 +1,93 @@
-use crate::game::player::PlayerInput;
-use crate::nav_builder::nav_builder::NavBuilderProgram;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use async_trait::async_trait;
+use bsi_tools_lib::math::Vector3;
+use futures::FutureExt;
+use tokio::sync::{mpsc, Mutex};
+use crate::config::VTunnelConfig;
+use crate::event_bus::{EventBus, HandlerResult};
+use crate::game::measure::MeasureProgram;
+use crate::game::player::{input_button, PlayerEvent, PlayerInput};
+use crate::game::world::MapChanged;
+use crate::nav_builder::auto_fill::AutoFillSettings;
+use crate::nav_builder::nav_builder::{NavBuilderProgram, NavPointStore, NavStoreError};
+use crate::nav_builder::nav_sync::NavPointSync;
+use crate::vtunnel::VTunnelMessage;
+use crate::vtunnel_emitter::EmitterHandle;
+
+/**
+ * Tells the script which program is running on a player's gadget and what colour to draw it with.
+ */
+#[derive(Debug, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "gadget_state", crate = "crate")]
+pub struct GadgetState {
+    // Filled in by the `GadgetTool` that owns the program.
+    pub user_id: u64,
+    pub program_name: String,
+    pub mode: i64,
+    pub color: Vector3,
+}
+
+impl GadgetState {
+    pub fn new(program_name: &str, mode: i64, color: Vector3) -> Self {
+        GadgetState {
+            user_id: 0,
+            program_name: program_name.to_string(),
+            mode,
+            color,
+        }
+    }
+}
+
+/**
+ * Sent by the script when a player picks up the gadget.
+ */
+#[derive(Debug, Default, Clone, VTunnelMessage)]
+#[vtunnel(name = "gadget_activated", crate = "crate")]
+pub struct GadgetActivated {
+    pub user_id: u64,
+}
+
+/**
+ * Sent by the script when a player drops the gadget.
+ */
+#[derive(Debug, Default, Clone, VTunnelMessage)]
+#[vtunnel(name = "gadget_deactivated", crate = "crate")]
+pub struct GadgetDeactivated {
+    pub user_id: u64,
+}
+
+/**
+ * Sent by the script to switch a player's gadget to a program by name.
+ */
+#[derive(Debug, Default, Clone, VTunnelMessage)]
+#[vtunnel(name = "gadget_select_program", crate = "crate")]
+pub struct GadgetSelectProgram {
+    pub user_id: u64,
+    pub program_name: String,
+}
+
+#[async_trait]
+pub trait GadgetProgram: Send + Sync {
+    /**
+     * Unique name of the program, used to select it.
+     */
+    fn name(&self) -> &'static str;
 
-pub trait GadgetProgram: Send {
     /**
-     * Called when the gadget is activated.
+     * Current state to show on the gadget.
+     * The tool sends it whenever it changes, so programs don't need to send it themselves.
+     */
+    fn state(&self) -> GadgetState;
+
+    /**
+     * Called when the gadget is activated, or when switching to this program while it's active.
      */
     async fn on_activate(&mut self);
 
     /**
-     * Called when the gadget is deactivated.
+     * Called when the gadget is deactivated, or when switching away from this program while it's active.
      */
     async fn on_deactivate(&mut self);
 
@@ -16,34 +95,497 @@ pub trait GadgetProgram: Send {
      * Called when the gadget input is received.
      */
     async fn on_input(&mut self, input: &PlayerInput);
+
+    /**
+     * Called after the game switched to another map, for anything the program kept from the old one.
+     */
+    async fn on_map_changed(&mut self) {}
 }
 
+/**
+ * The gadget held by one player, with its own copy of every program.
+ */
 pub struct GadgetTool {
-    current_program: Option<NavBuilderProgram>,
+    emitter: EmitterHandle,
+    user_id: u64,
+    programs: Vec<Box<dyn GadgetProgram>>,
+    current_program: usize,
+    active: bool,
 }
 
 impl GadgetTool {
-    pub fn new(current_program: NavBuilderProgram) -> Self {
+    pub fn new(emitter: EmitterHandle, user_id: u64) -> Self {
         Self {
-            current_program: Some(current_program),
+            emitter,
+            user_id,
+            programs: Vec::new(),
+            current_program: 0,
+            active: false,
         }
     }
 
+    /**
+     * Adds a program to the gadget. The first program registered is selected by default.
+     */
+    pub fn register(&mut self, program: Box<dyn GadgetProgram>) {
+        self.programs.push(program);
+    }
+
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn program_names(&self) -> Vec<&'static str> {
+        self.programs.iter().map(|program| program.name()).collect()
+    }
+
+    pub fn current_program_name(&self) -> Option<&'static str> {
+        self.programs.get(self.current_program).map(|program| program.name())
+    }
+
+    /**
+     * Switches to the program with the given name. Returns false if there's no such program.
+     */
+    pub async fn select_program(&mut self, name: &str) -> bool {
+        match self.programs.iter().position(|program| program.name() == name) {
+            Some(index) => {
+                self.switch_to(index).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /**
+     * Moves forwards or backwards through the registered programs, wrapping around.
+     */
+    pub async fn cycle_program(&mut self, offset: isize) {
+        if self.programs.is_empty() {
+            return;
+        }
+
+        let count = self.programs.len() as isize;
+        let index = (self.current_program as isize + offset).rem_euclid(count);
+        self.switch_to(index as usize).await;
+    }
+
+    async fn switch_to(&mut self, index: usize) {
+        if index == self.current_program {
+            return;
+        }
+
+        if self.active {
+            if let Some(program) = self.programs.get_mut(self.current_program) {
+                program.on_deactivate().await;
+            }
+        }
+
+        self.current_program = index;
+        if self.active {
+            self.programs[index].on_activate().await;
+        }
+
+        self.emit_state().await;
+    }
+
+    fn state(&self) -> GadgetState {
+        let mut state = match self.programs.get(self.current_program) {
+            Some(program) => program.state(),
+            None => GadgetState::new("", 0, Vector3::default()),
+        };
+        state.user_id = self.user_id;
+        state
+    }
+
+    pub async fn emit_state(&self) {
+        let state = self.state();
+        self.emitter.send::<GadgetState>(&state).await;
+    }
+
     pub async fn activate(&mut self) {
-        if let Some(program) = &mut self.current_program {
+        self.active = true;
+        if let Some(program) = self.programs.get_mut(self.current_program) {
             program.on_activate().await;
         }
+        self.emit_state().await;
     }
 
     pub async fn deactivate(&mut self) {
-        if let Some(program) = &mut self.current_program {
+        self.active = false;
+        if let Some(program) = self.programs.get_mut(self.current_program) {
             program.on_deactivate().await;
         }
+        self.emit_state().await;
+    }
+
+    pub async fn map_changed(&mut self) {
+        for program in &mut self.programs {
+            program.on_map_changed().await;
+        }
     }
 
     pub async fn input(&mut self, input: PlayerInput) {
-        if let Some(program) = &mut self.current_program {
+        // Pad left/right switches programs, everything else is handed to the current program.
+        if self.active && input.is_pressed(input_button::IN_PAD_LEFT_HAND1) {
+            self.cycle_program(-1).await;
+            return;
+        }
+        if self.active && input.is_pressed(input_button::IN_PAD_RIGHT_HAND1) {
+            self.cycle_program(1).await;
+            return;
+        }
+
+        let previous_state = self.state();
+        if let Some(program) = self.programs.get_mut(self.current_program) {
             program.on_input(&input).await;
         }
+        if self.state() != previous_state {
+            self.emit_state().await;
+        }
+    }
+}
+
+/**
+ * Everything the gadgets react to, kept in the order the bus published it.
+ */
+pub enum GadgetEvent {
+    Input(PlayerInput),
+    Activated(GadgetActivated),
+    Deactivated(GadgetDeactivated),
+    SelectProgram(GadgetSelectProgram),
+    MapChanged(MapChanged),
+    Player(PlayerEvent),
+}
+
+/**
+ * Every player's gadget, created the first time they use it.
+ * Each gadget runs in its own task and handles its player's events in order, so one player's
+ * program doesn't hold up anyone else's.
+ */
+pub struct Gadgets {
+    emitter: EmitterHandle,
+    // Queue of the task running each player's gadget.
+    tools: HashMap<u64, mpsc::UnboundedSender<GadgetEvent>>,
+    programs: Vec<String>,
+    fill_settings: AutoFillSettings,
+    // Shared by every player's nav builder, only opened if the nav builder is enabled.
+    nav_point_store: Option<Arc<Mutex<NavPointStore>>>,
+    // There's only one script, so every player's nav builder syncs through the same one.
+    nav_sync: Arc<Mutex<NavPointSync>>,
+}
+
+impl Gadgets {
+    pub fn new(emitter: EmitterHandle, config: &VTunnelConfig) -> Result<Gadgets, NavStoreError> {
+        let nav_point_store = match config.programs.iter().any(|name| name == "nav_builder") {
+            true => Some(Arc::new(Mutex::new(NavPointStore::open(&config.database)?))),
+            false => None,
+        };
+
+        Ok(Gadgets {
+            emitter,
+            tools: HashMap::new(),
+            programs: config.programs.clone(),
+            fill_settings: AutoFillSettings { radius: config.fill_radius, ..AutoFillSettings::default() },
+            nav_point_store,
+            nav_sync: Arc::new(Mutex::new(NavPointSync::new())),
+        })
+    }
+
+    pub fn subscribe(self, bus: &EventBus) {
+        // One queue for every gadget event, so an activation is never handled after the input that followed it.
+        let (sender, receiver) = mpsc::unbounded_channel();
+        bus.forward(sender.clone(), GadgetEvent::Input);
+        bus.forward(sender.clone(), GadgetEvent::Activated);
+        bus.forward(sender.clone(), GadgetEvent::Deactivated);
+        bus.forward(sender.clone(), GadgetEvent::SelectProgram);
+        bus.forward_event(sender.clone(), GadgetEvent::MapChanged);
+        bus.forward_event(sender, GadgetEvent::Player);
+
+        tokio::spawn(self.dispatch(receiver));
+    }
+
+    /**
+     * Hands every event to the gadget of the player it's for.
+     */
+    async fn dispatch(mut self, mut receiver: mpsc::UnboundedReceiver<GadgetEvent>) {
+        while let Some(event) = receiver.recv().await {
+            let user_id = match &event {
+                GadgetEvent::Input(input) => input.user_id,
+                GadgetEvent::Activated(activated) => activated.user_id,
+                GadgetEvent::Deactivated(deactivated) => deactivated.user_id,
+                GadgetEvent::SelectProgram(select) => select.user_id,
+                GadgetEvent::MapChanged(changed) => {
+                    if let Err(err) = self.switch_map(&changed.map_name).await {
+                        eprintln!("Failed to switch nav builder map: {}", err);
+                    }
+                    // Every gadget drops what it kept from the old map, after what's already queued for it.
+                    for tool in self.tools.values() {
+                        let _ = tool.send(GadgetEvent::MapChanged(changed.clone()));
+                    }
+                    continue;
+                }
+                GadgetEvent::Player(PlayerEvent::Left(player)) => {
+                    // The gadget finishes what's already queued and then deactivates.
+                    self.tools.remove(&player.user_id);
+                    continue;
+                }
+                GadgetEvent::Player(PlayerEvent::Joined(_)) => continue,
+            };
+
+            if self.tool(user_id).send(event).is_err() {
+                eprintln!("Gadget of user {} stopped", user_id);
+                self.tools.remove(&user_id);
+            }
+        }
+    }
+
+    /**
+     * Moves the nav builder to another map, and sends the script that map's nav points in place of the old ones.
+     */
+    async fn switch_map(&self, map_name: &str) -> Result<(), NavStoreError> {
+        if let Some(store) = &self.nav_point_store {
+            let nav_points = {
+                let mut store = store.lock().await;
+                store.switch_map(map_name)?;
+                store.nav_points().to_vec()
+            };
+            println!("Nav builder is on map \"{}\" with {} nav points", map_name, nav_points.len());
+            self.nav_sync.lock().await.send_changes(&self.emitter, nav_points).await;
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns the queue of the gadget owned by the given player, creating the gadget if needed.
+     * Programs are registered in the order they're listed, the first one is selected by default.
+     */
+    fn tool(&mut self, user_id: u64) -> &mpsc::UnboundedSender<GadgetEvent> {
+        let emitter = &self.emitter;
+        let nav_point_store = &self.nav_point_store;
+        let programs = &self.programs;
+        let fill_settings = &self.fill_settings;
+        let nav_sync = &self.nav_sync;
+
+        self.tools.entry(user_id).or_insert_with(|| {
+            let mut tool = GadgetTool::new(emitter.clone(), user_id);
+            for name in programs {
+                match (name.as_str(), nav_point_store) {
+                    ("nav_builder", Some(store)) => tool.register(Box::new(NavBuilderProgram::new(emitter.clone(), store.clone(), nav_sync.clone(), user_id, fill_settings.clone()))),
+                    ("measure", _) => tool.register(Box::new(MeasureProgram::new(emitter.clone()))),
+                    _ => eprintln!("Unknown gadget program \"{}\", skipping", name),
+                }
+            }
+
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(Self::run_tool(tool, receiver));
+            sender
+        })
+    }
+
+    /**
+     * Handles one player's gadget events in order until the player leaves, then deactivates the gadget.
+     * An event that fails or panics is logged and the gadget carries on with the next one.
+     */
+    async fn run_tool(mut tool: GadgetTool, mut receiver: mpsc::UnboundedReceiver<GadgetEvent>) {
+        while let Some(event) = receiver.recv().await {
+            match AssertUnwindSafe(Self::handle_tool_event(&mut tool, event)).catch_unwind().await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => eprintln!("Gadget of user {} failed: {}", tool.user_id(), err),
+                Err(_) => eprintln!("Gadget of user {} panicked", tool.user_id()),
+            }
+        }
+
+        if tool.is_active() {
+            tool.deactivate().await;
+        }
+    }
+
+    async fn handle_tool_event(tool: &mut GadgetTool, event: GadgetEvent) -> HandlerResult {
+        match event {
+            GadgetEvent::Input(input) => tool.input(input).await,
+            GadgetEvent::Activated(_) => tool.activate().await,
+            GadgetEvent::Deactivated(_) => tool.deactivate().await,
+            GadgetEvent::SelectProgram(select) => {
+                if !tool.select_program(&select.program_name).await {
+                    return Err(format!("unknown gadget program \"{}\", expected one of {:?}", select.program_name, tool.program_names()).into());
+                }
+            }
+            GadgetEvent::MapChanged(_) => tool.map_changed().await,
+            GadgetEvent::Player(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use bsi_tools_lib::math::Vector3;
+    use tokio::sync::mpsc;
+    use crate::config::VTunnelConfig;
+    use crate::event_bus::EventBus;
+    use crate::game::gadget::{GadgetActivated, GadgetDeactivated, GadgetProgram, GadgetState, GadgetTool, Gadgets};
+    use crate::game::player::{input_button, Player, PlayerEvent, PlayerInput};
+    use crate::game::world::MapChanged;
+    use crate::nav_builder::nav_builder::{NavPointStore, NavType};
+    use crate::vconsole::CommandPacket;
+    use crate::vtunnel::{parse_vtunnel_message, VTunnelDeserializable, VTunnelSerializable};
+    use crate::vtunnel_emitter::EmitterHandle;
+
+    struct DummyProgram {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl GadgetProgram for DummyProgram {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn state(&self) -> GadgetState {
+            GadgetState::new(self.name, 1, Vector3::new(255.0, 0.0, 0.0))
+        }
+
+        async fn on_activate(&mut self) {
+            self.events.lock().unwrap().push(format!("{} activate", self.name));
+        }
+
+        async fn on_deactivate(&mut self) {
+            self.events.lock().unwrap().push(format!("{} deactivate", self.name));
+        }
+
+        async fn on_input(&mut self, _input: &PlayerInput) {
+            self.events.lock().unwrap().push(format!("{} input", self.name));
+        }
+    }
+
+    fn last_state(receiver: &mut mpsc::Receiver<crate::vconsole::Packet>) -> GadgetState {
+        let mut state = GadgetState::new("", 0, Vector3::default());
+        while let Ok(packet) = receiver.try_recv() {
+            let command = CommandPacket::from_packet(&packet).unwrap();
+            for encoded in command.vtunnel_messages() {
+                state.apply_vtunnel_message(&parse_vtunnel_message(encoded).unwrap()).unwrap();
+            }
+        }
+        state
+    }
+
+    #[tokio::test]
+    async fn test_switch_programs() {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut tool = GadgetTool::new(EmitterHandle::new(sender), 3);
+        tool.register(Box::new(DummyProgram { name: "first", events: events.clone() }));
+        tool.register(Box::new(DummyProgram { name: "second", events: events.clone() }));
+        assert_eq!(tool.current_program_name(), Some("first"));
+
+        tool.activate().await;
+        let state = last_state(&mut receiver);
+        assert_eq!(state.user_id, 3);
+        assert_eq!(state.program_name, "first");
+
+        assert!(tool.select_program("second").await);
+        assert!(!tool.select_program("missing").await);
+        assert_eq!(last_state(&mut receiver).program_name, "second");
+
+        // Pad right wraps back around to the first program and isn't passed on.
+        let mut input = PlayerInput::new();
+        input.buttons_pressed = 1 << input_button::IN_PAD_RIGHT_HAND1;
+        tool.input(input).await;
+        assert_eq!(tool.current_program_name(), Some("first"));
+
+        tool.input(PlayerInput::new()).await;
+        assert_eq!(*events.lock().unwrap(), vec![
+            "first activate",
+            "first deactivate",
+            "second activate",
+            "second deactivate",
+            "first activate",
+            "first input",
+        ]);
+    }
+
+    fn measure_gadgets(bus: &EventBus) -> mpsc::Receiver<crate::vconsole::Packet> {
+        let (sender, receiver) = mpsc::channel(64);
+        let config = VTunnelConfig { programs: vec!["measure".to_string()], ..VTunnelConfig::default() };
+        Gadgets::new(EmitterHandle::new(sender), &config).unwrap().subscribe(bus);
+        receiver
+    }
+
+    fn click(user_id: u64) -> PlayerInput {
+        PlayerInput { user_id, trace_hit: true, buttons_pressed: 1 << input_button::IN_USE_HAND1, ..PlayerInput::new() }
+    }
+
+    // Names of the next messages sent to the script.
+    async fn next_messages(receiver: &mut mpsc::Receiver<crate::vconsole::Packet>, count: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        while names.len() < count {
+            let packet = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+            for encoded in CommandPacket::from_packet(&packet).unwrap().vtunnel_messages() {
+                names.push(parse_vtunnel_message(encoded).unwrap().name);
+            }
+        }
+        names
+    }
+
+    #[tokio::test]
+    async fn test_gadgets_dispatch_in_order() {
+        let bus = EventBus::new();
+        let mut receiver = measure_gadgets(&bus);
+
+        // The click only measures if the activation before it was handled first, and the one after the
+        // deactivation mustn't.
+        bus.publish(&GadgetActivated { user_id: 1 }.serialize());
+        bus.publish(&click(1).serialize());
+        bus.publish(&GadgetDeactivated { user_id: 1 }.serialize());
+        bus.publish(&click(1).serialize());
+        bus.publish(&GadgetActivated { user_id: 1 }.serialize());
+
+        assert_eq!(next_messages(&mut receiver, 4).await, vec!["gadget_state", "draw_debug_sphere", "gadget_state", "gadget_state"]);
+    }
+
+    #[tokio::test]
+    async fn test_gadgets_player_left() {
+        let bus = EventBus::new();
+        let mut receiver = measure_gadgets(&bus);
+
+        bus.publish(&GadgetActivated { user_id: 1 }.serialize());
+        assert_eq!(next_messages(&mut receiver, 1).await, vec!["gadget_state"]);
+
+        // Leaving deactivates the gadget, and coming back starts over with an inactive one that ignores the click.
+        bus.emit(PlayerEvent::Left(Player { user_id: 1, ..Player::new() }));
+        bus.publish(&click(1).serialize());
+        bus.publish(&GadgetDeactivated { user_id: 1 }.serialize());
+        assert_eq!(next_messages(&mut receiver, 2).await, vec!["gadget_state", "gadget_state"]);
+        assert!(tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gadgets_map_changed_syncs_nav_points() {
+        let db_path = std::env::temp_dir().join(format!("vtunnel_gadget_map_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        {
+            let mut store = NavPointStore::open(&db_path).unwrap();
+            store.switch_map("other").unwrap();
+            let group = store.begin_edit_group(1);
+            store.add_nav_point(group, &Vector3::new(16.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap();
+        }
+
+        let bus = EventBus::new();
+        let (sender, mut receiver) = mpsc::channel(64);
+        let config = VTunnelConfig { database: db_path.clone(), ..VTunnelConfig::default() };
+        Gadgets::new(EmitterHandle::new(sender), &config).unwrap().subscribe(&bus);
+
+        // The script only learns about the other map's nav point from the switch.
+        bus.emit(MapChanged { map_name: "other".to_string() });
+        assert_eq!(next_messages(&mut receiver, 1).await, vec!["add_nav_point"]);
+        let _ = std::fs::remove_file(&db_path);
     }
 }
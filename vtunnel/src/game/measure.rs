@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use bsi_tools_lib::math::Vector3;
+use crate::game::commands::DrawDebugSphere;
+use crate::game::gadget::{GadgetProgram, GadgetState};
+use crate::game::player::{input_button, PlayerInput};
+use crate::vtunnel_emitter::EmitterHandle;
+
+/**
+ * Measures the distance between two points traced with the gadget.
+ */
+pub struct MeasureProgram {
+    emitter: EmitterHandle,
+    active: bool,
+    start: Option<Vector3>,
+}
+
+impl MeasureProgram {
+    const COLOR: Vector3 = Vector3 { x: 0.0, y: 255.0, z: 255.0 };
+    const INCHES_TO_METERS: f64 = 0.0254;
+
+    pub fn new(emitter: EmitterHandle) -> MeasureProgram {
+        MeasureProgram {
+            emitter,
+            active: false,
+            start: None,
+        }
+    }
+
+    async fn draw_point(&self, position: &Vector3) {
+        self.emitter.send(&DrawDebugSphere {
+            position: position.clone(),
+            color: Self::COLOR,
+            color_alpha: 1.0,
+            radius: 2.0,
+            z_test: false,
+            duration_seconds: 10.0,
+        }).await;
+    }
+}
+
+#[async_trait]
+impl GadgetProgram for MeasureProgram {
+    fn name(&self) -> &'static str {
+        "measure"
+    }
+
+    fn state(&self) -> GadgetState {
+        GadgetState::new(self.name(), 0, Self::COLOR)
+    }
+
+    async fn on_activate(&mut self) {
+        self.active = true;
+        self.start = None;
+    }
+
+    async fn on_deactivate(&mut self) {
+        self.active = false;
+    }
+
+    async fn on_input(&mut self, input: &PlayerInput) {
+        if !self.active || !input.trace_hit || !input.is_pressed(input_button::IN_USE_HAND1) {
+            return;
+        }
+
+        let position = input.trace_position.clone();
+        self.draw_point(&position).await;
+        match self.start.take() {
+            None => self.start = Some(position),
+            Some(start) => {
+                let distance = start.distance(&position);
+                println!("Measured {:.2} units ({:.2}m)", distance, distance * Self::INCHES_TO_METERS);
+            }
+        }
+    }
+}
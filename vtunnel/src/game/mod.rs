@@ -3,3 +3,6 @@ pub mod player;
 pub mod commands;
 pub mod trace;
 pub mod gadget;
+pub mod measure;
+pub mod world;
+pub mod state;
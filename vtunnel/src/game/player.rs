@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use bsi_tools_lib::math::Vector3;
-use crate::vtunnel::{VTunnelDeserializable, VTunnelMessage};
+use crate::vtunnel::VTunnelMessage;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "player_state", crate = "crate")]
 pub struct Player {
     pub steam_id: String,
     pub user_id: u64,
@@ -22,13 +24,9 @@ impl Player {
     }
 }
 
-impl VTunnelDeserializable for Player {
-    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage) {
-        self.steam_id = msg.data[0].get_string().unwrap().to_string();
-        self.user_id = msg.data[1].get_int().unwrap() as u64;
-        self.name = msg.data[2].get_string().unwrap().to_string();
-        self.position = msg.data[3].get_vector3().unwrap();
-        self.rotation = msg.data[4].get_vector3().unwrap();
+impl Default for Player {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -55,7 +53,8 @@ pub mod input_button {
     pub const THUMBSTICK_TOUCH_HAND1: u64 = 43;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, VTunnelMessage)]
+#[vtunnel(name = "player_input", crate = "crate")]
 pub struct PlayerInput {
     pub user_id: u64,
     pub hand: u8,
@@ -112,23 +111,141 @@ impl PlayerInput {
     }
 }
 
-impl VTunnelDeserializable for PlayerInput {
-    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage) {
-        self.user_id = msg.data[0].get_int().unwrap() as u64;
-        self.hand = msg.data[1].get_int().unwrap() as u8;
-        self.hand_position = msg.data[2].get_vector3().unwrap();
-        self.hand_rotation = msg.data[3].get_vector3().unwrap();
-        self.trace_hit = msg.data[4].get_bool().unwrap();
-        self.trace_fraction = msg.data[5].get_float().unwrap();
-        self.trace_normal = msg.data[6].get_vector3().unwrap();
-        self.trace_position = msg.data[7].get_vector3().unwrap();
-        self.buttons_down = msg.data[8].get_int().unwrap() as u64;
-        self.buttons_pressed = msg.data[9].get_int().unwrap() as u64;
-        self.buttons_released = msg.data[10].get_int().unwrap() as u64;
-        self.joystick_x = msg.data[11].get_float().unwrap();
-        self.joystick_y = msg.data[12].get_float().unwrap();
-        self.trackpad_x = msg.data[13].get_float().unwrap();
-        self.trackpad_y = msg.data[14].get_float().unwrap();
-        self.trigger = msg.data[15].get_float().unwrap();
+impl Default for PlayerInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    Joined(Player),
+    Left(Player),
+}
+
+/**
+ * Every player the script has reported, keyed on user id.
+ * The script only sends `player_state` for players that are in the game, so a player that
+ * hasn't been heard from for a while is treated as having left.
+ */
+#[derive(Debug)]
+pub struct PlayerTable {
+    players: HashMap<u64, TrackedPlayer>,
+    timeout: f64,
+}
+
+#[derive(Debug)]
+struct TrackedPlayer {
+    player: Player,
+    // Server time of the last player_state received for this player.
+    last_seen: f64,
+}
+
+impl PlayerTable {
+    pub const DEFAULT_TIMEOUT_SECONDS: f64 = 5.0;
+
+    pub fn new() -> PlayerTable {
+        PlayerTable::with_timeout(Self::DEFAULT_TIMEOUT_SECONDS)
+    }
+
+    pub fn with_timeout(timeout: f64) -> PlayerTable {
+        PlayerTable {
+            players: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /**
+     * Stores the latest state of a player. Returns a join event the first time a player is seen.
+     */
+    pub fn update(&mut self, player: Player, server_time: f64) -> Option<PlayerEvent> {
+        match self.players.get_mut(&player.user_id) {
+            Some(tracked) => {
+                tracked.player = player;
+                tracked.last_seen = server_time;
+                None
+            }
+            None => {
+                self.players.insert(player.user_id, TrackedPlayer { player: player.clone(), last_seen: server_time });
+                Some(PlayerEvent::Joined(player))
+            }
+        }
+    }
+
+    /**
+     * Removes players that haven't been updated within the timeout.
+     */
+    pub fn expire(&mut self, server_time: f64) -> Vec<PlayerEvent> {
+        let expired: Vec<u64> = self.players
+            .iter()
+            .filter(|(_, tracked)| server_time - tracked.last_seen > self.timeout)
+            .map(|(user_id, _)| *user_id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|user_id| self.players.remove(&user_id))
+            .map(|tracked| PlayerEvent::Left(tracked.player))
+            .collect()
+    }
+
+    /**
+     * Removes every player, e.g. when the connection to the game is lost.
+     */
+    pub fn clear(&mut self) -> Vec<PlayerEvent> {
+        self.players.drain().map(|(_, tracked)| PlayerEvent::Left(tracked.player)).collect()
+    }
+
+    pub fn get(&self, user_id: u64) -> Option<&Player> {
+        self.players.get(&user_id).map(|tracked| &tracked.player)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.players.values().map(|tracked| &tracked.player)
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+}
+
+impl Default for PlayerTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::player::{Player, PlayerEvent, PlayerTable};
+
+    fn player(user_id: u64, name: &str) -> Player {
+        Player {
+            user_id,
+            name: name.to_string(),
+            ..Player::new()
+        }
+    }
+
+    #[test]
+    fn test_player_join_and_leave() {
+        let mut table = PlayerTable::with_timeout(2.0);
+
+        assert_eq!(table.update(player(1, "host"), 10.0), Some(PlayerEvent::Joined(player(1, "host"))));
+        assert_eq!(table.update(player(2, "guest"), 10.0), Some(PlayerEvent::Joined(player(2, "guest"))));
+        assert_eq!(table.update(player(1, "host renamed"), 11.5), None);
+        assert_eq!(table.get(1).unwrap().name, "host renamed");
+        assert_eq!(table.len(), 2);
+
+        assert!(table.expire(12.0).is_empty());
+        assert_eq!(table.expire(12.5), vec![PlayerEvent::Left(player(2, "guest"))]);
+        assert!(table.get(2).is_none());
+
+        assert_eq!(table.clear(), vec![PlayerEvent::Left(player(1, "host renamed"))]);
+        assert!(table.is_empty());
     }
 }
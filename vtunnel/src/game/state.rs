@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use crate::config::{LogLevel, VTunnelConfig};
+use crate::event_bus::EventBus;
+use crate::game::gadget::Gadgets;
+use crate::game::world::{ConnectionLost, MapChanged, World};
+use crate::nav_builder::nav_builder::NavStoreError;
+use crate::vtunnel::{VTunnelMessage, VTunnelSerializable};
+use crate::vtunnel_emitter::EmitterHandle;
+
+/**
+ * Handles the VTunnel messages from the script: the handshake, then everything else through the event bus.
+ */
+pub struct GameState {
+    session_id: u32,
+    session_ready: bool,
+    // Used before random ids when replaying a recording.
+    pub preset_session_ids: VecDeque<u32>,
+
+    emitter: EmitterHandle,
+    bus: EventBus,
+    world: Arc<Mutex<World>>,
+    log_level: LogLevel,
+    // Server time the world state was last printed at.
+    last_state_print: f64,
+}
+
+impl GameState {
+    pub async fn new(emitter: EmitterHandle, config: &VTunnelConfig) -> Result<GameState, NavStoreError> {
+        let bus = EventBus::new();
+        let world = World::subscribe(&bus);
+        Gadgets::new(emitter.clone(), config)?.subscribe(&bus);
+
+        Ok(GameState {
+            session_id: 0,
+            session_ready: false,
+            preset_session_ids: VecDeque::new(),
+
+            emitter,
+            bus,
+            world,
+            log_level: config.log_level,
+            last_state_print: 0.0,
+        })
+    }
+
+    pub fn print_state(&self) {
+        let world = self.world.lock().unwrap();
+        println!("Server time: {}", world.server_time);
+        println!("Liz: {:?}", world.liz);
+        for player in world.players.players() {
+            println!("Player: {:?}", player);
+        }
+    }
+
+    pub async fn handle_vmsg(&mut self, vmsg: VTunnelMessage) {
+        let msg_name = vmsg.name.as_str();
+
+        // Sent by the connection supervisor when VConsole goes away. A new handshake is needed after reconnecting.
+        if msg_name == "vtunnel_disconnected" {
+            self.session_ready = false;
+            self.bus.emit(ConnectionLost);
+            return;
+        }
+
+        // Sent by the console state when VConsole reports which addon is running, which can come before the handshake.
+        if msg_name == "vtunnel_addon" {
+            match vmsg.get_field::<String>(0, "map_name") {
+                Ok(map_name) => {
+                    self.bus.emit(MapChanged { map_name });
+                }
+                Err(err) => eprintln!("Failed to read addon name: {}", err),
+            }
+            return;
+        }
+
+        // Handle when client requests handshake (for when client starts after server is running)
+        if msg_name == "vtunnel_request_handshake" {
+            self.session_ready = false;
+            self.session_id = self.preset_session_ids.pop_front().unwrap_or_else(rand::random::<u32>);
+
+            let handshake_response = self.emitter.send_request::<VTunnelHandshake>(VTunnelHandshake::new(self.session_id)).await;
+            match handshake_response {
+                Ok(_) => {
+                    self.session_ready = true;
+                    let mut connected_vmsg = VTunnelMessage::new("vtunnel_connected".to_string());
+                    connected_vmsg.set_id(self.session_id as u64);
+                    self.emitter.send_vmsg(connected_vmsg).await;
+                }
+                Err(err) => {
+                    println!("VTunnel handshake failed... {} {:?}", self.session_id, err);
+                }
+            }
+            return;
+        }
+
+        // Block all messages until client is ready
+        if !self.session_ready {
+            return;
+        }
+
+        for err in self.bus.publish(&vmsg).errors {
+            eprintln!("Failed to handle VTunnel message: {}", err);
+        }
+
+        // Dumps the world about once a second of game time.
+        let server_time = self.world.lock().unwrap().server_time;
+        if self.log_level >= LogLevel::Verbose && server_time - self.last_state_print > 1.0 {
+            self.print_state();
+            self.last_state_print = server_time;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VTunnelHandshake {
+    pub session_id: u32,
+}
+
+impl VTunnelHandshake {
+    pub fn new(session_id: u32) -> VTunnelHandshake {
+        VTunnelHandshake {
+            session_id,
+        }
+    }
+}
+
+impl VTunnelSerializable for VTunnelHandshake {
+    fn serialize(&self) -> VTunnelMessage {
+        let mut vmsg = VTunnelMessage::new("vtunnel_handshake".to_string());
+        vmsg.set_id(self.session_id as u64);
+        vmsg
+    }
+}
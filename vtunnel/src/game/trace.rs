@@ -1,5 +1,7 @@
 use bsi_tools_lib::math::Vector3;
-use crate::vtunnel::{VTunnelDeserializable, VTunnelMessage, VTunnelSerializable};
+use futures::future::try_join_all;
+use crate::vconsole::{VTunnelMessageBatchPacket, COMMAND_LENGTH_LIMIT};
+use crate::vtunnel::{encode_vtunnel_message, VTunnelDataPart, VTunnelDataType, VTunnelDeserializable, VTunnelField, VTunnelMessage, VTunnelSerializable};
 use crate::vtunnel_emitter::VTunnelEmitter;
 
 /**
@@ -10,11 +12,28 @@ pub enum TraceMask {
     TraceMaskPlayerSolid = 33636363, // From L4D2 script API, may not be correct for Source 2.
 }
 
+impl VTunnelField for TraceMask {
+    const DATA_TYPE: VTunnelDataType = VTunnelDataType::Int;
+
+    fn to_data_part(&self) -> VTunnelDataPart {
+        VTunnelDataPart::Int(*self as i64)
+    }
+
+    fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+        match part.get_int()? {
+            mask if mask == TraceMask::TraceMaskPlayerSolid as i64 => Some(TraceMask::TraceMaskPlayerSolid),
+            _ => None,
+        }
+    }
+}
+
 
 /**
  * Stores the results of a trace
+ * Sent back as both `line_trace_result` and `box_trace_result`.
  */
-#[derive(Debug)]
+#[derive(Debug, Clone, VTunnelMessage)]
+#[vtunnel(name = "trace_result", crate = "crate")]
 pub struct TraceResult {
     pub hit: bool,
     pub hit_position: Vector3,
@@ -25,6 +44,7 @@ pub struct TraceResult {
 }
 
 impl TraceResult {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         TraceResult {
             hit: false,
@@ -37,21 +57,11 @@ impl TraceResult {
     }
 }
 
-impl VTunnelDeserializable for TraceResult {
-    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage) {
-        self.hit = msg.data[0].get_bool().unwrap();
-        self.hit_position = msg.data[1].get_vector3().unwrap();
-        self.hit_normal = msg.data[2].get_vector3().unwrap();
-        self.hit_entity_id = msg.data[3].get_int().unwrap() as u64;
-        self.start_in_solid = msg.data[4].get_bool().unwrap();
-        self.fraction = msg.data[5].get_float().unwrap();
-    }
-}
-
 /**
  * Performs a line trace between two points.
  */
-#[derive(Debug)]
+#[derive(Debug, VTunnelMessage)]
+#[vtunnel(name = "line_trace", crate = "crate")]
 pub struct LineTrace {
     pub start_position: Vector3,
     pub end_position: Vector3,
@@ -60,18 +70,6 @@ pub struct LineTrace {
     pub draw_debug: bool,
 }
 
-impl VTunnelSerializable for LineTrace {
-    fn serialize(&self) -> VTunnelMessage {
-        let mut vmsg = VTunnelMessage::new("line_trace".to_string());
-        vmsg.add_vector3(self.start_position.clone());
-        vmsg.add_vector3(self.end_position.clone());
-        vmsg.add_int(self.mask as i64);
-        vmsg.add_int(self.ignore_entity_id as i64);
-        vmsg.add_bool(self.draw_debug);
-        vmsg
-    }
-}
-
 impl LineTrace {
     pub fn new(start_position: Vector3, end_position: Vector3) -> Self {
         LineTrace {
@@ -89,7 +87,7 @@ impl LineTrace {
     pub async fn run(self, emitter: &VTunnelEmitter) -> Result<TraceResult, std::io::Error> {
         let vmsg = emitter.send_request::<LineTrace>(self).await?;
         let mut result = TraceResult::new();
-        result.apply_vtunnel_message(&vmsg);
+        result.apply_vtunnel_message(&vmsg).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
         Ok(result)
     }
 }
@@ -98,7 +96,8 @@ impl LineTrace {
 /**
  * Performs a box/hull trace between two points.
  */
-#[derive(Debug)]
+#[derive(Debug, VTunnelMessage)]
+#[vtunnel(name = "box_trace", crate = "crate")]
 pub struct BoxTrace {
     pub start_position: Vector3,
     pub end_position: Vector3,
@@ -109,20 +108,6 @@ pub struct BoxTrace {
     pub draw_debug: bool,
 }
 
-impl VTunnelSerializable for crate::game::trace::BoxTrace {
-    fn serialize(&self) -> VTunnelMessage {
-        let mut vmsg = VTunnelMessage::new("box_trace".to_string());
-        vmsg.add_vector3(self.start_position.clone());
-        vmsg.add_vector3(self.end_position.clone());
-        vmsg.add_vector3(self.mins.clone());
-        vmsg.add_vector3(self.maxs.clone());
-        vmsg.add_int(self.mask as i64);
-        vmsg.add_int(self.ignore_entity_id as i64);
-        vmsg.add_bool(self.draw_debug);
-        vmsg
-    }
-}
-
 impl BoxTrace {
     pub fn new(start_position: Vector3, end_position: Vector3, mins: Vector3, maxs: Vector3) -> Self {
         BoxTrace {
@@ -142,7 +127,222 @@ impl BoxTrace {
     pub async fn run(self, emitter: &VTunnelEmitter) -> Result<TraceResult, std::io::Error> {
         let vmsg = emitter.send_request::<BoxTrace>(self).await?;
         let mut result = TraceResult::new();
-        result.apply_vtunnel_message(&vmsg);
+        result.apply_vtunnel_message(&vmsg).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+
+/**
+ * Runs many line/box traces with as few round-trips as possible.
+ *
+ * Sent as `trace_batch`:
+ * int - Trace mask, for every trace
+ * int - Entity to ignore, for every trace
+ * bool - Draw debug
+ * Then for every trace:
+ * v3 - Start position
+ * v3 - End position
+ * v3 - Mins, zero for line traces
+ * v3 - Maxs, zero for line traces
+ *
+ * Answered with `trace_batch_result`:
+ * int - Number of results
+ * Then the fields of a `TraceResult` for every trace, in order.
+ *
+ * Batches too big for one console command are split into several requests, which are all sent at once.
+ */
+#[derive(Debug, Clone)]
+pub struct TraceBatch {
+    pub traces: Vec<BatchTrace>,
+    pub mask: TraceMask,
+    pub ignore_entity_id: u64,
+    pub draw_debug: bool,
+}
+
+impl TraceBatch {
+    const HEADER_PARTS: usize = 3;
+    const TRACE_PARTS: usize = 4;
+    const RESULT_PARTS: usize = 6;
+
+    pub fn new() -> Self {
+        TraceBatch {
+            traces: Vec::new(),
+            mask: TraceMask::TraceMaskPlayerSolid,
+            ignore_entity_id: 0,
+            draw_debug: false,
+        }
+    }
+
+    pub fn add_line(&mut self, start_position: Vector3, end_position: Vector3) {
+        self.traces.push(BatchTrace { start_position, end_position, mins: Vector3::default(), maxs: Vector3::default() });
+    }
+
+    pub fn add_box(&mut self, start_position: Vector3, end_position: Vector3, mins: Vector3, maxs: Vector3) {
+        self.traces.push(BatchTrace { start_position, end_position, mins, maxs });
+    }
+
+    pub fn len(&self) -> usize {
+        self.traces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.traces.is_empty()
+    }
+
+    fn new_request(&self) -> VTunnelMessage {
+        let mut vmsg = VTunnelMessage::new("trace_batch".to_string());
+        vmsg.data.push(self.mask.to_data_part());
+        vmsg.add_int(self.ignore_entity_id as i64);
+        vmsg.add_bool(self.draw_debug);
+        vmsg
+    }
+
+    fn add_trace(vmsg: &mut VTunnelMessage, trace: &BatchTrace) {
+        vmsg.add_vector3(trace.start_position.clone());
+        vmsg.add_vector3(trace.end_position.clone());
+        vmsg.add_vector3(trace.mins.clone());
+        vmsg.add_vector3(trace.maxs.clone());
+    }
+
+    /**
+     * Splits the traces into requests that each fit in a single console command.
+     */
+    pub fn to_requests(&self) -> Vec<VTunnelMessage> {
+        let max_message_length = COMMAND_LENGTH_LIMIT - VTunnelMessageBatchPacket::base_command_length();
+        let fits = |vmsg: &VTunnelMessage| {
+            // The request id isn't known yet, so measure with the longest one possible.
+            let mut measured = vmsg.clone();
+            measured.set_id(u64::MAX);
+            VTunnelMessageBatchPacket::encoded_message_length(&encode_vtunnel_message(&measured)) <= max_message_length
+        };
+
+        let mut requests = Vec::new();
+        let mut request = self.new_request();
+        for trace in &self.traces {
+            let mut with_trace = request.clone();
+            Self::add_trace(&mut with_trace, trace);
+
+            // A trace that doesn't fit on its own still gets a request, the emitter will fragment it.
+            if !fits(&with_trace) && request.data.len() > Self::HEADER_PARTS {
+                requests.push(request);
+                request = self.new_request();
+                Self::add_trace(&mut request, trace);
+            } else {
+                request = with_trace;
+            }
+        }
+
+        if request.data.len() > Self::HEADER_PARTS {
+            requests.push(request);
+        }
+        requests
+    }
+
+    /**
+     * Reads the results out of a `trace_batch_result` reply.
+     */
+    pub fn parse_results(vmsg: &VTunnelMessage, expected: usize) -> Result<Vec<TraceResult>, std::io::Error> {
+        let invalid = |err: String| std::io::Error::new(std::io::ErrorKind::InvalidData, err);
+
+        let count = vmsg.get_field::<u64>(0, "count").map_err(|err| invalid(err.to_string()))? as usize;
+        if count != expected || vmsg.data.len() != 1 + count * Self::RESULT_PARTS {
+            return Err(invalid(format!("expected {} trace results, got {} ({} parts)", expected, count, vmsg.data.len())));
+        }
+
+        vmsg.data[1..]
+            .chunks(Self::RESULT_PARTS)
+            .map(|parts| {
+                let mut result_vmsg = VTunnelMessage::new(vmsg.name.clone());
+                result_vmsg.data = parts.to_vec();
+
+                let mut result = TraceResult::new();
+                result.apply_vtunnel_message(&result_vmsg).map_err(|err| invalid(err.to_string()))?;
+                Ok(result)
+            })
+            .collect()
+    }
+
+    /**
+     * Runs every trace and returns the results in the same order the traces were added.
+     */
+    pub async fn run(self, emitter: &VTunnelEmitter) -> Result<Vec<TraceResult>, std::io::Error> {
+        let requests = self.to_requests();
+        let replies = try_join_all(requests.into_iter().map(|vmsg| async move {
+            let expected = (vmsg.data.len() - Self::HEADER_PARTS) / Self::TRACE_PARTS;
+            let reply = emitter.send_request(TraceBatchRequest(vmsg)).await?;
+            Self::parse_results(&reply, expected)
+        })).await?;
+
+        Ok(replies.into_iter().flatten().collect())
+    }
+}
+
+impl Default for TraceBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * A single trace in a `TraceBatch`. Line traces have zero mins and maxs.
+ */
+#[derive(Debug, Clone)]
+pub struct BatchTrace {
+    pub start_position: Vector3,
+    pub end_position: Vector3,
+    pub mins: Vector3,
+    pub maxs: Vector3,
+}
+
+/**
+ * One already-built chunk of a `TraceBatch`.
+ */
+struct TraceBatchRequest(VTunnelMessage);
+
+impl VTunnelSerializable for TraceBatchRequest {
+    fn serialize(&self) -> VTunnelMessage {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::game::trace::{TraceBatch, TraceResult};
+    use crate::vconsole::{VTunnelMessageBatchPacket, COMMAND_LENGTH_LIMIT};
+    use crate::vtunnel::{encode_vtunnel_message, VTunnelMessage, VTunnelSerializable};
+
+    #[test]
+    fn test_trace_batch_split_and_results() {
+        let mut batch = TraceBatch::new();
+        for i in 0..50 {
+            let position = Vector3::new(-1234.5 + i as f64 * 16.0, 678.25, 16.0);
+            batch.add_box(position.clone(), position.sub(&Vector3::new(0.0, 0.0, 1000.0)), Vector3::new(-16.0, -16.0, 0.0), Vector3::new(16.0, 16.0, 72.0));
+        }
+
+        let requests = batch.to_requests();
+        assert!(requests.len() > 1);
+        let mut total = 0;
+        for request in &requests {
+            let mut request = request.clone();
+            request.set_id(u64::MAX);
+            let length = VTunnelMessageBatchPacket::base_command_length() + VTunnelMessageBatchPacket::encoded_message_length(&encode_vtunnel_message(&request));
+            assert!(length <= COMMAND_LENGTH_LIMIT, "request is {} bytes", length);
+            total += (request.data.len() - 3) / 4;
+        }
+        assert_eq!(total, 50);
+        assert!(TraceBatch::new().to_requests().is_empty());
+
+        let mut reply = VTunnelMessage::new("trace_batch_result".to_string());
+        reply.add_int(2);
+        for fraction in [0.25, 1.0] {
+            let result = TraceResult { hit: fraction < 1.0, fraction, ..TraceResult::new() };
+            reply.data.extend(result.serialize().data);
+        }
+        let results = TraceBatch::parse_results(&reply, 2).unwrap();
+        assert!(results[0].hit);
+        assert_eq!(results[1].fraction, 1.0);
+        assert!(TraceBatch::parse_results(&reply, 3).is_err());
+    }
+}
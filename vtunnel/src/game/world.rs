@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+use crate::event_bus::EventBus;
+use crate::game::elizabeth::Elizabeth;
+use crate::game::player::{Player, PlayerEvent, PlayerTable};
+use crate::vtunnel::VTunnelMessage;
+
+#[derive(Debug, Default, Clone, VTunnelMessage)]
+#[vtunnel(name = "world_state", crate = "crate")]
+pub struct WorldState {
+    pub server_time: f64,
+}
+
+/**
+ * Emitted when the connection to VConsole is lost.
+ */
+#[derive(Debug, Clone)]
+pub struct ConnectionLost;
+
+/**
+ * Emitted when VConsole reports the running addon, which is the map being played.
+ */
+#[derive(Debug, Clone)]
+pub struct MapChanged {
+    pub map_name: String,
+}
+
+/**
+ * Latest state of the world as reported by the script.
+ */
+pub struct World {
+    pub server_time: f64,
+    pub liz: Elizabeth,
+    pub players: PlayerTable,
+}
+
+impl World {
+    /**
+     * Keeps a world up to date with the bus, and emits a `PlayerEvent` whenever someone joins or leaves.
+     */
+    pub fn subscribe(bus: &EventBus) -> Arc<Mutex<World>> {
+        let world = Arc::new(Mutex::new(World {
+            server_time: 0.0,
+            liz: Elizabeth::new(),
+            players: PlayerTable::new(),
+        }));
+
+        let (state, events) = (world.clone(), bus.clone());
+        bus.on(move |world_state: WorldState| {
+            let mut world = state.lock().unwrap();
+            world.server_time = world_state.server_time;
+            for event in world.players.expire(world_state.server_time) {
+                events.emit(event);
+            }
+            async { Ok(()) }
+        });
+
+        let state = world.clone();
+        bus.on(move |liz: Elizabeth| {
+            state.lock().unwrap().liz = liz;
+            async { Ok(()) }
+        });
+
+        let (state, events) = (world.clone(), bus.clone());
+        bus.on(move |player: Player| {
+            let mut world = state.lock().unwrap();
+            let server_time = world.server_time;
+            if let Some(event) = world.players.update(player, server_time) {
+                events.emit(event);
+            }
+            async { Ok(()) }
+        });
+
+        let (state, events) = (world.clone(), bus.clone());
+        bus.on_event(move |_: ConnectionLost| {
+            for event in state.lock().unwrap().players.clear() {
+                events.emit(event);
+            }
+            async { Ok(()) }
+        });
+
+        bus.on_event(|event: PlayerEvent| async move {
+            match event {
+                PlayerEvent::Joined(player) => println!("{} ({}) joined", player.name, player.user_id),
+                PlayerEvent::Left(player) => println!("{} ({}) left", player.name, player.user_id),
+            }
+            Ok(())
+        });
+
+        world
+    }
+}
@@ -3,3 +3,9 @@ pub mod vtunnel;
 pub mod game;
 pub mod vtunnel_emitter;
 pub mod nav_builder;
+pub mod schema;
+pub mod config;
+pub mod mock_server;
+pub mod recording;
+pub mod event_bus;
+pub mod connection;
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+use std::path::Path;
+use bsi_tools_lib::math::Vector3;
+use futures::SinkExt;
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+use crate::game::trace::{BoxTrace, LineTrace, TraceResult};
+use crate::nav_builder::nav_sync::NavSyncChecksum;
+use crate::vconsole::{AddonPacket, AppInfoPacket, ChannelPacket, ConvarPacket, PacketCodec, PrintPacket, VConsoleChannel, VConsolePacket};
+use crate::vtunnel::{encode_vtunnel_message, parse_vtunnel_message, VTunnelDeserializable, VTunnelFragment, VTunnelMessage, VTunnelMessageError, VTunnelSerializable};
+
+// Channel the mock prints VScript output on, same as the game.
+pub const MOCK_VSCRIPT_CHANNEL_ID: u32 = 7;
+
+// Most fragments a message is allowed to claim it's split into, so a bad count can't allocate without limit.
+pub const MAX_FRAGMENT_COUNT: u32 = 4096;
+
+/**
+ * Axis aligned box that traces collide with.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockSolid {
+    pub mins: [f64; 3],
+    pub maxs: [f64; 3],
+    #[serde(default)]
+    pub entity_id: u64,
+}
+
+/**
+ * Describes the world the mock server pretends to run.
+ *
+ * ```toml
+ * addon_name = "bioshock_infinite"
+ *
+ * [[solids]]
+ * mins = [-512.0, -512.0, -16.0]
+ * maxs = [512.0, 512.0, 0.0]
+ * ```
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockScene {
+    pub executable: String,
+    pub addon_name: String,
+    pub channels: Vec<String>,
+    pub convars: Vec<String>,
+    pub solids: Vec<MockSolid>,
+}
+
+impl Default for MockScene {
+    fn default() -> Self {
+        MockScene {
+            executable: "steamtours.exe".to_string(),
+            addon_name: "bioshock_infinite".to_string(),
+            channels: vec!["VScript".to_string(), "RenderSystem".to_string()],
+            convars: vec!["vtunnel_enabled".to_string()],
+            // 1024x1024 floor with its top at z = 0
+            solids: vec![MockSolid { mins: [-512.0, -512.0, -16.0], maxs: [512.0, 512.0, 0.0], entity_id: 0 }],
+        }
+    }
+}
+
+impl MockScene {
+    pub fn from_file(path: &Path) -> Result<MockScene, std::io::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /**
+     * Sweeps a box from start to end against every solid. A zero sized box is a line trace.
+     */
+    pub fn trace(&self, start: &Vector3, end: &Vector3, mins: &Vector3, maxs: &Vector3) -> TraceResult {
+        let start = [start.x, start.y, start.z];
+        let end = [end.x, end.y, end.z];
+        let delta = [end[0] - start[0], end[1] - start[1], end[2] - start[2]];
+
+        let mut result = TraceResult::new();
+        result.fraction = 1.0;
+        result.hit_position = Vector3::new(end[0], end[1], end[2]);
+
+        for solid in &self.solids {
+            // Grow the solid by the box so the box can be traced as a point.
+            let solid_mins = [solid.mins[0] - maxs.x, solid.mins[1] - maxs.y, solid.mins[2] - maxs.z];
+            let solid_maxs = [solid.maxs[0] - mins.x, solid.maxs[1] - mins.y, solid.maxs[2] - mins.z];
+
+            if (0..3).all(|axis| start[axis] > solid_mins[axis] && start[axis] < solid_maxs[axis]) {
+                result.start_in_solid = true;
+                continue;
+            }
+
+            let mut enter = 0.0f64;
+            let mut exit = 1.0f64;
+            let mut enter_axis = None;
+            for axis in 0..3 {
+                if delta[axis].abs() < f64::EPSILON {
+                    if start[axis] < solid_mins[axis] || start[axis] > solid_maxs[axis] {
+                        exit = -1.0;
+                        break;
+                    }
+                    continue;
+                }
+
+                let t1 = (solid_mins[axis] - start[axis]) / delta[axis];
+                let t2 = (solid_maxs[axis] - start[axis]) / delta[axis];
+                let (near, far) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+                if near > enter {
+                    enter = near;
+                    enter_axis = Some(axis);
+                }
+                exit = exit.min(far);
+            }
+
+            let Some(axis) = enter_axis else {
+                continue;
+            };
+            if enter > exit || enter >= result.fraction {
+                continue;
+            }
+
+            let mut normal = [0.0; 3];
+            normal[axis] = -delta[axis].signum();
+
+            result.hit = true;
+            result.fraction = enter;
+            result.hit_entity_id = solid.entity_id;
+            result.hit_position = Vector3::new(start[0] + delta[0] * enter, start[1] + delta[1] * enter, start[2] + delta[2] * enter);
+            result.hit_normal = Vector3::new(normal[0], normal[1], normal[2]);
+        }
+
+        result
+    }
+}
+
+/**
+ * Pretends to be the game on the other end of VConsole.
+ * Answers VTunnel messages the same way the script side does, using the scene for traces.
+ */
+pub struct MockVConsole {
+    scene: MockScene,
+    fragments: HashMap<u64, Vec<Option<String>>>,
+    session_id: Option<u64>,
+    received: Vec<VTunnelMessage>,
+    // The script's copy of the nav points, by id.
+    nav_points: HashMap<u64, (Vector3, u8)>,
+}
+
+impl MockVConsole {
+    pub fn new(scene: MockScene) -> MockVConsole {
+        MockVConsole {
+            scene,
+            fragments: HashMap::new(),
+            session_id: None,
+            received: Vec::new(),
+            nav_points: HashMap::new(),
+        }
+    }
+
+    /**
+     * Forgets everything the script keeps between messages, like the game does when scripts are reloaded.
+     */
+    pub fn reload_script(&mut self) {
+        self.fragments.clear();
+        self.nav_points.clear();
+    }
+
+    /**
+     * Checksum of the script's nav points, what it answers `nav_sync_checksum` with.
+     */
+    pub fn nav_sync_checksum(&self) -> NavSyncChecksum {
+        NavSyncChecksum::of(self.nav_points.iter().map(|(id, (position, nav_type))| (*id, position, *nav_type)))
+    }
+
+    /**
+     * Session id the client echoed back during the last handshake.
+     */
+    pub fn session_id(&self) -> Option<u64> {
+        self.session_id
+    }
+
+    /**
+     * Every VTunnel message received so far, after fragments have been reassembled.
+     */
+    pub fn received(&self) -> &[VTunnelMessage] {
+        &self.received
+    }
+
+    /**
+     * Packets the game sends as soon as a client connects.
+     */
+    pub fn connect_packets(&self) -> Vec<VConsolePacket> {
+        let mut packets = vec![
+            VConsolePacket::AppInfo(AppInfoPacket { _unknown: vec![0; AppInfoPacket::HEADER_SIZE], executable: self.scene.executable.clone() }),
+            VConsolePacket::Addon(AddonPacket { _unknown: [0; 4], enabled: true, _unknown2: 0, name: self.scene.addon_name.clone() }),
+            VConsolePacket::Channel(ChannelPacket {
+                channels: self.scene.channels.iter().enumerate().map(|(index, name)| VConsoleChannel {
+                    id: MOCK_VSCRIPT_CHANNEL_ID + index as u32,
+                    flags: 0,
+                    default_verbosity: 2,
+                    verbosity: 2,
+                    _unknown: [0; 6],
+                    color: [255, 255, 255, 255],
+                    name: name.clone(),
+                }).collect(),
+            }),
+        ];
+
+        for convar in &self.scene.convars {
+            packets.push(VConsolePacket::Convar(ConvarPacket { name: convar.clone(), flags: 0, min: None, max: None, _unknown: [0; 12], help: String::new() }));
+        }
+
+        packets
+    }
+
+    /**
+     * Handles a packet from the client and returns the packets to send back.
+     */
+    pub fn handle_packet(&mut self, packet: VConsolePacket) -> Vec<VConsolePacket> {
+        let VConsolePacket::Command(command) = packet else {
+            return vec![];
+        };
+
+        let mut replies = Vec::new();
+        for encoded_msg in command.vtunnel_messages() {
+            let Some(encoded_msg) = self.reassemble(encoded_msg) else {
+                continue;
+            };
+
+            match parse_vtunnel_message(&encoded_msg) {
+                Ok(vmsg) => replies.extend(self.handle_vmsg(vmsg)),
+                Err(err) => replies.push(self.print(&format!("Bad VTunnel message ({}): {}", err, encoded_msg))),
+            }
+        }
+
+        replies
+    }
+
+    /**
+     * Mirrors VTunnel.reassemble on the script side.
+     */
+    fn reassemble(&mut self, encoded_msg: &str) -> Option<String> {
+        let Ok(vmsg) = parse_vtunnel_message(encoded_msg) else {
+            return Some(encoded_msg.to_string());
+        };
+        if vmsg.name != "vtunnel_fragment" {
+            return Some(encoded_msg.to_string());
+        }
+
+        let mut fragment = VTunnelFragment { fragment_id: 0, index: 0, count: 0, data: String::new() };
+        fragment.apply_vtunnel_message(&vmsg).ok()?;
+        if fragment.count > MAX_FRAGMENT_COUNT {
+            return None;
+        }
+
+        let parts = self.fragments.entry(fragment.fragment_id).or_insert_with(|| vec![None; fragment.count as usize]);
+        *parts.get_mut(fragment.index as usize)? = Some(fragment.data);
+        if parts.iter().any(|part| part.is_none()) {
+            return None;
+        }
+
+        let parts = self.fragments.remove(&fragment.fragment_id)?;
+        Some(parts.into_iter().flatten().collect())
+    }
+
+    fn handle_vmsg(&mut self, vmsg: VTunnelMessage) -> Vec<VConsolePacket> {
+        self.received.push(vmsg.clone());
+
+        match vmsg.name.as_str() {
+            "vtunnel_request_handshake" => vec![self.send(VTunnelMessage::new("vtunnel_request_handshake".to_string()))],
+            "vtunnel_handshake" => {
+                self.session_id = Some(vmsg.id);
+                let mut reply = VTunnelMessage::new("vtunnel_handshake".to_string());
+                reply.set_id(vmsg.id);
+                vec![self.send(reply)]
+            }
+            "vtunnel_connected" => vec![self.print("VTunnel connected!")],
+            "line_trace" => {
+                let mut trace = LineTrace::new(Vector3::default(), Vector3::default());
+                match trace.apply_vtunnel_message(&vmsg) {
+                    Ok(_) => {
+                        let result = self.scene.trace(&trace.start_position, &trace.end_position, &Vector3::default(), &Vector3::default());
+                        vec![self.send_trace_result(vmsg.id, result, "line_trace_result")]
+                    }
+                    Err(err) => vec![self.print(&format!("Bad line_trace: {}", err))],
+                }
+            }
+            "box_trace" => {
+                let mut trace = BoxTrace::new(Vector3::default(), Vector3::default(), Vector3::default(), Vector3::default());
+                match trace.apply_vtunnel_message(&vmsg) {
+                    Ok(_) => {
+                        let result = self.scene.trace(&trace.start_position, &trace.end_position, &trace.mins, &trace.maxs);
+                        vec![self.send_trace_result(vmsg.id, result, "box_trace_result")]
+                    }
+                    Err(err) => vec![self.print(&format!("Bad box_trace: {}", err))],
+                }
+            }
+            "trace_batch" => match Self::run_trace_batch(&self.scene, &vmsg) {
+                Ok(mut reply) => {
+                    reply.set_id(vmsg.id);
+                    vec![self.send(reply)]
+                }
+                Err(err) => vec![self.print(&format!("Bad trace_batch: {}", err))],
+            },
+            "clear_nav_points" => {
+                self.nav_points.clear();
+                vec![]
+            }
+            "add_nav_point" => match Self::read_nav_point(&vmsg) {
+                Ok((id, position, nav_type)) => {
+                    self.nav_points.insert(id, (position, nav_type));
+                    vec![]
+                }
+                Err(err) => vec![self.print(&format!("Bad add_nav_point: {}", err))],
+            },
+            "remove_nav_point" => match vmsg.get_field::<u64>(0, "id") {
+                Ok(id) => {
+                    self.nav_points.remove(&id);
+                    vec![]
+                }
+                Err(err) => vec![self.print(&format!("Bad remove_nav_point: {}", err))],
+            },
+            "nav_sync_checksum" => {
+                let mut reply = self.nav_sync_checksum().serialize();
+                reply.set_id(vmsg.id);
+                vec![self.send(reply)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn read_nav_point(vmsg: &VTunnelMessage) -> Result<(u64, Vector3, u8), VTunnelMessageError> {
+        Ok((vmsg.get_field(0, "id")?, vmsg.get_field(1, "position")?, vmsg.get_field(2, "nav_type")?))
+    }
+
+    fn run_trace_batch(scene: &MockScene, vmsg: &VTunnelMessage) -> Result<VTunnelMessage, VTunnelMessageError> {
+        // Skip the mask, ignored entity and debug flag, then 4 vectors per trace.
+        let mut reply = VTunnelMessage::new("trace_batch_result".to_string());
+        let trace_count = vmsg.data.len().saturating_sub(3) / 4;
+        reply.add_int(trace_count as i64);
+        for index in 0..trace_count {
+            let field = |offset: usize, name: &'static str| vmsg.get_field::<Vector3>(3 + index * 4 + offset, name);
+            let result = scene.trace(&field(0, "start_position")?, &field(1, "end_position")?, &field(2, "mins")?, &field(3, "maxs")?);
+            reply.data.extend(result.serialize().data);
+        }
+        Ok(reply)
+    }
+
+    fn send_trace_result(&self, id: u64, result: TraceResult, name: &str) -> VConsolePacket {
+        let mut vmsg = result.serialize();
+        vmsg.name = name.to_string();
+        vmsg.set_id(id);
+        self.send(vmsg)
+    }
+
+    /**
+     * Wraps a VTunnel message in a print, the same way the script side sends them.
+     */
+    pub fn send(&self, vmsg: VTunnelMessage) -> VConsolePacket {
+        self.print(&encode_vtunnel_message(&vmsg))
+    }
+
+    pub fn print(&self, text: &str) -> VConsolePacket {
+        VConsolePacket::Print(PrintPacket {
+            channel_id: MOCK_VSCRIPT_CHANNEL_ID,
+            verbosity: 2,
+            _unknown: [0; 18],
+            color: [255, 255, 255, 255],
+            message: format!("{}\n", text),
+        })
+    }
+}
+
+/**
+ * Serves a single client until it disconnects.
+ * Messages sent on `injected` are printed to the client as if the game sent them.
+ */
+pub async fn serve_connection(stream: TcpStream, mock: &mut MockVConsole, injected: &mut mpsc::Receiver<VTunnelMessage>) -> Result<(), std::io::Error> {
+    let mut framed = Framed::new(stream, PacketCodec);
+    for packet in mock.connect_packets() {
+        framed.send(packet).await?;
+    }
+
+    loop {
+        tokio::select! {
+            packet = framed.next() => {
+                match packet {
+                    Some(Ok(packet)) => {
+                        for reply in mock.handle_packet(packet) {
+                            framed.send(reply).await?;
+                        }
+                    }
+                    Some(Err(err)) => return Err(err),
+                    None => return Ok(()),
+                }
+            }
+            Some(vmsg) = injected.recv() => {
+                framed.send(mock.send(vmsg)).await?;
+            }
+        }
+    }
+}
+
+/**
+ * Accepts clients one at a time, forever. Each client gets a fresh game state.
+ */
+pub async fn run_mock_server(listener: TcpListener, scene: MockScene, mut injected: mpsc::Receiver<VTunnelMessage>) -> Result<(), std::io::Error> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        println!("Client connected from {}", address);
+
+        let mut mock = MockVConsole::new(scene.clone());
+        if let Err(err) = serve_connection(stream, &mut mock, &mut injected).await {
+            eprintln!("Client error: {:?}", err);
+        }
+        println!("Client disconnected, received {} messages", mock.received().len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use bsi_tools_lib::math::Vector3;
+    use futures::SinkExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+    use crate::config::{LogLevel, VTunnelConfig};
+    use crate::connection::{run_connection, spawn_message_handlers, ConsoleState};
+    use crate::game::gadget::GadgetActivated;
+    use crate::game::player::{input_button, PlayerInput};
+    use crate::game::state::GameState;
+    use crate::game::trace::{BoxTrace, LineTrace, TraceBatch};
+    use crate::mock_server::{serve_connection, MockScene, MockVConsole};
+    use crate::nav_builder::nav_builder::NavPointStore;
+    use crate::nav_builder::nav_sync::NavSyncChecksum;
+    use crate::vconsole::{CommandPacket, Packet, PacketCodec, VConsolePacket};
+    use crate::vtunnel::{parse_vtunnel_message, VTunnelFragment, VTunnelMessage, VTunnelSerializable};
+    use crate::vtunnel_emitter::{pack_vtunnel_messages, EmitterHandle};
+
+    fn replies(mock: &mut MockVConsole, vmsgs: Vec<VTunnelMessage>) -> Vec<VTunnelMessage> {
+        let mut fragment_id_sequence = 0;
+        pack_vtunnel_messages(vmsgs, &mut fragment_id_sequence)
+            .into_iter()
+            .flat_map(|packet| mock.handle_packet(VConsolePacket::from_packet(packet)))
+            .filter_map(|packet| match packet {
+                VConsolePacket::Print(print) => parse_vtunnel_message(print.text()).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mock_handshake_and_fragments() {
+        let mut mock = MockVConsole::new(MockScene::default());
+
+        let mut handshake = VTunnelMessage::new("vtunnel_handshake".to_string());
+        handshake.set_id(1234);
+        let reply = replies(&mut mock, vec![handshake]);
+        assert_eq!(reply[0].name, "vtunnel_handshake");
+        assert_eq!(reply[0].id, 1234);
+        assert_eq!(mock.session_id(), Some(1234));
+
+        // Long enough to be fragmented, should come out whole on the other side.
+        let mut long_vmsg = VTunnelMessage::new("long_message".to_string());
+        long_vmsg.add_string("x".repeat(2000));
+        replies(&mut mock, vec![long_vmsg.clone()]);
+        assert_eq!(mock.received().last(), Some(&long_vmsg));
+
+        // A count this large is never waited on.
+        let huge = VTunnelFragment { fragment_id: 9, index: 0, count: u32::MAX, data: "x".to_string() };
+        replies(&mut mock, vec![huge.serialize()]);
+        assert!(mock.fragments.is_empty());
+
+        let unrelated = mock.handle_packet(VConsolePacket::Command(CommandPacket::new("echo hi".to_string())));
+        assert!(unrelated.is_empty());
+    }
+
+    #[test]
+    fn test_mock_scene_trace() {
+        let scene = MockScene::default();
+        let zero = Vector3::default();
+
+        let result = scene.trace(&Vector3::new(0.0, 0.0, 100.0), &Vector3::new(0.0, 0.0, -100.0), &zero, &zero);
+        assert!(result.hit);
+        assert_eq!(result.hit_position, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(result.hit_normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(result.fraction, 0.5);
+
+        let result = scene.trace(&Vector3::new(0.0, 0.0, 100.0), &Vector3::new(0.0, 0.0, 50.0), &zero, &zero);
+        assert!(!result.hit);
+        assert_eq!(result.fraction, 1.0);
+
+        // Box with its bottom 10 units below its origin lands 10 units above the floor.
+        let result = scene.trace(&Vector3::new(0.0, 0.0, 100.0), &Vector3::new(0.0, 0.0, -100.0), &Vector3::new(-8.0, -8.0, -10.0), &Vector3::new(8.0, 8.0, 10.0));
+        assert_eq!(result.hit_position, Vector3::new(0.0, 0.0, 10.0));
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_traces_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (_injected_sender, mut injected) = mpsc::channel(1);
+            let mut mock = MockVConsole::new(MockScene::default());
+            serve_connection(stream, &mut mock, &mut injected).await.unwrap();
+        });
+
+        let (outbox_sender, mut outbox_receiver) = mpsc::channel::<Packet>(16);
+        let emitter = EmitterHandle::new(outbox_sender);
+        let reply_emitter = emitter.clone();
+        let mut framed = Framed::new(TcpStream::connect(address).await.unwrap(), PacketCodec);
+        let connected = Arc::new(tokio::sync::Notify::new());
+        let connected_notify = Arc::clone(&connected);
+        tokio::spawn(async move {
+            let mut channels_seen = false;
+            loop {
+                tokio::select! {
+                    Some(Ok(packet)) = framed.next() => match packet {
+                        VConsolePacket::Channel(_) if !channels_seen => {
+                            channels_seen = true;
+                            connected_notify.notify_one();
+                        }
+                        VConsolePacket::Print(print) => {
+                            if let Ok(vmsg) = parse_vtunnel_message(print.text()) {
+                                reply_emitter.handle_reply(vmsg).await;
+                            }
+                        }
+                        _ => {}
+                    },
+                    Some(packet) = outbox_receiver.recv() => framed.send(packet).await.unwrap(),
+                    else => break,
+                }
+            }
+        });
+        connected.notified().await;
+
+        let result = LineTrace::new(Vector3::new(16.0, 32.0, 64.0), Vector3::new(16.0, 32.0, -64.0)).run(&emitter).await.unwrap();
+        assert!(result.hit);
+        assert_eq!(result.hit_position, Vector3::new(16.0, 32.0, 0.0));
+
+        let mins = Vector3::new(-16.0, -16.0, 0.0);
+        let maxs = Vector3::new(16.0, 16.0, 72.0);
+        let result = BoxTrace::new(Vector3::new(600.0, 0.0, 64.0), Vector3::new(600.0, 0.0, -64.0), mins.clone(), maxs.clone()).run(&emitter).await.unwrap();
+        assert!(!result.hit);
+
+        // Enough traces to need several requests, half of them off the edge of the floor.
+        let mut batch = TraceBatch::new();
+        for i in 0..40 {
+            let position = Vector3::new(i as f64 * 50.0 - 975.0, 0.0, 64.0);
+            batch.add_box(position.clone(), position.sub(&Vector3::new(0.0, 0.0, 128.0)), mins.clone(), maxs.clone());
+        }
+        let batch_len = batch.len();
+        let results = batch.run(&emitter).await.unwrap();
+        assert_eq!(results.len(), batch_len);
+        for (i, result) in results.iter().enumerate() {
+            let x = i as f64 * 50.0 - 975.0;
+            assert_eq!(result.hit, x.abs() < 512.0 + 16.0, "trace {} at x {}", i, x);
+        }
+    }
+
+    /**
+     * Runs the real connection and game state against the mock: the handshake, then a player adding a nav point
+     * with the nav builder, which traces against the mock scene and syncs the new point back to it.
+     */
+    #[tokio::test]
+    async fn test_vtunnel_against_mock() {
+        let db_path = std::env::temp_dir().join(format!("vtunnel_mock_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let config = Arc::new(VTunnelConfig { database: db_path.clone(), log_level: LogLevel::Quiet, ..VTunnelConfig::default() });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, PacketCodec);
+            let mut mock = MockVConsole::new(MockScene::default());
+            for packet in mock.connect_packets() {
+                framed.send(packet).await.unwrap();
+            }
+
+            // Once connected, pick up the gadget and click twice, the first click after picking it up is skipped.
+            let mut clicked = false;
+            while let Some(Ok(packet)) = framed.next().await {
+                for reply in mock.handle_packet(packet) {
+                    framed.send(reply).await.unwrap();
+                }
+                if !clicked && mock.received().iter().any(|vmsg| vmsg.name == "vtunnel_connected") {
+                    clicked = true;
+                    let click = PlayerInput {
+                        user_id: 1,
+                        trace_hit: true,
+                        trace_position: Vector3::new(64.0, 32.0, 0.0),
+                        buttons_pressed: 1 << input_button::IN_USE_HAND1,
+                        ..PlayerInput::new()
+                    };
+                    for vmsg in [GadgetActivated { user_id: 1 }.serialize(), click.serialize(), click.serialize()] {
+                        framed.send(mock.send(vmsg)).await.unwrap();
+                    }
+                }
+                if mock.received().iter().any(|vmsg| vmsg.name == "add_nav_point") {
+                    return mock;
+                }
+            }
+            panic!("connection closed before a nav point was added");
+        });
+
+        let (inbox_sender, inbox_receiver) = mpsc::channel(1000);
+        let (inbox_reply_sender, inbox_reply_receiver) = mpsc::channel(1000);
+        let (outbox_sender, outbox_receiver) = mpsc::channel(1000);
+        let emitter = EmitterHandle::with_timeout(outbox_sender, config.request_timeout());
+        let game_state = GameState::new(emitter.clone(), &config).await.unwrap();
+        spawn_message_handlers(game_state, emitter.clone(), inbox_receiver, inbox_reply_receiver);
+
+        let outbox_receiver = Arc::new(tokio::sync::Mutex::new(outbox_receiver));
+        let recorder = Arc::new(std::sync::Mutex::new(None));
+        let stream = TcpStream::connect(address).await.unwrap();
+        tokio::spawn(async move {
+            let mut console_state = ConsoleState::new(config, inbox_sender, inbox_reply_sender);
+            let _ = run_connection(stream, &outbox_receiver, &mut console_state, &recorder).await;
+        });
+
+        let mock = tokio::time::timeout(std::time::Duration::from_secs(10), server).await.unwrap().unwrap();
+        assert!(mock.session_id().is_some());
+
+        // The point the nav builder stored is the one the mock was sent, on the floor under the click.
+        let store = NavPointStore::open_map(&db_path, Some(&MockScene::default().addon_name)).unwrap();
+        let nav_points = store.nav_points();
+        assert_eq!(nav_points.len(), 1);
+        assert_eq!(nav_points[0].position.z.round(), 0.0);
+        assert_eq!(mock.nav_sync_checksum(), NavSyncChecksum::of(nav_points.iter().map(|nav_point| (nav_point.id, &nav_point.position, nav_point.nav_type.to_int()))));
+
+        emitter.shutdown().await;
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
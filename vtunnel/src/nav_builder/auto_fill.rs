@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use bsi_tools_lib::math::Vector3;
+use tokio::sync::Mutex;
+use crate::game::elizabeth::Elizabeth;
+use crate::game::trace::TraceBatch;
+use crate::nav_builder::nav_builder::{round_to_grid, NavPointStore, NavType};
+use crate::vtunnel::{VTunnelMessage, VTunnelMessageBatch};
+use crate::vtunnel_emitter::EmitterHandle;
+
+#[derive(Debug, Clone)]
+pub struct AutoFillSettings {
+    // How far from the starting point to fill, in units.
+    pub radius: f64,
+    // Largest height difference between neighbouring nav points.
+    pub step_height: f64,
+    // Steepest floor that still gets nav points.
+    pub max_slope_degrees: f64,
+}
+
+impl Default for AutoFillSettings {
+    fn default() -> Self {
+        AutoFillSettings {
+            radius: 512.0,
+            step_height: 18.0,
+            max_slope_degrees: 45.0,
+        }
+    }
+}
+
+/**
+ * Sent to the script after every ring of an auto fill, and once more when it stops.
+ */
+#[derive(Debug, Default, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "nav_fill_progress", crate = "crate")]
+pub struct NavFillProgress {
+    pub user_id: u64,
+    // Nav points added so far.
+    pub added: u64,
+    // Grid cells traced so far.
+    pub visited: u64,
+    // Grid cells waiting to be traced.
+    pub queued: u64,
+    pub done: bool,
+    pub cancelled: bool,
+}
+
+/**
+ * A grid cell waiting to be traced, and the floor of the cell it was reached from.
+ */
+#[derive(Debug, Clone)]
+struct FillCell {
+    x: i64,
+    y: i64,
+    parent_floor: Vector3,
+    // The starting cell has no neighbour to check for walls against.
+    has_parent: bool,
+}
+
+/**
+ * Flood fills the nav point grid outwards from a point, one ring of cells at a time.
+ *
+ * Every ring takes two batched round-trips: floor traces for every cell, then a headroom trace and a
+ * wall trace back to the neighbouring cell for every floor found. Cells that can be walked to get a
+ * walkable nav point and keep spreading, floors without headroom get an obstacle nav point.
+ * Only one floor is filled per grid column, so overlapping floors need a fill started on each.
+ */
+pub struct AutoFill {
+    emitter: EmitterHandle,
+    store: Arc<Mutex<NavPointStore>>,
+    settings: AutoFillSettings,
+    user_id: u64,
+    // Layer the new nav points go into.
+    layer: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl AutoFill {
+    pub fn new(emitter: EmitterHandle, store: Arc<Mutex<NavPointStore>>, settings: AutoFillSettings, user_id: u64, layer: &str) -> Self {
+        AutoFill {
+            emitter,
+            store,
+            settings,
+            user_id,
+            layer: layer.to_string(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /**
+     * Flag that stops the fill after the ring it's working on.
+     * It also stops by itself, without adding the ring, if the store switches to another map.
+     */
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    fn cell_position(x: i64, y: i64) -> Vector3 {
+        Vector3::new(x as f64 * NavPointStore::NAV_POINT_GRID_SIZE, y as f64 * NavPointStore::NAV_POINT_GRID_SIZE, 0.0)
+    }
+
+    pub async fn run(self, start: &Vector3) -> Result<NavFillProgress, std::io::Error> {
+        let grid_start = round_to_grid(start);
+        let start_x = (grid_start.x / NavPointStore::NAV_POINT_GRID_SIZE) as i64;
+        let start_y = (grid_start.y / NavPointStore::NAV_POINT_GRID_SIZE) as i64;
+        let min_floor_normal_z = self.settings.max_slope_degrees.to_radians().cos();
+
+        let mut progress = NavFillProgress { user_id: self.user_id, ..NavFillProgress::default() };
+        let mut visited = HashSet::from([(start_x, start_y)]);
+        let mut ring = vec![FillCell { x: start_x, y: start_y, parent_floor: start.clone(), has_parent: false }];
+        // The whole fill is undone in one go, and only belongs on the map it started on.
+        let (group, map_name) = {
+            let mut store = self.store.lock().await;
+            (store.begin_edit_group(self.user_id), store.map_name().to_string())
+        };
+
+        while !ring.is_empty() {
+            if self.cancelled.load(Ordering::SeqCst) {
+                progress.cancelled = true;
+                break;
+            }
+
+            // Floors within a step of the neighbouring cell.
+            let mut floor_batch = TraceBatch::new();
+            for cell in &ring {
+                let mut top = Self::cell_position(cell.x, cell.y);
+                top.z = cell.parent_floor.z + self.settings.step_height;
+                let bottom = top.sub(&Vector3::new(0.0, 0.0, self.settings.step_height * 2.0 + 1.0));
+                floor_batch.add_line(top, bottom);
+            }
+            let floor_results = floor_batch.run(&self.emitter).await?;
+
+            let floors: Vec<(&FillCell, Vector3)> = ring
+                .iter()
+                .zip(floor_results)
+                .filter(|(_, result)| result.hit && !result.start_in_solid && result.hit_normal.z >= min_floor_normal_z)
+                .map(|(cell, result)| {
+                    let mut floor = Self::cell_position(cell.x, cell.y);
+                    floor.z = result.hit_position.z;
+                    (cell, floor)
+                })
+                .collect();
+
+            // Room to stand, and nothing in the way from the neighbouring cell.
+            let mut space_batch = TraceBatch::new();
+            for (cell, floor) in &floors {
+                space_batch.add_box(
+                    floor.add(&Vector3::new(0.0, 0.0, NavPointStore::NAV_POINT_GRID_SIZE)),
+                    floor.add(&Vector3::new(0.0, 0.0, 66.0)),
+                    Elizabeth::MINS,
+                    Elizabeth::MAXS,
+                );
+                let step_up = Vector3::new(0.0, 0.0, self.settings.step_height);
+                let wall_start = if cell.has_parent { cell.parent_floor.add(&step_up) } else { floor.add(&step_up) };
+                space_batch.add_line(wall_start, floor.add(&step_up));
+            }
+            let space_results = space_batch.run(&self.emitter).await?;
+
+            let mut draw_batch = VTunnelMessageBatch::new();
+            let mut next_ring = Vec::new();
+            {
+                let mut store = self.store.lock().await;
+                if store.map_name() != map_name {
+                    progress.cancelled = true;
+                    break;
+                }
+                for ((cell, floor), results) in floors.iter().zip(space_results.chunks(2)) {
+                    let (space, wall) = (&results[0], &results[1]);
+                    if wall.hit || wall.start_in_solid {
+                        continue;
+                    }
+
+                    let nav_type = if space.hit || space.start_in_solid { NavType::Obstacle } else { NavType::Walkable };
+                    let walkable = matches!(nav_type, NavType::Walkable);
+                    let nav_point = store.add_nav_point(group, floor, nav_type, &self.layer).map_err(std::io::Error::other)?;
+                    if let Some(nav_point) = nav_point {
+                        draw_batch.add_message(nav_point.to_draw_vmsg());
+                        progress.added += 1;
+                    }
+                    if !walkable {
+                        continue;
+                    }
+
+                    let (x, y) = (cell.x, cell.y);
+                    for (next_x, next_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                        let distance = Self::cell_position(next_x - start_x, next_y - start_y);
+                        if distance.x.hypot(distance.y) > self.settings.radius || !visited.insert((next_x, next_y)) {
+                            continue;
+                        }
+                        next_ring.push(FillCell { x: next_x, y: next_y, parent_floor: floor.clone(), has_parent: true });
+                    }
+                }
+            }
+
+            progress.visited += ring.len() as u64;
+            progress.queued = next_ring.len() as u64;
+            if !draw_batch.messages.is_empty() {
+                self.emitter.send_batch(draw_batch).await;
+            }
+            self.emitter.send(&progress).await;
+            ring = next_ring;
+        }
+
+        progress.done = true;
+        progress.queued = 0;
+        self.emitter.send(&progress).await;
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use bsi_tools_lib::math::Vector3;
+    use tokio::sync::{mpsc, Mutex};
+    use crate::mock_server::{MockScene, MockSolid, MockVConsole};
+    use crate::nav_builder::auto_fill::{AutoFill, AutoFillSettings};
+    use crate::nav_builder::nav_builder::{NavPointStore, NavType};
+    use crate::vconsole::VConsolePacket;
+    use crate::vtunnel::parse_vtunnel_message;
+    use crate::vtunnel_emitter::EmitterHandle;
+
+    /**
+     * Connects an emitter straight to a mock console, without going through TCP.
+     */
+    fn mock_emitter(scene: MockScene) -> EmitterHandle {
+        mock_emitter_switching(scene, None)
+    }
+
+    /**
+     * Like `mock_emitter`, but switches the store to another map when the first packet arrives.
+     */
+    fn mock_emitter_switching(scene: MockScene, mut switch_map: Option<(Arc<Mutex<NavPointStore>>, &'static str)>) -> EmitterHandle {
+        let (sender, mut receiver) = mpsc::channel(64);
+        let emitter = EmitterHandle::new(sender);
+        let reply_emitter = emitter.clone();
+        tokio::spawn(async move {
+            let mut mock = MockVConsole::new(scene);
+            while let Some(packet) = receiver.recv().await {
+                if let Some((store, map_name)) = switch_map.take() {
+                    store.lock().await.switch_map(map_name).unwrap();
+                }
+                for reply in mock.handle_packet(VConsolePacket::from_packet(packet)) {
+                    if let VConsolePacket::Print(print) = reply {
+                        if let Ok(vmsg) = parse_vtunnel_message(print.text()) {
+                            reply_emitter.handle_reply(vmsg).await;
+                        }
+                    }
+                }
+            }
+        });
+        emitter
+    }
+
+    async fn fill(scene: MockScene, radius: f64) -> Vec<(Vector3, NavType)> {
+        let store = Arc::new(Mutex::new(NavPointStore::open(Path::new(":memory:")).unwrap()));
+        let settings = AutoFillSettings { radius, ..AutoFillSettings::default() };
+        let progress = AutoFill::new(mock_emitter(scene), store.clone(), settings, 1, NavPointStore::DEFAULT_LAYER).run(&Vector3::new(0.0, 0.0, 0.0)).await.unwrap();
+        assert!(progress.done && !progress.cancelled);
+
+        let store = store.lock().await;
+        assert_eq!(progress.added as usize, store.nav_points().len());
+        store.nav_points().iter().map(|nav_point| (nav_point.position.clone(), nav_point.nav_type.clone())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_auto_fill_open_floor() {
+        // Every cell within 40 units, 1 + 4 + 4 + 4 + 8.
+        let points = fill(MockScene::default(), 40.0).await;
+        assert_eq!(points.len(), 21);
+        assert!(points.iter().all(|(position, nav_type)| position.z == 0.0 && matches!(nav_type, NavType::Walkable)));
+    }
+
+    #[tokio::test]
+    async fn test_auto_fill_walls_and_steps() {
+        let mut scene = MockScene::default();
+        // A wall along x = 32, and a step too tall to climb along y = -32.
+        scene.solids.push(MockSolid { mins: [24.0, -512.0, 0.0], maxs: [40.0, 512.0, 128.0], entity_id: 2 });
+        scene.solids.push(MockSolid { mins: [-512.0, -512.0, 0.0], maxs: [20.0, -24.0, 24.0], entity_id: 3 });
+
+        let points = fill(scene, 64.0).await;
+        assert!(!points.is_empty());
+        for (position, nav_type) in &points {
+            assert!(position.x < 24.0, "{:?} is past the wall", position);
+            assert!(position.y > -24.0, "{:?} is on top of the step", position);
+            // Right next to the wall there's no room to stand.
+            if position.x == 16.0 {
+                assert!(matches!(nav_type, NavType::Obstacle));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auto_fill_stops_on_map_switch() {
+        let store = Arc::new(Mutex::new(NavPointStore::open(Path::new(":memory:")).unwrap()));
+        let emitter = mock_emitter_switching(MockScene::default(), Some((store.clone(), "other")));
+        let progress = AutoFill::new(emitter, store.clone(), AutoFillSettings::default(), 1, NavPointStore::DEFAULT_LAYER).run(&Vector3::new(0.0, 0.0, 0.0)).await.unwrap();
+        assert!(progress.done && progress.cancelled);
+        assert_eq!(progress.added, 0);
+
+        // Nothing went into the new map, and nothing more into the old one.
+        let mut store = store.lock().await;
+        assert!(store.nav_points().is_empty());
+        store.switch_map("").unwrap();
+        assert!(store.nav_points().is_empty());
+    }
+}
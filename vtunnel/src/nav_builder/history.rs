@@ -0,0 +1,264 @@
+use bsi_tools_lib::math::Vector3;
+use rusqlite::{Connection, OptionalExtension};
+use crate::nav_builder::nav_builder::{NavPoint, NavType};
+use crate::vtunnel::VTunnelMessage;
+
+/**
+ * A single change to the nav points, as stored in the edit journal.
+ */
+#[derive(Debug, Clone)]
+pub enum NavEdit {
+    Add(NavPoint),
+    Remove(NavPoint),
+    SetType { nav_point: NavPoint, old_nav_type: NavType },
+}
+
+impl NavEdit {
+    fn operation(&self) -> u8 {
+        match self {
+            NavEdit::Add(_) => 0,
+            NavEdit::Remove(_) => 1,
+            NavEdit::SetType { .. } => 2,
+        }
+    }
+
+    pub fn nav_point(&self) -> &NavPoint {
+        match self {
+            NavEdit::Add(nav_point) => nav_point,
+            NavEdit::Remove(nav_point) => nav_point,
+            NavEdit::SetType { nav_point, .. } => nav_point,
+        }
+    }
+
+    /**
+     * The edit that undoes this one.
+     */
+    pub fn inverse(&self) -> NavEdit {
+        match self {
+            NavEdit::Add(nav_point) => NavEdit::Remove(nav_point.clone()),
+            NavEdit::Remove(nav_point) => NavEdit::Add(nav_point.clone()),
+            NavEdit::SetType { nav_point, old_nav_type } => NavEdit::SetType {
+                nav_point: NavPoint { nav_type: old_nav_type.clone(), ..nav_point.clone() },
+                old_nav_type: nav_point.nav_type.clone(),
+            },
+        }
+    }
+}
+
+/**
+ * Edits made by one action, like a trigger pull or a whole auto fill. Undo and redo work on whole groups.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EditGroup {
+    pub id: u64,
+    pub user_id: u64,
+}
+
+/**
+ * Sent to the script after a player's history changes, so the gadget can show it.
+ */
+#[derive(Debug, Default, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "nav_edit_history", crate = "crate")]
+pub struct NavEditHistory {
+    pub user_id: u64,
+    // Edit groups that can be undone.
+    pub undo_count: u64,
+    // Edit groups that can be redone.
+    pub redo_count: u64,
+    pub last_action: String,
+}
+
+/**
+ * Undo/redo history for the nav points, kept in the `nav_edit` table next to them so it survives restarts.
+ *
+ * Every player has their own linear history on each map. Undone edits stay in the journal until that player
+ * makes a new edit on the same map, which drops them, the same as any editor's redo stack.
+ */
+pub struct EditJournal {
+    next_group_id: u64,
+}
+
+impl EditJournal {
+    /**
+     * The `nav_edit` table has to exist already, see `migrations`.
+     */
+    pub fn open(db: &Connection) -> rusqlite::Result<Self> {
+        let last_group_id: u64 = db.query_row("SELECT COALESCE(MAX(group_id), 0) FROM nav_edit", [], |row| row.get(0))?;
+        Ok(EditJournal { next_group_id: last_group_id + 1 })
+    }
+
+    pub fn begin_group(&mut self, user_id: u64) -> EditGroup {
+        let group = EditGroup { id: self.next_group_id, user_id };
+        self.next_group_id += 1;
+        group
+    }
+
+    pub fn record(&self, db: &Connection, map_name: &str, group: EditGroup, edit: &NavEdit) -> rusqlite::Result<()> {
+        // A new edit makes everything this player undid unreachable.
+        db.execute("DELETE FROM nav_edit WHERE map_name = ?1 AND user_id = ?2 AND undone = 1", (map_name, group.user_id))?;
+
+        let nav_point = edit.nav_point();
+        let old_nav_type = match edit {
+            NavEdit::SetType { old_nav_type, .. } => old_nav_type.to_int(),
+            _ => nav_point.nav_type.to_int(),
+        };
+        db.execute(
+            "INSERT INTO nav_edit (group_id, user_id, operation, nav_point_id, position_x, position_y, position_z, nav_type, old_nav_type, map_name, layer)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            (
+                group.id, group.user_id, edit.operation(), nav_point.id,
+                nav_point.position.x, nav_point.position.y, nav_point.position.z,
+                nav_point.nav_type.to_int(), old_nav_type, map_name, &nav_point.layer,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /**
+     * Group ids are only unique within one VTunnel, another one on the same database can hand out the same ones.
+     * Scoping by map and player keeps their groups apart, since each player only edits through one.
+     */
+    fn load_group(db: &Connection, map_name: &str, user_id: u64, group_id: u64) -> rusqlite::Result<Vec<NavEdit>> {
+        let mut query = db.prepare(
+            "SELECT operation, nav_point_id, position_x, position_y, position_z, nav_type, old_nav_type, layer
+            FROM nav_edit WHERE map_name = ?1 AND user_id = ?2 AND group_id = ?3 ORDER BY id"
+        )?;
+
+        let edits = query.query_map((map_name, user_id, group_id), |row| {
+            let nav_point = NavPoint {
+                id: row.get(1)?,
+                position: Vector3::new(row.get(2)?, row.get(3)?, row.get(4)?),
+                nav_type: NavType::from_int(row.get(5)?),
+                layer: row.get(7)?,
+            };
+            Ok(match row.get::<_, u8>(0)? {
+                0 => NavEdit::Add(nav_point),
+                1 => NavEdit::Remove(nav_point),
+                _ => NavEdit::SetType { nav_point, old_nav_type: NavType::from_int(row.get(6)?) },
+            })
+        })?;
+        edits.collect()
+    }
+
+    /**
+     * Marks the player's latest group as undone and returns the edits that undo it, newest first.
+     */
+    pub fn undo(&self, db: &Connection, map_name: &str, user_id: u64) -> rusqlite::Result<Vec<NavEdit>> {
+        let group_id: Option<u64> = db.query_row(
+            "SELECT MAX(group_id) FROM nav_edit WHERE map_name = ?1 AND user_id = ?2 AND undone = 0", (map_name, user_id), |row| row.get(0),
+        ).optional()?.flatten();
+        let Some(group_id) = group_id else {
+            return Ok(Vec::new());
+        };
+
+        db.execute("UPDATE nav_edit SET undone = 1 WHERE map_name = ?1 AND user_id = ?2 AND group_id = ?3", (map_name, user_id, group_id))?;
+        Ok(Self::load_group(db, map_name, user_id, group_id)?.iter().rev().map(NavEdit::inverse).collect())
+    }
+
+    /**
+     * Marks the player's most recently undone group as done again and returns its edits, oldest first.
+     */
+    pub fn redo(&self, db: &Connection, map_name: &str, user_id: u64) -> rusqlite::Result<Vec<NavEdit>> {
+        let group_id: Option<u64> = db.query_row(
+            "SELECT MIN(group_id) FROM nav_edit WHERE map_name = ?1 AND user_id = ?2 AND undone = 1", (map_name, user_id), |row| row.get(0),
+        ).optional()?.flatten();
+        let Some(group_id) = group_id else {
+            return Ok(Vec::new());
+        };
+
+        db.execute("UPDATE nav_edit SET undone = 0 WHERE map_name = ?1 AND user_id = ?2 AND group_id = ?3", (map_name, user_id, group_id))?;
+        Self::load_group(db, map_name, user_id, group_id)
+    }
+
+    /**
+     * How many groups the player can undo and redo.
+     */
+    pub fn counts(&self, db: &Connection, map_name: &str, user_id: u64) -> rusqlite::Result<(u64, u64)> {
+        db.query_row(
+            "SELECT COUNT(DISTINCT CASE WHEN undone = 0 THEN group_id END), COUNT(DISTINCT CASE WHEN undone = 1 THEN group_id END)
+            FROM nav_edit WHERE map_name = ?1 AND user_id = ?2",
+            (map_name, user_id),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_builder::nav_builder::{NavPointStore, NavType};
+
+    #[test]
+    fn test_undo_redo_survives_restart() {
+        let db_path = std::env::temp_dir().join(format!("vtunnel_history_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let positions = |store: &NavPointStore| {
+            let mut positions: Vec<f64> = store.nav_points().iter().map(|nav_point| nav_point.position.x).collect();
+            positions.sort_by(f64::total_cmp);
+            positions
+        };
+
+        {
+            let mut store = NavPointStore::open(&db_path).unwrap();
+            let group = store.begin_edit_group(1);
+            store.add_nav_point(group, &Vector3::new(0.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+            let second = store.add_nav_point(group, &Vector3::new(16.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+            // Another player's edit isn't part of player 1's history.
+            let other = store.begin_edit_group(2);
+            store.add_nav_point(other, &Vector3::new(64.0, 0.0, 0.0), NavType::Obstacle, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+            let group = store.begin_edit_group(1);
+            store.remove_nav_point(group, second.id).unwrap().unwrap();
+            assert_eq!(store.history(1, "").unwrap().undo_count, 2);
+        }
+
+        let mut store = NavPointStore::open(&db_path).unwrap();
+        assert_eq!(positions(&store), vec![0.0, 64.0]);
+
+        assert_eq!(store.undo(1).unwrap().len(), 1);
+        assert_eq!(positions(&store), vec![0.0, 16.0, 64.0]);
+        assert_eq!(store.undo(1).unwrap().len(), 2);
+        assert_eq!(positions(&store), vec![64.0]);
+        assert!(store.undo(1).unwrap().is_empty());
+
+        assert_eq!(store.redo(1).unwrap().len(), 2);
+        assert_eq!(positions(&store), vec![0.0, 16.0, 64.0]);
+        let history = store.history(1, "redo").unwrap();
+        assert_eq!((history.undo_count, history.redo_count), (1, 1));
+
+        // A new edit drops what's left to redo.
+        let group = store.begin_edit_group(1);
+        store.add_nav_point(group, &Vector3::new(32.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+        assert!(store.redo(1).unwrap().is_empty());
+        assert_eq!(store.history(1, "").unwrap().undo_count, 2);
+        assert_eq!(store.undo(2).unwrap().len(), 1);
+        assert_eq!(positions(&store), vec![0.0, 16.0, 32.0]);
+
+        drop(store);
+        std::fs::remove_file(&db_path).unwrap();
+    }
+
+    #[test]
+    fn test_two_stores_on_one_database() {
+        let db_path = std::env::temp_dir().join(format!("vtunnel_history_shared_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+
+        // Both hand out the same group ids, as two VTunnels running at once would.
+        let mut first = NavPointStore::open(&db_path).unwrap();
+        let mut second = NavPointStore::open(&db_path).unwrap();
+        let first_group = first.begin_edit_group(1);
+        let second_group = second.begin_edit_group(2);
+        assert_eq!(first_group.id, second_group.id);
+
+        first.add_nav_point(first_group, &Vector3::new(0.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+        second.add_nav_point(second_group, &Vector3::new(16.0, 0.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+
+        // Undoing player 1's group leaves player 2's alone.
+        assert_eq!(first.undo(1).unwrap().len(), 1);
+        assert_eq!(first.history(1, "").unwrap().redo_count, 1);
+        assert_eq!(second.history(2, "").unwrap().undo_count, 1);
+        assert_eq!(second.redo(2).unwrap().len(), 0);
+
+        drop((first, second));
+        std::fs::remove_file(&db_path).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use rusqlite::Connection;
+use crate::nav_builder::nav_builder::NavStoreError;
+
+/**
+ * Schema changes for the nav builder database, in order. Migration `n` (counting from 1) brings a
+ * database to `PRAGMA user_version = n`. Only ever add to the end of this list, databases out in
+ * the wild have already run everything before it.
+ */
+const MIGRATIONS: &[&str] = &[
+    // 1: Everything from before migrations. IF NOT EXISTS, since older databases already have some of it.
+    "CREATE TABLE IF NOT EXISTS nav_point (
+        id          INTEGER PRIMARY KEY,
+        position_x  REAL NOT NULL,
+        position_y  REAL NOT NULL,
+        position_z  REAL NOT NULL,
+        nav_type    INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS nav_edit (
+        id            INTEGER PRIMARY KEY,
+        group_id      INTEGER NOT NULL,
+        user_id       INTEGER NOT NULL,
+        operation     INTEGER NOT NULL,
+        nav_point_id  INTEGER NOT NULL,
+        position_x    REAL NOT NULL,
+        position_y    REAL NOT NULL,
+        position_z    REAL NOT NULL,
+        nav_type      INTEGER NOT NULL,
+        old_nav_type  INTEGER NOT NULL,
+        undone        INTEGER NOT NULL DEFAULT 0
+    );",
+    // 2: When each nav point was placed, in unix seconds. 0 for ones placed before this.
+    "ALTER TABLE nav_point ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;",
+    // 3: Maps and layers. Points from before this have no map until one adopts them, see `NavPointStore::adopt_unnamed`.
+    "ALTER TABLE nav_point ADD COLUMN map_name TEXT NOT NULL DEFAULT '';
+    ALTER TABLE nav_point ADD COLUMN layer TEXT NOT NULL DEFAULT 'default';
+    ALTER TABLE nav_edit ADD COLUMN map_name TEXT NOT NULL DEFAULT '';
+    ALTER TABLE nav_edit ADD COLUMN layer TEXT NOT NULL DEFAULT 'default';
+    CREATE INDEX nav_point_map_name ON nav_point (map_name);",
+];
+
+/**
+ * The version a database is at after running every migration.
+ */
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+pub fn database_version(db: &Connection) -> Result<u32, NavStoreError> {
+    Ok(db.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+/**
+ * Runs every migration the database hasn't had yet, each in its own transaction.
+ * Returns the version it started at.
+ */
+pub fn migrate(db: &mut Connection) -> Result<u32, NavStoreError> {
+    let start_version = database_version(db)?;
+    if start_version > latest_version() {
+        return Err(NavStoreError::NewerDatabase { version: start_version, supported: latest_version() });
+    }
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(start_version as usize) {
+        let version = index as u32 + 1;
+        let run = |db: &mut Connection| -> rusqlite::Result<()> {
+            let transaction = db.transaction()?;
+            transaction.execute_batch(sql)?;
+            transaction.pragma_update(None, "user_version", version)?;
+            transaction.commit()
+        };
+        run(db).map_err(|source| NavStoreError::Migration { version, source })?;
+    }
+
+    Ok(start_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::nav_builder::migrations::{database_version, latest_version, migrate};
+    use crate::nav_builder::nav_builder::NavStoreError;
+
+    #[test]
+    fn test_migrate_old_database() {
+        // A database from before migrations, with a nav point in it.
+        let mut db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE nav_point (
+                id          INTEGER PRIMARY KEY,
+                position_x  REAL NOT NULL,
+                position_y  REAL NOT NULL,
+                position_z  REAL NOT NULL,
+                nav_type    INTEGER NOT NULL
+            );
+            INSERT INTO nav_point (position_x, position_y, position_z, nav_type) VALUES (16, 32, 0, 1);"
+        ).unwrap();
+
+        assert_eq!(migrate(&mut db).unwrap(), 0);
+        assert_eq!(database_version(&db).unwrap(), latest_version());
+        let (nav_type, created_at, map_name, layer): (u8, i64, String, String) = db.query_row(
+            "SELECT nav_type, created_at, map_name, layer FROM nav_point", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        ).unwrap();
+        assert_eq!((nav_type, created_at, map_name.as_str(), layer.as_str()), (1, 0, "", "default"));
+        db.query_row("SELECT COUNT(*) FROM nav_edit", [], |row| row.get::<_, i64>(0)).unwrap();
+
+        // Nothing left to do the second time.
+        assert_eq!(migrate(&mut db).unwrap(), latest_version());
+
+        db.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        assert!(matches!(migrate(&mut db), Err(NavStoreError::NewerDatabase { .. })));
+    }
+}
@@ -1 +1,9 @@
-pub mod nav_builder;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod nav_builder;
+pub mod auto_fill;
+pub mod history;
+pub mod migrations;
+pub mod nav_graph;
+pub mod nav_index;
+pub mod nav_sync;
+pub mod nav_io;
@@ -1,19 +1,39 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use rusqlite::Connection;
 use tokio::sync::Mutex;
-use crate::game::commands::DrawDebugSphere;
+use tokio::task::JoinHandle;
+use crate::game::commands::{DrawDebugLine, DrawDebugSphere};
 use crate::game::elizabeth::Elizabeth;
-use crate::game::gadget::{GadgetProgram};
+use async_trait::async_trait;
+use crate::game::gadget::{GadgetProgram, GadgetState};
 use crate::game::player::{input_button, PlayerInput};
 use crate::game::trace::{BoxTrace, LineTrace};
+use crate::nav_builder::auto_fill::{AutoFill, AutoFillSettings};
+use crate::nav_builder::migrations;
+use crate::nav_builder::history::{EditGroup, EditJournal, NavEdit, NavEditHistory};
+use crate::nav_builder::nav_graph::NavGraph;
+use crate::nav_builder::nav_index::NavPointIndex;
+use crate::nav_builder::nav_sync::NavPointSync;
+use steamvr_nav_tools::nav_parser::nav::{NAV_MESH_CROUCH, NAV_MESH_JUMP, NAV_MESH_NO_LIZ};
 use bsi_tools_lib::math::Vector3;
 use crate::vtunnel::{VTunnelMessage, VTunnelMessageBatch, VTunnelSerializable};
-use crate::vtunnel_emitter::VTunnelEmitter;
+use crate::vtunnel_emitter::EmitterHandle;
 
 #[derive(Debug, Clone)]
 pub enum NavType {
     Walkable,
     Obstacle,
+    // Walkable, but only by jumping up to it.
+    Jump,
+    // Walkable, but only crouched.
+    Crouch,
+    // Walkable, but Elizabeth isn't allowed.
+    NoLiz,
 }
 
 impl NavType {
@@ -21,6 +41,9 @@ impl NavType {
         match self {
             NavType::Walkable => 0,
             NavType::Obstacle => 1,
+            NavType::Jump => 2,
+            NavType::Crouch => 3,
+            NavType::NoLiz => 4,
         }
     }
 
@@ -28,9 +51,79 @@ impl NavType {
         match value {
             0 => NavType::Walkable,
             1 => NavType::Obstacle,
+            2 => NavType::Jump,
+            3 => NavType::Crouch,
+            4 => NavType::NoLiz,
             _ => NavType::Obstacle,
         }
     }
+
+    /**
+     * Name used in exported files.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            NavType::Walkable => "walkable",
+            NavType::Obstacle => "obstacle",
+            NavType::Jump => "jump",
+            NavType::Crouch => "crouch",
+            NavType::NoLiz => "no_liz",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "walkable" => Some(NavType::Walkable),
+            "obstacle" => Some(NavType::Obstacle),
+            "jump" => Some(NavType::Jump),
+            "crouch" => Some(NavType::Crouch),
+            "no_liz" => Some(NavType::NoLiz),
+            _ => None,
+        }
+    }
+
+    /**
+     * Whether the point ends up in the nav mesh at all.
+     */
+    pub fn is_walkable(&self) -> bool {
+        !matches!(self, NavType::Obstacle)
+    }
+
+    /**
+     * Attributes of the nav areas baked from points of this type.
+     */
+    pub fn nav_area_attributes(&self) -> u32 {
+        match self {
+            NavType::Jump => NAV_MESH_JUMP,
+            NavType::Crouch => NAV_MESH_CROUCH,
+            NavType::NoLiz => NAV_MESH_NO_LIZ,
+            NavType::Walkable | NavType::Obstacle => 0,
+        }
+    }
+
+    /**
+     * What toggle mode turns this into. Jump, crouch and no-Elizabeth points are left alone,
+     * since toggling them back would have no way to know which one they were.
+     */
+    pub fn toggled(&self) -> Option<NavType> {
+        match self {
+            NavType::Obstacle => Some(NavType::Walkable),
+            NavType::Walkable => Some(NavType::Obstacle),
+            NavType::Jump | NavType::Crouch | NavType::NoLiz => None,
+        }
+    }
+
+    /**
+     * What mark mode turns this into, going through the special walkable types.
+     */
+    pub fn next_mark(&self) -> NavType {
+        match self {
+            NavType::Jump => NavType::Crouch,
+            NavType::Crouch => NavType::NoLiz,
+            NavType::NoLiz => NavType::Walkable,
+            NavType::Walkable | NavType::Obstacle => NavType::Jump,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,10 +131,14 @@ pub struct NavPoint {
     pub id: u64,
     pub position: Vector3,
     pub nav_type: NavType,
+    pub layer: String,
 }
 
 impl NavPoint {
-    fn to_add_vmsg(&self) -> VTunnelMessage {
+    /**
+     * Adds the nav point to the script's copy, or updates the one with the same id.
+     */
+    pub(crate) fn to_add_vmsg(&self) -> VTunnelMessage {
         let mut vmsg = VTunnelMessage::new("add_nav_point".to_string());
         vmsg.add_int(self.id as i64);
         vmsg.add_vector3(self.position.clone());
@@ -49,11 +146,17 @@ impl NavPoint {
         vmsg
     }
 
+    pub(crate) fn to_remove_vmsg(id: u64) -> VTunnelMessage {
+        let mut vmsg = VTunnelMessage::new("remove_nav_point".to_string());
+        vmsg.add_int(id as i64);
+        vmsg
+    }
+
     /**
      * Returns a VTunnelMessage to draw the nav point.
      * Smaller than calling the debug draws directly.
      */
-    fn to_draw_vmsg(&self) -> VTunnelMessage {
+    pub fn to_draw_vmsg(&self) -> VTunnelMessage {
         let mut vmsg = VTunnelMessage::new("draw_nav_point".to_string());
         vmsg.add_int(self.id as i64);
         vmsg.add_vector3(self.position.clone());
@@ -66,6 +169,10 @@ pub enum NavEditorMode {
     Add,
     Remove,
     Toggle,
+    Mark,
+    AutoFill,
+    Path,
+    Layer,
 }
 
 impl NavEditorMode {
@@ -74,6 +181,10 @@ impl NavEditorMode {
             NavEditorMode::Add => 0,
             NavEditorMode::Remove => 1,
             NavEditorMode::Toggle => 2,
+            NavEditorMode::AutoFill => 3,
+            NavEditorMode::Path => 4,
+            NavEditorMode::Mark => 5,
+            NavEditorMode::Layer => 6,
         }
     }
 
@@ -82,174 +193,557 @@ impl NavEditorMode {
             NavEditorMode::Add => Vector3::new(0.0, 255.0, 0.0),
             NavEditorMode::Remove => Vector3::new(255.0, 0.0, 0.0),
             NavEditorMode::Toggle => Vector3::new(0.0, 0.0, 255.0),
+            NavEditorMode::AutoFill => Vector3::new(255.0, 255.0, 0.0),
+            NavEditorMode::Path => Vector3::new(255.0, 0.0, 255.0),
+            NavEditorMode::Mark => Vector3::new(255.0, 255.0, 255.0),
+            NavEditorMode::Layer => Vector3::new(255.0, 128.0, 0.0),
         }
     }
 }
 
-pub struct NavBuilderProgram {
-    emitter: &'static VTunnelEmitter,
-    db: Mutex<Connection>,
+#[derive(Debug)]
+pub enum NavStoreError {
+    Sqlite(rusqlite::Error),
+    Migration { version: u32, source: rusqlite::Error },
+    // The database was made by a newer VTunnel, and might have changes this one doesn't know about.
+    NewerDatabase { version: u32, supported: u32 },
+    // No map was picked and the database has more than one.
+    AmbiguousMap { map_names: Vec<String> },
+    // Nav points from before maps can only be moved to a map that has none of its own.
+    MapNotEmpty { map_name: String },
+}
+
+impl fmt::Display for NavStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NavStoreError::Sqlite(source) => write!(f, "nav builder database error: {}", source),
+            NavStoreError::Migration { version, source } => write!(f, "failed to migrate nav builder database to version {}: {}", version, source),
+            NavStoreError::NewerDatabase { version, supported } => {
+                write!(f, "nav builder database is version {}, but this VTunnel only knows up to version {}", version, supported)
+            }
+            NavStoreError::AmbiguousMap { map_names } => write!(f, "nav builder database has nav points for several maps, pick one of {:?}", map_names),
+            NavStoreError::MapNotEmpty { map_name } => write!(f, "map \"{}\" already has nav points, can't move the ones from before maps to it", map_name),
+        }
+    }
+}
+
+impl std::error::Error for NavStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NavStoreError::Sqlite(source) => Some(source),
+            NavStoreError::Migration { source, .. } => Some(source),
+            NavStoreError::NewerDatabase { .. } | NavStoreError::AmbiguousMap { .. } | NavStoreError::MapNotEmpty { .. } => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for NavStoreError {
+    fn from(source: rusqlite::Error) -> Self {
+        NavStoreError::Sqlite(source)
+    }
+}
+
+/**
+ * Nav points loaded from the database, shared by every player's nav builder.
+ *
+ * The database holds nav points for any number of maps, but only the current map's are loaded.
+ * Within a map, nav points are split into named layers, and every layer can have a nav point on each grid spot.
+ */
+pub struct NavPointStore {
+    db: Connection,
+    map_name: String,
     nav_points: Vec<NavPoint>,
-    nav_points_set: HashSet<Vector3>,
-    active: bool,
-    is_first_click: bool,
-    nav_editor_mode: NavEditorMode,
+    nav_points_set: HashSet<(String, Vector3)>,
+    nav_point_index: NavPointIndex,
+    journal: EditJournal,
+    // Bumped whenever the loaded nav points change.
+    revision: u64,
 }
 
-impl NavBuilderProgram {
-    const NAV_POINT_GRID_SIZE: f64 = 16.0; // 1 foot in Source units
+impl NavPointStore {
+    pub const NAV_POINT_GRID_SIZE: f64 = 16.0; // 1 foot in Source units
+    pub const DEFAULT_LAYER: &'static str = "default";
 
-    pub async fn new(emitter: &'static VTunnelEmitter) -> Self {
+    /**
+     * Opens the database, creating it or bringing it up to date if needed.
+     * Starts on the unnamed map, until `switch_map` is called.
+     */
+    pub fn open(db_path: &Path) -> Result<Self, NavStoreError> {
+        let mut db = Connection::open(db_path)?;
+        let old_version = migrations::migrate(&mut db)?;
+        if old_version != migrations::latest_version() {
+            println!("Migrated nav builder database from version {} to {}", old_version, migrations::latest_version());
+        }
+
+        let journal = EditJournal::open(&db)?;
         let mut s = Self {
-            emitter,
-            db: Mutex::new(Connection::open("nav_builder.db").unwrap()),
+            db,
+            map_name: String::new(),
             nav_points: Vec::new(),
             nav_points_set: HashSet::new(),
-            active: false,
-            is_first_click: true,
-            nav_editor_mode: NavEditorMode::Add,
+            nav_point_index: NavPointIndex::new(),
+            journal,
+            revision: 0,
         };
 
-        s.load_db().await;
-        s
+        s.load_db()?;
+        Ok(s)
     }
 
-    async fn load_db(&mut self) {
-        let db = self.db.lock().await;
-        db.execute(
-            "CREATE TABLE IF NOT EXISTS nav_point (
-            id    INTEGER PRIMARY KEY,
-            position_x  REAL NOT NULL,
-            position_y  REAL NOT NULL,
-            position_z  REAL NOT NULL,
-            nav_type    INTEGER NOT NULL
-        )", ()).unwrap();
+    /**
+     * Opens the database on a map. Without one, it opens the only map that has nav points.
+     */
+    pub fn open_map(db_path: &Path, map_name: Option<&str>) -> Result<Self, NavStoreError> {
+        let mut store = Self::open(db_path)?;
+        let map_name = match map_name {
+            Some(map_name) => map_name.to_string(),
+            None => {
+                let mut map_names = store.map_names()?;
+                if map_names.len() > 1 {
+                    return Err(NavStoreError::AmbiguousMap { map_names });
+                }
+                map_names.pop().unwrap_or_default()
+            }
+        };
+
+        store.switch_map(&map_name)?;
+        Ok(store)
+    }
 
-        let mut nav_point_query = db.prepare("SELECT id, position_x, position_Y, position_z, nav_type FROM nav_point").unwrap();
-        let nav_point_iter = nav_point_query.query_map([], |row| {
+    fn load_db(&mut self) -> Result<(), NavStoreError> {
+        let mut nav_point_query = self.db.prepare("SELECT id, position_x, position_y, position_z, nav_type, layer FROM nav_point WHERE map_name = ?1")?;
+        let nav_point_iter = nav_point_query.query_map([&self.map_name], |row| {
             Ok(NavPoint {
                 id: row.get(0)?,
                 position: Vector3::new(row.get(1)?, row.get(2)?, row.get(3)?),
                 nav_type: NavType::from_int(row.get(4)?),
+                layer: row.get(5)?,
             })
-        }).unwrap();
-
-        for nav_point in nav_point_iter {
-            if let Ok(np) = nav_point {
-                let mut unique_position = self.round_to_grid(&np.position);
-                unique_position.z = unique_position.z.ceil();
-                self.nav_points_set.insert(unique_position);
-                self.nav_points.push(np);
-            }
+        })?;
+
+        for np in nav_point_iter {
+            let np = np?;
+            self.nav_points_set.insert(Self::unique_key(&np));
+            self.nav_point_index.insert(np.clone());
+            self.nav_points.push(np);
         }
+        Ok(())
     }
 
-    fn get_nearest_nav_point(&self, position: &Vector3) -> Option<&NavPoint> {
-        let mut nearest_nav_point = None;
-        let mut nearest_distance = f64::MAX;
+    pub fn map_name(&self) -> &str {
+        &self.map_name
+    }
 
-        for nav_point in &self.nav_points {
-            let distance = nav_point.position.distance(&position);
-            if distance < nearest_distance {
-                nearest_distance = distance;
-                nearest_nav_point = Some(nav_point);
-            }
-        }
+    /**
+     * Every map in the database that has nav points.
+     */
+    pub fn map_names(&self) -> Result<Vec<String>, NavStoreError> {
+        let mut query = self.db.prepare("SELECT DISTINCT map_name FROM nav_point ORDER BY map_name")?;
+        let map_names = query.query_map([], |row| row.get(0))?;
+        Ok(map_names.collect::<Result<_, _>>()?)
+    }
 
-        // Don't return nav points that are too far away.
-        if nearest_distance > Self::NAV_POINT_GRID_SIZE {
-            return None;
+    /**
+     * Loads another map's nav points in place of the current ones.
+     * Nav points from before maps were tracked stay on the unnamed map until `adopt_unnamed` moves them.
+     */
+    pub fn switch_map(&mut self, map_name: &str) -> Result<(), NavStoreError> {
+        if map_name == self.map_name {
+            return Ok(());
         }
 
-        nearest_nav_point
+        self.map_name = map_name.to_string();
+        self.reload()
     }
 
-    async fn upload_nav_points(&self) {
-        let mut vmsg_batch = VTunnelMessageBatch::new();
-        vmsg_batch.set_batch_size(15);
+    fn reload(&mut self) -> Result<(), NavStoreError> {
+        self.nav_points.clear();
+        self.nav_points_set.clear();
+        self.nav_point_index.clear();
+        self.revision += 1;
+        self.load_db()
+    }
 
-        let clear_vmsg = VTunnelMessage::new("clear_nav_points".to_string());
-        vmsg_batch.add_message(clear_vmsg);
+    /**
+     * Moves the nav points from before maps were tracked, and their history, to a map that has none of its own yet.
+     * Returns how many nav points were moved.
+     */
+    pub fn adopt_unnamed(&mut self, map_name: &str) -> Result<usize, NavStoreError> {
+        let transaction = self.db.transaction()?;
+        let has_own: bool = transaction.query_row("SELECT EXISTS (SELECT 1 FROM nav_point WHERE map_name = ?1)", [map_name], |row| row.get(0))?;
+        if has_own {
+            return Err(NavStoreError::MapNotEmpty { map_name: map_name.to_string() });
+        }
+        let adopted = transaction.execute("UPDATE nav_point SET map_name = ?1 WHERE map_name = ''", [map_name])?;
+        transaction.execute("UPDATE nav_edit SET map_name = ?1 WHERE map_name = ''", [map_name])?;
+        transaction.commit()?;
 
-        for nav_point in &self.nav_points {
-            vmsg_batch.add_message(nav_point.to_add_vmsg());
+        if self.map_name == map_name || self.map_name.is_empty() {
+            self.reload()?;
         }
+        Ok(adopted)
+    }
 
-        self.emitter.send_batch(vmsg_batch).await;
+    pub fn nav_points(&self) -> &[NavPoint] {
+        &self.nav_points
+    }
+
+    /**
+     * Changes whenever the current map's nav points do, so anything built from them knows when to rebuild.
+     */
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /**
+     * Every layer on the current map that has nav points, and the default layer, sorted by name.
+     */
+    pub fn layers(&self) -> Vec<String> {
+        let mut layers: Vec<String> = self.nav_points.iter().map(|nav_point| nav_point.layer.clone()).collect();
+        layers.push(Self::DEFAULT_LAYER.to_string());
+        layers.sort();
+        layers.dedup();
+        layers
+    }
+
+    /**
+     * Nearest nav point on the layer within a grid cell of the position.
+     */
+    pub fn get_nearest_nav_point(&self, position: &Vector3, layer: &str) -> Option<&NavPoint> {
+        self.nav_point_index.within_radius(position, NavPointStore::NAV_POINT_GRID_SIZE)
+            .into_iter()
+            .find(|nav_point| nav_point.layer == layer)
     }
 
-    async fn add_nav_point(&mut self, position: &Vector3, nav_type: NavType) -> Option<NavPoint> {
-        let position = self.round_to_grid(position);
+    /**
+     * Nav points within the radius, closest first.
+     */
+    pub fn nav_points_within_radius(&self, position: &Vector3, radius: f64) -> Vec<&NavPoint> {
+        self.nav_point_index.within_radius(position, radius)
+    }
 
-        let mut unique_position = position.clone();
+    pub fn nav_points_within_bounds(&self, mins: &Vector3, maxs: &Vector3) -> Vec<&NavPoint> {
+        self.nav_point_index.within_bounds(mins, maxs)
+    }
+
+    fn unique_key(nav_point: &NavPoint) -> (String, Vector3) {
+        let mut unique_position = round_to_grid(&nav_point.position);
         unique_position.z = unique_position.z.ceil();
-        if self.nav_points_set.contains(&unique_position) {
-            return None;
+        (nav_point.layer.clone(), unique_position)
+    }
+
+    pub fn begin_edit_group(&mut self, user_id: u64) -> EditGroup {
+        self.journal.begin_group(user_id)
+    }
+
+    /**
+     * Adds a nav point to a layer, snapped to the grid. None if the layer already has one there.
+     */
+    pub fn add_nav_point(&mut self, group: EditGroup, position: &Vector3, nav_type: NavType, layer: &str) -> Result<Option<NavPoint>, NavStoreError> {
+        let nav_point = NavPoint { id: 0, position: round_to_grid(position), nav_type, layer: layer.to_string() };
+        self.insert_nav_point(nav_point, Some(group))
+    }
+
+    pub fn remove_nav_point(&mut self, group: EditGroup, nav_point_id: u64) -> Result<Option<NavPoint>, NavStoreError> {
+        self.delete_nav_point(nav_point_id, Some(group))
+    }
+
+    pub fn set_nav_type(&mut self, group: EditGroup, nav_point_id: u64, nav_type: NavType) -> Result<Option<NavPoint>, NavStoreError> {
+        if self.update_nav_type(nav_point_id, nav_type, Some(group))?.is_none() {
+            return Ok(None);
         }
+        Ok(self.nav_points.iter().find(|nav_point| nav_point.id == nav_point_id).cloned())
+    }
 
-        let mut nav_point = NavPoint { id: 0, position, nav_type };
-        let db = self.db.lock().await;
-        db.execute(
-            "INSERT INTO nav_point (position_x, position_y, position_z, nav_type) VALUES (?1, ?2, ?3, ?4)",
-            (&nav_point.position.x, &nav_point.position.y, &nav_point.position.z, nav_point.nav_type.to_int()),
-        ).unwrap();
-        nav_point.id = db.last_insert_rowid() as u64;
+    /**
+     * Undoes the player's latest edit group on the current map. Returns the edits that were applied to do it.
+     */
+    pub fn undo(&mut self, user_id: u64) -> Result<Vec<NavEdit>, NavStoreError> {
+        let edits = self.journal.undo(&self.db, &self.map_name, user_id)?;
+        self.apply_edits(edits)
+    }
 
-        self.nav_points_set.insert(unique_position);
-        self.nav_points.push(nav_point.clone());
+    /**
+     * Redoes the player's most recently undone edit group on the current map. Returns the edits that were applied to do it.
+     */
+    pub fn redo(&mut self, user_id: u64) -> Result<Vec<NavEdit>, NavStoreError> {
+        let edits = self.journal.redo(&self.db, &self.map_name, user_id)?;
+        self.apply_edits(edits)
+    }
 
-        Some(nav_point)
+    pub fn history(&self, user_id: u64, last_action: &str) -> Result<NavEditHistory, NavStoreError> {
+        let (undo_count, redo_count) = self.journal.counts(&self.db, &self.map_name, user_id)?;
+        Ok(NavEditHistory { user_id, undo_count, redo_count, last_action: last_action.to_string() })
     }
 
-    async fn remove_nav_point(&mut self, nav_point_id: u64) -> Option<NavPoint> {
-        let mut remove_index = None;
-        for (index, nav_point) in self.nav_points.iter().enumerate() {
-            if nav_point.id == nav_point_id {
-                remove_index = Some(index);
-                break;
+    fn apply_edits(&mut self, edits: Vec<NavEdit>) -> Result<Vec<NavEdit>, NavStoreError> {
+        let mut applied = Vec::new();
+        for edit in edits {
+            if self.apply_edit(&edit)? {
+                applied.push(edit);
             }
         }
+        Ok(applied)
+    }
 
-        if remove_index.is_none() {
-            return None;
+    /**
+     * Applies an edit from the journal without recording it again.
+     * Edits that no longer fit, like re-adding a point where another one has been added since, are skipped.
+     */
+    fn apply_edit(&mut self, edit: &NavEdit) -> Result<bool, NavStoreError> {
+        Ok(match edit {
+            NavEdit::Add(nav_point) => self.insert_nav_point(nav_point.clone(), None)?.is_some(),
+            NavEdit::Remove(nav_point) => {
+                // Ids can be reused once a point is gone, make sure it's still the same point.
+                let unique_key = Self::unique_key(nav_point);
+                let is_same_point = self.nav_points.iter().any(|other| other.id == nav_point.id && Self::unique_key(other) == unique_key);
+                is_same_point && self.delete_nav_point(nav_point.id, None)?.is_some()
+            }
+            NavEdit::SetType { nav_point, .. } => self.update_nav_type(nav_point.id, nav_point.nav_type.clone(), None)?.is_some(),
+        })
+    }
+
+    /**
+     * Inserts a nav point into the current map, keeping its id unless it's 0. None if the spot or the id is taken.
+     * With a group, the edit is journaled in the same transaction.
+     */
+    fn insert_nav_point(&mut self, mut nav_point: NavPoint, group: Option<EditGroup>) -> Result<Option<NavPoint>, NavStoreError> {
+        let unique_key = Self::unique_key(&nav_point);
+        if self.nav_points_set.contains(&unique_key) {
+            return Ok(None);
         }
 
-        let remove_index = remove_index.unwrap();
-        let removed_nav_point = self.nav_points.remove(remove_index);
+        // The id can belong to a point on another map, which isn't loaded.
+        let id = (nav_point.id != 0).then_some(nav_point.id);
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let transaction = self.db.transaction()?;
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO nav_point (id, position_x, position_y, position_z, nav_type, created_at, map_name, layer) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (id, &nav_point.position.x, &nav_point.position.y, &nav_point.position.z, nav_point.nav_type.to_int(), created_at, &self.map_name, &nav_point.layer),
+        )?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        nav_point.id = transaction.last_insert_rowid() as u64;
+        if let Some(group) = group {
+            self.journal.record(&transaction, &self.map_name, group, &NavEdit::Add(nav_point.clone()))?;
+        }
+        transaction.commit()?;
 
-        let mut unique_position = self.round_to_grid(&removed_nav_point.position);
-        unique_position.z = unique_position.z.ceil();
-        self.nav_points_set.remove(&unique_position);
+        self.nav_points_set.insert(unique_key);
+        self.nav_point_index.insert(nav_point.clone());
+        self.nav_points.push(nav_point.clone());
+        self.revision += 1;
+
+        Ok(Some(nav_point))
+    }
 
-        let db = self.db.lock().await;
-        db.execute(
+    fn delete_nav_point(&mut self, nav_point_id: u64, group: Option<EditGroup>) -> Result<Option<NavPoint>, NavStoreError> {
+        let Some(remove_index) = self.nav_points.iter().position(|nav_point| nav_point.id == nav_point_id) else {
+            return Ok(None);
+        };
+
+        let transaction = self.db.transaction()?;
+        transaction.execute(
             "DELETE FROM nav_point WHERE id = ?1",
-            [removed_nav_point.id],
-        ).unwrap();
+            [nav_point_id],
+        )?;
+        if let Some(group) = group {
+            self.journal.record(&transaction, &self.map_name, group, &NavEdit::Remove(self.nav_points[remove_index].clone()))?;
+        }
+        transaction.commit()?;
 
-        Some(removed_nav_point)
+        let removed_nav_point = self.nav_points.remove(remove_index);
+        self.nav_points_set.remove(&Self::unique_key(&removed_nav_point));
+        self.nav_point_index.remove(&removed_nav_point);
+        self.revision += 1;
+
+        Ok(Some(removed_nav_point))
+    }
+
+    /**
+     * Returns the nav type it had before.
+     */
+    fn update_nav_type(&mut self, nav_point_id: u64, nav_type: NavType, group: Option<EditGroup>) -> Result<Option<NavType>, NavStoreError> {
+        let Some(nav_point) = self.nav_points.iter_mut().find(|nav_point| nav_point.id == nav_point_id) else {
+            return Ok(None);
+        };
+        let transaction = self.db.transaction()?;
+        transaction.execute(
+            "UPDATE nav_point SET nav_type = ?1 WHERE id = ?2",
+            (nav_type.to_int(), nav_point_id),
+        )?;
+        if let Some(group) = group {
+            let edit = NavEdit::SetType { nav_point: NavPoint { nav_type: nav_type.clone(), ..nav_point.clone() }, old_nav_type: nav_point.nav_type.clone() };
+            self.journal.record(&transaction, &self.map_name, group, &edit)?;
+        }
+        transaction.commit()?;
+
+        self.nav_point_index.remove(nav_point);
+        let old_nav_type = std::mem::replace(&mut nav_point.nav_type, nav_type);
+        self.nav_point_index.insert(nav_point.clone());
+        self.revision += 1;
+        Ok(Some(old_nav_type))
+    }
+}
+
+pub fn round_to_grid(position: &Vector3) -> Vector3 {
+    Vector3::new(
+        (position.x / NavPointStore::NAV_POINT_GRID_SIZE).round() * NavPointStore::NAV_POINT_GRID_SIZE,
+        (position.y / NavPointStore::NAV_POINT_GRID_SIZE).round() * NavPointStore::NAV_POINT_GRID_SIZE,
+        position.z,
+    )
+}
+
+/**
+ * The layer after `active` in `layers`. Going past the last one starts a new, empty layer,
+ * which only gets saved once it has nav points.
+ */
+pub fn next_layer(layers: &[String], active: &str) -> String {
+    match layers.iter().position(|layer| layer == active) {
+        Some(index) if index + 1 < layers.len() => layers[index + 1].clone(),
+        Some(_) => (1..).map(|number| format!("layer_{}", number)).find(|name| !layers.contains(name)).unwrap(),
+        None => layers.first().cloned().unwrap_or_else(|| NavPointStore::DEFAULT_LAYER.to_string()),
+    }
+}
+
+/**
+ * Sent to the script when a player switches layers or hides one, so the gadget can show it.
+ */
+#[derive(Debug, Default, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "nav_layer_state", crate = "crate")]
+pub struct NavLayerState {
+    pub user_id: u64,
+    pub map_name: String,
+    // Layer new nav points go into.
+    pub active_layer: String,
+    pub active_layer_hidden: bool,
+    pub hidden_count: u64,
+}
+
+/**
+ * Per-player nav builder. The nav points themselves live in a shared `NavPointStore`.
+ */
+pub struct NavBuilderProgram {
+    emitter: EmitterHandle,
+    store: Arc<Mutex<NavPointStore>>,
+    user_id: u64,
+    fill_settings: AutoFillSettings,
+    // Set while an auto fill is running in the background.
+    fill_cancelled: Option<Arc<AtomicBool>>,
+    fill_task: Option<JoinHandle<()>>,
+    active: bool,
+    is_first_click: bool,
+    nav_editor_mode: NavEditorMode,
+    // First end of the path in path mode, the next click finds a path to it.
+    path_start: Option<NavPoint>,
+    // Set when menu was held for a redo or to hide a layer, so letting go of it doesn't sync.
+    menu_used_as_modifier: bool,
+    active_layer: String,
+    // Layers this player doesn't want drawn. They're still synced, the script's copy is shared by every player.
+    hidden_layers: HashSet<String>,
+    // Graph of the visible nav points for path mode, and the store revision it was built from.
+    path_graph: Option<(u64, NavGraph)>,
+    // What the script has of the nav points, shared by every player's nav builder.
+    nav_sync: Arc<Mutex<NavPointSync>>,
+}
+
+impl NavBuilderProgram {
+    pub fn new(emitter: EmitterHandle, store: Arc<Mutex<NavPointStore>>, nav_sync: Arc<Mutex<NavPointSync>>, user_id: u64, fill_settings: AutoFillSettings) -> Self {
+        Self {
+            emitter,
+            store,
+            user_id,
+            fill_settings,
+            fill_cancelled: None,
+            fill_task: None,
+            active: false,
+            is_first_click: true,
+            nav_editor_mode: NavEditorMode::Add,
+            path_start: None,
+            menu_used_as_modifier: false,
+            active_layer: NavPointStore::DEFAULT_LAYER.to_string(),
+            hidden_layers: HashSet::new(),
+            path_graph: None,
+            nav_sync,
+        }
     }
 
-    fn round_to_grid(&self, position: &Vector3) -> Vector3 {
-        Vector3::new(
-            (position.x / Self::NAV_POINT_GRID_SIZE).round() * Self::NAV_POINT_GRID_SIZE,
-            (position.y / Self::NAV_POINT_GRID_SIZE).round() * Self::NAV_POINT_GRID_SIZE,
-            position.z,
-        )
+    fn is_visible(&self, nav_point: &NavPoint) -> bool {
+        !self.hidden_layers.contains(&nav_point.layer)
     }
 
-    pub async fn emit_state(&self) {
-        self.emitter.send::<NavBuilderProgram>(&self).await;
+    fn is_filling(&self) -> bool {
+        self.fill_task.as_ref().is_some_and(|task| !task.is_finished())
     }
 
-    pub async fn cycle_mode(&mut self) {
+    /**
+     * Starts flood filling nav points from the given position in the background.
+     */
+    fn start_fill(&mut self, position: &Vector3) {
+        let auto_fill = AutoFill::new(self.emitter.clone(), self.store.clone(), self.fill_settings.clone(), self.user_id, &self.active_layer);
+        let position = position.clone();
+        let (emitter, store, nav_sync) = (self.emitter.clone(), self.store.clone(), self.nav_sync.clone());
+        self.fill_cancelled = Some(auto_fill.cancel_flag());
+        self.fill_task = Some(tokio::spawn(async move {
+            match auto_fill.run(&position).await {
+                Ok(progress) if progress.cancelled => println!("Auto fill cancelled after adding {} nav points", progress.added),
+                Ok(progress) => println!("Auto fill added {} nav points", progress.added),
+                Err(err) => eprintln!("Auto fill failed: {}", err),
+            }
+            // Even a failed or cancelled fill may have added some. It's running on its own, so it can wait on the check too.
+            Self::verify_store_nav_points(&emitter, &store, &nav_sync).await;
+        }));
+    }
+
+    /**
+     * Stops a running fill once it finishes the ring it's working on.
+     */
+    fn cancel_fill(&mut self) {
+        if let Some(cancelled) = self.fill_cancelled.take() {
+            cancelled.store(true, Ordering::SeqCst);
+        }
+        self.fill_task = None;
+    }
+
+    /**
+     * Sends the script what changed in the current map's nav points, on every layer, without waiting on it.
+     * See `NavPointSync`.
+     */
+    async fn send_nav_point_changes(&self) {
+        let nav_points = self.store.lock().await.nav_points().to_vec();
+        self.nav_sync.lock().await.send_changes(&self.emitter, nav_points).await;
+    }
+
+    /**
+     * Checks the script's copy of the nav points and sends them all again if it's off.
+     * Waits on the script, so it's kept off the editing path.
+     */
+    async fn verify_nav_points(&self) {
+        Self::verify_store_nav_points(&self.emitter, &self.store, &self.nav_sync).await;
+    }
+
+    async fn verify_store_nav_points(emitter: &EmitterHandle, store: &Mutex<NavPointStore>, nav_sync: &Mutex<NavPointSync>) {
+        let nav_points = store.lock().await.nav_points().to_vec();
+
+        match nav_sync.lock().await.verify(emitter, nav_points).await {
+            Ok(summary) if summary.full_resync => println!("NavBuilder resynced {} nav points", summary.changed),
+            Ok(_) => {}
+            Err(e) => eprintln!("NavBuilder failed to sync nav points: {}", e),
+        }
+    }
+
+    pub fn cycle_mode(&mut self) {
         self.nav_editor_mode = match self.nav_editor_mode {
             NavEditorMode::Add => NavEditorMode::Remove,
             NavEditorMode::Remove => NavEditorMode::Toggle,
-            NavEditorMode::Toggle => NavEditorMode::Add,
+            NavEditorMode::Toggle => NavEditorMode::Mark,
+            NavEditorMode::Mark => NavEditorMode::AutoFill,
+            NavEditorMode::AutoFill => NavEditorMode::Path,
+            NavEditorMode::Path => NavEditorMode::Layer,
+            NavEditorMode::Layer => NavEditorMode::Add,
         };
-
-        self.emit_state().await;
+        self.path_start = None;
     }
 
     /**
@@ -257,10 +751,10 @@ impl NavBuilderProgram {
      * Returns boolean if the position has space, and the position of the space if valid.
      */
     async fn position_has_space(&self, position: &Vector3) -> (bool, Option<Vector3>) {
-        let position = self.round_to_grid(&position);
+        let position = round_to_grid(position);
 
         let floor_trace = LineTrace::new(position.add(&Vector3::new(0.0, 0.0, 10.0)), position.sub(&Vector3::new(0.0, 0.0, 1000.0)));
-        let floor_trace_result = floor_trace.run(self.emitter).await;
+        let floor_trace_result = floor_trace.run(&self.emitter).await;
         if floor_trace_result.is_err() {
             return (false, None);
         }
@@ -270,12 +764,12 @@ impl NavBuilderProgram {
         }
 
         let space_trace = BoxTrace::new(
-            floor_trace_result.hit_position.add(&Vector3::new(0.0, 0.0, Self::NAV_POINT_GRID_SIZE)),
+            floor_trace_result.hit_position.add(&Vector3::new(0.0, 0.0, NavPointStore::NAV_POINT_GRID_SIZE)),
             floor_trace_result.hit_position.add(&Vector3::new(0.0, 0.0, 66.0)),
             Elizabeth::MINS,
             Elizabeth::MAXS,
         );
-        let space_trace_result = space_trace.run(self.emitter).await;
+        let space_trace_result = space_trace.run(&self.emitter).await;
         if space_trace_result.is_err() {
             return (false, None);
         }
@@ -285,31 +779,180 @@ impl NavBuilderProgram {
         let has_space = !space_trace_result.hit;
 
         if is_floor && has_space {
-            (true, Some(self.round_to_grid(&floor_trace_result.hit_position)))
+            (true, Some(round_to_grid(&floor_trace_result.hit_position)))
         } else {
-            (false, Some(self.round_to_grid(&floor_trace_result.hit_position)))
+            (false, Some(round_to_grid(&floor_trace_result.hit_position)))
         }
     }
 
     const POINT_RENDER_LIMIT: usize = 100;
     async fn render_nearby_nav_points(&mut self, position: Vector3) {
         let mut vmsg_batch = VTunnelMessageBatch::new();
-        vmsg_batch.set_batch_size(15);
-
-        let mut render_count = 0;
-        let _ = &self.nav_points.sort_by(|a, b| a.position.distance(&position).partial_cmp(&b.position.distance(&position)).unwrap());
-        for nav_point in &self.nav_points {
-            if nav_point.position.distance(&position) < 1000.0 {
-                vmsg_batch.add_message(nav_point.to_draw_vmsg());
-                render_count += 1;
+
+        let store = self.store.lock().await;
+        for nav_point in store.nav_points_within_radius(&position, 1000.0).into_iter().filter(|nav_point| self.is_visible(nav_point)).take(Self::POINT_RENDER_LIMIT) {
+            vmsg_batch.add_message(nav_point.to_draw_vmsg());
+        }
+        drop(store);
+
+        self.emitter.send_batch(vmsg_batch).await;
+    }
+
+    /**
+     * Picks the start of a path on the first click, and draws the path to it on the second.
+     */
+    async fn on_path_clicked(&mut self, position: &Vector3) {
+        // Only rebuilt once the nav points or the hidden layers have changed.
+        let store = self.store.lock().await;
+        if !matches!(&self.path_graph, Some((revision, _)) if *revision == store.revision()) {
+            let visible: Vec<NavPoint> = store.nav_points().iter().filter(|nav_point| !self.hidden_layers.contains(&nav_point.layer)).cloned().collect();
+            self.path_graph = Some((store.revision(), NavGraph::build(&visible, NavGraph::DEFAULT_STEP_HEIGHT)));
+        }
+        drop(store);
+        let Some((_, graph)) = &self.path_graph else {
+            return;
+        };
+
+        let Some(clicked) = graph.get_nearest_nav_point(position).cloned() else {
+            println!("No walkable nav point near {:?}", position);
+            return;
+        };
+
+        let Some(start) = self.path_start.take() else {
+            let draw_sphere = DrawDebugSphere {
+                position: clicked.position.clone(),
+                color: NavEditorMode::Path.to_color(),
+                color_alpha: 1.0,
+                radius: 8.0,
+                z_test: true,
+                duration_seconds: 10.0,
+            };
+            self.emitter.send::<DrawDebugSphere>(&draw_sphere).await;
+            self.path_start = Some(clicked);
+            return;
+        };
+
+        let Some(path) = graph.find_path(start.id, clicked.id) else {
+            println!("No path from nav point {} to {}", start.id, clicked.id);
+            let draw_sphere = DrawDebugSphere {
+                position: clicked.position.clone(),
+                color: Vector3::new(255.0, 0.0, 0.0),
+                color_alpha: 1.0,
+                radius: 8.0,
+                z_test: true,
+                duration_seconds: 10.0,
+            };
+            self.emitter.send::<DrawDebugSphere>(&draw_sphere).await;
+            return;
+        };
+
+        println!("Path from nav point {} to {} is {} nav points long", start.id, clicked.id, path.len());
+        let mut vmsg_batch = VTunnelMessageBatch::new();
+        for pair in path.windows(2) {
+            let draw_line = DrawDebugLine {
+                start_position: pair[0].position.add(&Vector3::new(0.0, 0.0, 4.0)),
+                end_position: pair[1].position.add(&Vector3::new(0.0, 0.0, 4.0)),
+                color: NavEditorMode::Path.to_color(),
+                z_test: true,
+                duration_seconds: 10.0,
+            };
+            vmsg_batch.add_message(draw_line.serialize());
+        }
+        self.emitter.send_batch(vmsg_batch).await;
+    }
+
+    fn removed_draw_vmsg(nav_point: &NavPoint) -> VTunnelMessage {
+        DrawDebugSphere {
+            position: nav_point.position.clone(),
+            color: Vector3::new(255.0, 0.0, 0.0),
+            color_alpha: 1.0,
+            radius: 5.0,
+            z_test: true,
+            duration_seconds: 10.0,
+        }.serialize()
+    }
+
+    async fn send_history(&self, last_action: &str) {
+        let history = self.store.lock().await.history(self.user_id, last_action);
+        match history {
+            Ok(history) => self.emitter.send(&history).await,
+            Err(e) => eprintln!("NavBuilder failed to read edit history: {}", e),
+        }
+    }
+
+    async fn send_layer_state(&self) {
+        let map_name = self.store.lock().await.map_name().to_string();
+        self.emitter.send(&NavLayerState {
+            user_id: self.user_id,
+            map_name,
+            active_layer: self.active_layer.clone(),
+            active_layer_hidden: self.hidden_layers.contains(&self.active_layer),
+            hidden_count: self.hidden_layers.len() as u64,
+        }).await;
+    }
+
+    /**
+     * Undoes or redoes this player's last edit group, and draws what changed.
+     */
+    async fn step_history(&mut self, redo: bool) {
+        let edits = {
+            let mut store = self.store.lock().await;
+            if redo { store.redo(self.user_id) } else { store.undo(self.user_id) }
+        };
+        let action = if redo { "redo" } else { "undo" };
+        let edits = match edits {
+            Ok(edits) => edits,
+            Err(e) => {
+                eprintln!("NavBuilder {} failed: {}", action, e);
+                return;
             }
+        };
 
-            if render_count >= Self::POINT_RENDER_LIMIT {
-                break;
+        let mut vmsg_batch = VTunnelMessageBatch::new();
+        for edit in &edits {
+            match edit {
+                NavEdit::Remove(nav_point) => vmsg_batch.add_message(Self::removed_draw_vmsg(nav_point)),
+                _ => vmsg_batch.add_message(edit.nav_point().to_draw_vmsg()),
             }
         }
+        if !vmsg_batch.messages.is_empty() {
+            self.emitter.send_batch(vmsg_batch).await;
+        }
 
-        self.emitter.send_batch(vmsg_batch).await;
+        println!("NavBuilder {} changed {} nav points", action, edits.len());
+        self.send_history(&format!("{} {}", action, edits.len())).await;
+        if !edits.is_empty() {
+            self.send_nav_point_changes().await;
+        }
+    }
+
+    /**
+     * Changes the type of the active layer's nav point closest to the position, as one edit.
+     */
+    async fn change_nearest_nav_type(&mut self, position: &Vector3, change: fn(&NavType) -> Option<NavType>) {
+        let nav_point = {
+            let mut store = self.store.lock().await;
+            let Some(nearest) = store.get_nearest_nav_point(position, &self.active_layer).cloned() else {
+                return;
+            };
+            let Some(nav_type) = change(&nearest.nav_type) else {
+                return;
+            };
+            let group = store.begin_edit_group(self.user_id);
+            store.set_nav_type(group, nearest.id, nav_type)
+        };
+        let nav_point = match nav_point {
+            Ok(Some(nav_point)) => nav_point,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("NavBuilder failed to change nav type: {}", e);
+                return;
+            }
+        };
+
+        self.emitter.send_vmsg(nav_point.to_draw_vmsg()).await;
+        self.send_history(&format!("{:?}", nav_point.nav_type)).await;
+        self.send_nav_point_changes().await;
     }
 
     async fn on_trigger_clicked(&mut self, input: &PlayerInput) {
@@ -320,64 +963,120 @@ impl NavBuilderProgram {
                     return;
                 }
 
-                let nav_point: Option<NavPoint>;
-                if has_space {
-                    nav_point = self.add_nav_point(&position.clone().unwrap(), NavType::Walkable).await;
-                } else {
-                    nav_point = self.add_nav_point(&position.clone().unwrap(), NavType::Obstacle).await;
+                let nav_type = if has_space { NavType::Walkable } else { NavType::Obstacle };
+                let nav_point = {
+                    let mut store = self.store.lock().await;
+                    let group = store.begin_edit_group(self.user_id);
+                    store.add_nav_point(group, &position.unwrap(), nav_type, &self.active_layer)
+                };
+                let nav_point = match nav_point {
+                    Ok(Some(nav_point)) => nav_point,
+                    Ok(None) => return,
+                    Err(e) => {
+                        eprintln!("NavBuilder failed to add nav point: {}", e);
+                        return;
+                    }
                 };
 
-                if nav_point.is_none() {
-                    return;
-                }
-
-                self.emitter.send_vmsg(nav_point.unwrap().to_draw_vmsg()).await;
+                self.emitter.send_vmsg(nav_point.to_draw_vmsg()).await;
+                self.send_history("added 1").await;
+                self.send_nav_point_changes().await;
             }
             NavEditorMode::Remove => {
-                let nav_point = self.get_nearest_nav_point(&input.trace_position);
-                if nav_point.is_none() {
-                    return;
-                }
-
-                let removed_nav_point = self.remove_nav_point(nav_point.unwrap().id).await;
-                if removed_nav_point.is_none() {
-                    return;
-                }
-
-                let draw_sphere = DrawDebugSphere {
-                    position: removed_nav_point.unwrap().position.clone(),
-                    color: Vector3::new(255.0, 0.0, 0.0),
-                    color_alpha: 1.0,
-                    radius: 5.0,
-                    z_test: true,
-                    duration_seconds: 10.0,
+                let removed_nav_point = {
+                    let mut store = self.store.lock().await;
+                    let Some(nav_point_id) = store.get_nearest_nav_point(&input.trace_position, &self.active_layer).map(|nav_point| nav_point.id) else {
+                        return;
+                    };
+                    let group = store.begin_edit_group(self.user_id);
+                    store.remove_nav_point(group, nav_point_id)
+                };
+                let removed_nav_point = match removed_nav_point {
+                    Ok(Some(removed_nav_point)) => removed_nav_point,
+                    Ok(None) => return,
+                    Err(e) => {
+                        eprintln!("NavBuilder failed to remove nav point: {}", e);
+                        return;
+                    }
                 };
 
-                self.emitter.send::<DrawDebugSphere>(&draw_sphere).await;
+                self.emitter.send_vmsg(Self::removed_draw_vmsg(&removed_nav_point)).await;
+                self.send_history("removed 1").await;
+                self.send_nav_point_changes().await;
+            }
+            NavEditorMode::AutoFill => {
+                // Clicking again while it's running stops it.
+                if self.is_filling() {
+                    self.cancel_fill();
+                } else {
+                    self.start_fill(&input.trace_position);
+                }
+            }
+            NavEditorMode::Path => {
+                self.on_path_clicked(&input.trace_position).await;
             }
             NavEditorMode::Toggle => {
-                let mut move_to_vmsg = VTunnelMessage::new("liz_move_to".to_string());
-                move_to_vmsg.add_vector3(input.trace_position.clone());
-                self.emitter.send_vmsg(move_to_vmsg).await;
+                self.change_nearest_nav_type(&input.trace_position, NavType::toggled).await;
+            }
+            NavEditorMode::Mark => {
+                self.change_nearest_nav_type(&input.trace_position, |nav_type| Some(nav_type.next_mark())).await;
+            }
+            NavEditorMode::Layer => {
+                // With menu held it hides or shows the active layer, otherwise it moves on to the next one.
+                if input.is_down(input_button::IN_MENU_HAND1) {
+                    self.menu_used_as_modifier = true;
+                    if !self.hidden_layers.remove(&self.active_layer) {
+                        self.hidden_layers.insert(self.active_layer.clone());
+                    }
+                    self.path_graph = None;
+                    self.render_nearby_nav_points(input.hand_position.clone()).await;
+                } else {
+                    let layers = self.store.lock().await.layers();
+                    self.active_layer = next_layer(&layers, &self.active_layer);
+                }
+                println!("NavBuilder layer {} ({} hidden)", self.active_layer, self.hidden_layers.len());
+                self.send_layer_state().await;
             }
         }
     }
 }
 
+#[async_trait]
 impl GadgetProgram for NavBuilderProgram {
+    fn name(&self) -> &'static str {
+        "nav_builder"
+    }
+
+    fn state(&self) -> GadgetState {
+        let color = if self.active && !self.is_first_click {
+            self.nav_editor_mode.to_color()
+        } else {
+            Vector3::default()
+        };
+
+        GadgetState::new(self.name(), self.nav_editor_mode.to_int() as i64, color)
+    }
+
     async fn on_activate(&mut self) {
         self.active = true;
         self.is_first_click = true;
-        self.emit_state().await;
         println!("NavBuilder activated!");
     }
 
     async fn on_deactivate(&mut self) {
         self.active = false;
-        self.emit_state().await;
+        self.path_start = None;
+        self.cancel_fill();
         println!("NavBuilder deactivated!");
     }
 
+    async fn on_map_changed(&mut self) {
+        // A fill and paths are on the old map.
+        self.cancel_fill();
+        self.path_start = None;
+        self.path_graph = None;
+    }
+
     async fn on_input(&mut self, input: &PlayerInput) {
         if !self.active {
             return;
@@ -386,34 +1085,109 @@ impl GadgetProgram for NavBuilderProgram {
         if input.is_pressed(input_button::IN_USE_HAND1) {
             if self.is_first_click {
                 self.is_first_click = false;
-                self.emit_state().await;
             } else {
                 self.on_trigger_clicked(input).await;
             }
         }
 
         if input.is_pressed(input_button::IN_PAD_DOWN_HAND1) {
-            self.cycle_mode().await;
+            self.cycle_mode();
+            if matches!(self.nav_editor_mode, NavEditorMode::Layer) {
+                self.send_layer_state().await;
+            }
+        }
+
+        // Pad up undoes, or redoes while menu is held.
+        if input.is_pressed(input_button::IN_PAD_UP_HAND1) {
+            let redo = input.is_down(input_button::IN_MENU_HAND1);
+            self.menu_used_as_modifier |= redo;
+            self.step_history(redo).await;
         }
 
         if input.is_pressed(input_button::IN_MENU_HAND1) {
-            self.upload_nav_points().await;
-            // self.render_nearby_nav_points(input.hand_position.clone()).await;
+            self.menu_used_as_modifier = false;
+        }
+
+        // Menu on its own checks the sync and draws the nav points around the hand on release, so it can still be used for redo.
+        if input.is_released(input_button::IN_MENU_HAND1) && !self.menu_used_as_modifier {
+            self.verify_nav_points().await;
+            self.render_nearby_nav_points(input.hand_position.clone()).await;
         }
     }
 }
 
-impl VTunnelSerializable for NavBuilderProgram {
-    fn serialize(&self) -> VTunnelMessage {
-        let mut vmsg = VTunnelMessage::new("gadget_state".to_string());
-        vmsg.add_int(self.nav_editor_mode.to_int() as i64);
-
-        if self.active && !self.is_first_click {
-            vmsg.add_vector3(self.nav_editor_mode.to_color());
-        } else {
-            vmsg.add_vector3(Vector3::default());
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_builder::nav_builder::{next_layer, NavPointStore, NavStoreError, NavType};
+
+    #[test]
+    fn test_change_nav_type() {
+        let mut store = NavPointStore::open(Path::new(":memory:")).unwrap();
+        let group = store.begin_edit_group(1);
+        let nav_point = store.add_nav_point(group, &Vector3::new(3.0, -2.0, 0.0), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+
+        let mut nav_type = NavType::Walkable;
+        let mut marks = Vec::new();
+        for _ in 0..4 {
+            nav_type = nav_type.next_mark();
+            let group = store.begin_edit_group(1);
+            store.set_nav_type(group, nav_point.id, nav_type.clone()).unwrap().unwrap();
+            marks.push(NavType::from_int(store.nav_points()[0].nav_type.to_int()));
         }
-
-        vmsg
+        assert!(matches!(marks[..], [NavType::Jump, NavType::Crouch, NavType::NoLiz, NavType::Walkable]));
+        assert!(matches!(NavType::Obstacle.toggled(), Some(NavType::Walkable)));
+        assert!(matches!(NavType::Walkable.toggled(), Some(NavType::Obstacle)));
+        assert!(NavType::Crouch.toggled().is_none());
+
+        // Both the store and the spatial index see the change, and it can be undone.
+        let revision = store.revision();
+        let group = store.begin_edit_group(1);
+        store.set_nav_type(group, nav_point.id, NavType::Obstacle).unwrap().unwrap();
+        assert_ne!(store.revision(), revision);
+        assert!(matches!(store.get_nearest_nav_point(&Vector3::new(0.0, 0.0, 0.0), NavPointStore::DEFAULT_LAYER).unwrap().nav_type, NavType::Obstacle));
+        store.undo(1).unwrap();
+        assert!(matches!(store.get_nearest_nav_point(&Vector3::new(0.0, 0.0, 0.0), NavPointStore::DEFAULT_LAYER).unwrap().nav_type, NavType::Walkable));
+        assert!(store.nav_points()[0].nav_type.is_walkable());
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_maps_and_layers() {
+        let mut store = NavPointStore::open(Path::new(":memory:")).unwrap();
+        let position = Vector3::new(0.0, 0.0, 0.0);
+        let group = store.begin_edit_group(1);
+        store.add_nav_point(group, &position, NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+
+        // Points from before maps stay unnamed until a map adopts them, which only works once.
+        store.switch_map("battleship_bay").unwrap();
+        assert!(store.nav_points().is_empty());
+        assert_eq!(store.adopt_unnamed("battleship_bay").unwrap(), 1);
+        assert_eq!(store.nav_points().len(), 1);
+        assert!(matches!(store.adopt_unnamed("battleship_bay"), Err(NavStoreError::MapNotEmpty { .. })));
+
+        // Each layer gets its own point on a grid spot.
+        let group = store.begin_edit_group(1);
+        let wall = store.add_nav_point(group, &position, NavType::Obstacle, "walls").unwrap().unwrap();
+        assert!(store.add_nav_point(group, &position, NavType::Walkable, "walls").unwrap().is_none());
+        assert_eq!(store.layers(), vec!["default", "walls"]);
+        assert_eq!(store.get_nearest_nav_point(&position, "walls").unwrap().id, wall.id);
+
+        // Other maps don't see them, or their history.
+        store.switch_map("sunday_lobby").unwrap();
+        assert!(store.nav_points().is_empty());
+        assert!(store.undo(1).unwrap().is_empty());
+        let group = store.begin_edit_group(1);
+        store.add_nav_point(group, &position, NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+        assert_eq!(store.map_names().unwrap(), vec!["battleship_bay", "sunday_lobby"]);
+
+        store.switch_map("battleship_bay").unwrap();
+        assert_eq!(store.nav_points().len(), 2);
+        assert_eq!(store.undo(1).unwrap().len(), 1);
+        assert_eq!(store.layers(), vec!["default"]);
+
+        let layers = vec!["default".to_string(), "walls".to_string()];
+        assert_eq!(next_layer(&layers, "default"), "walls");
+        assert_eq!(next_layer(&layers, "walls"), "layer_1");
+        assert_eq!(next_layer(&layers, "layer_1"), "default");
+    }
+}
@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use bsi_tools_lib::math::Vector3;
+use crate::nav_builder::nav_builder::{NavPoint, NavPointStore};
+use crate::nav_builder::nav_index::NavPointIndex;
+
+/**
+ * Walkable nav points connected to their neighbours on the grid.
+ *
+ * Points are connected in all 8 directions when their heights are within a step of each other.
+ * Diagonals are only connected when both points beside them are too, so paths don't cut corners.
+ */
+pub struct NavGraph {
+    nav_points: Vec<NavPoint>,
+    // Neighbour indices and the distance to them, for every nav point.
+    edges: Vec<Vec<(usize, f64)>>,
+    indices: HashMap<u64, usize>,
+    index: NavPointIndex,
+}
+
+/**
+ * Entry in the A* open set, ordered so the heap pops the lowest estimate first.
+ */
+struct OpenNode {
+    estimate: f64,
+    index: usize,
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate).then(other.index.cmp(&self.index))
+    }
+}
+
+impl NavGraph {
+    pub const DEFAULT_STEP_HEIGHT: f64 = 18.0;
+
+    pub fn build(nav_points: &[NavPoint], step_height: f64) -> NavGraph {
+        let nav_points: Vec<NavPoint> = nav_points
+            .iter()
+            .filter(|nav_point| nav_point.nav_type.is_walkable())
+            .cloned()
+            .collect();
+
+        // Grid column -> nav points in it, there can be one per floor.
+        let mut columns: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (index, nav_point) in nav_points.iter().enumerate() {
+            columns.entry(Self::grid_cell(&nav_point.position)).or_default().push(index);
+        }
+
+        let find_step = |from: &Vector3, x: i64, y: i64| -> Option<usize> {
+            columns.get(&(x, y))?
+                .iter()
+                .copied()
+                .filter(|index| (nav_points[*index].position.z - from.z).abs() <= step_height)
+                .min_by(|a, b| {
+                    let a = (nav_points[*a].position.z - from.z).abs();
+                    let b = (nav_points[*b].position.z - from.z).abs();
+                    a.total_cmp(&b)
+                })
+        };
+
+        let mut edges = Vec::with_capacity(nav_points.len());
+        for nav_point in &nav_points {
+            let (x, y) = Self::grid_cell(&nav_point.position);
+            let mut neighbours = Vec::new();
+            for offset_x in -1..=1 {
+                for offset_y in -1..=1 {
+                    if offset_x == 0 && offset_y == 0 {
+                        continue;
+                    }
+                    let Some(neighbour) = find_step(&nav_point.position, x + offset_x, y + offset_y) else {
+                        continue;
+                    };
+                    if offset_x != 0 && offset_y != 0
+                        && (find_step(&nav_point.position, x + offset_x, y).is_none() || find_step(&nav_point.position, x, y + offset_y).is_none()) {
+                        continue;
+                    }
+                    neighbours.push((neighbour, nav_point.position.distance(&nav_points[neighbour].position)));
+                }
+            }
+            edges.push(neighbours);
+        }
+
+        let indices = nav_points.iter().enumerate().map(|(index, nav_point)| (nav_point.id, index)).collect();
+        let mut index = NavPointIndex::new();
+        for nav_point in &nav_points {
+            index.insert(nav_point.clone());
+        }
+        NavGraph { nav_points, edges, indices, index }
+    }
+
+    fn grid_cell(position: &Vector3) -> (i64, i64) {
+        (
+            (position.x / NavPointStore::NAV_POINT_GRID_SIZE).round() as i64,
+            (position.y / NavPointStore::NAV_POINT_GRID_SIZE).round() as i64,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.nav_points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nav_points.is_empty()
+    }
+
+    pub fn neighbours(&self, nav_point_id: u64) -> Vec<&NavPoint> {
+        let Some(index) = self.indices.get(&nav_point_id) else {
+            return Vec::new();
+        };
+        self.edges[*index].iter().map(|(neighbour, _)| &self.nav_points[*neighbour]).collect()
+    }
+
+    /**
+     * Returns the closest walkable nav point within a couple of grid cells of the position.
+     */
+    pub fn get_nearest_nav_point(&self, position: &Vector3) -> Option<&NavPoint> {
+        self.index.nearest(position, NavPointStore::NAV_POINT_GRID_SIZE * 2.0)
+    }
+
+    /**
+     * Finds the shortest path between two nav points with A*.
+     * The path includes both ends, and is None if either isn't walkable or they aren't connected.
+     */
+    pub fn find_path(&self, start_id: u64, goal_id: u64) -> Option<Vec<&NavPoint>> {
+        let start = *self.indices.get(&start_id)?;
+        let goal = *self.indices.get(&goal_id)?;
+        let goal_position = &self.nav_points[goal].position;
+
+        let mut costs = vec![f64::INFINITY; self.nav_points.len()];
+        let mut came_from = vec![None; self.nav_points.len()];
+        let mut open = BinaryHeap::new();
+        costs[start] = 0.0;
+        open.push(OpenNode { estimate: self.nav_points[start].position.distance(goal_position), index: start });
+
+        while let Some(OpenNode { estimate, index }) = open.pop() {
+            if index == goal {
+                let mut path = vec![&self.nav_points[goal]];
+                let mut current = goal;
+                while let Some(previous) = came_from[current] {
+                    path.push(&self.nav_points[previous]);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            // Already reached this one on a shorter path.
+            if estimate > costs[index] + self.nav_points[index].position.distance(goal_position) {
+                continue;
+            }
+
+            for (neighbour, distance) in &self.edges[index] {
+                let cost = costs[index] + distance;
+                if cost < costs[*neighbour] {
+                    costs[*neighbour] = cost;
+                    came_from[*neighbour] = Some(index);
+                    open.push(OpenNode {
+                        estimate: cost + self.nav_points[*neighbour].position.distance(goal_position),
+                        index: *neighbour,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_builder::nav_builder::{NavPoint, NavPointStore, NavType};
+    use crate::nav_builder::nav_graph::NavGraph;
+
+    fn nav_point(id: u64, x: i64, y: i64, z: f64, nav_type: NavType) -> NavPoint {
+        NavPoint { id, position: Vector3::new(x as f64 * 16.0, y as f64 * 16.0, z), nav_type, layer: NavPointStore::DEFAULT_LAYER.to_string() }
+    }
+
+    #[test]
+    fn test_find_path() {
+        // A 5x5 floor with an obstacle wall at x = 2 that has a gap at y = 4,
+        // and a ledge at x = 5 that's too tall to step onto.
+        let mut nav_points = Vec::new();
+        for x in 0..5 {
+            for y in 0..5 {
+                let nav_type = if x == 2 && y != 4 { NavType::Obstacle } else { NavType::Walkable };
+                nav_points.push(nav_point(x as u64 * 10 + y as u64, x, y, 0.0, nav_type));
+            }
+        }
+        nav_points.push(nav_point(100, 5, 0, 40.0, NavType::Walkable));
+
+        let graph = NavGraph::build(&nav_points, NavGraph::DEFAULT_STEP_HEIGHT);
+        assert_eq!(graph.len(), 22);
+        // Corner of the floor: right, down, and the diagonal between them.
+        assert_eq!(graph.neighbours(0).len(), 3);
+        // Next to the wall the diagonal past it isn't allowed.
+        assert_eq!(graph.neighbours(13).len(), 5);
+
+        let path = graph.find_path(0, 40).unwrap();
+        let ids: Vec<u64> = path.iter().map(|nav_point| nav_point.id).collect();
+        assert_eq!(ids.first(), Some(&0));
+        assert_eq!(ids.last(), Some(&40));
+        assert!(ids.contains(&24), "{:?} doesn't go through the gap", ids);
+        assert!(path.windows(2).all(|pair| pair[0].position.distance(&pair[1].position) < 23.0));
+
+        assert!(graph.find_path(0, 100).is_none());
+        assert!(graph.find_path(0, 20).is_none());
+        assert_eq!(graph.get_nearest_nav_point(&Vector3::new(66.0, 3.0, 0.0)).unwrap().id, 40);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use bsi_tools_lib::math::Vector3;
+use crate::nav_builder::nav_builder::NavPoint;
+
+/**
+ * Uniform grid over nav points, for finding the ones near a position without looking at all of them.
+ *
+ * Every cell is a cube `cell_size` units wide. Queries only look at the cells that overlap the area
+ * they cover, so they stay fast as long as the cells are around the size of a typical query.
+ */
+pub struct NavPointIndex {
+    cell_size: f64,
+    cells: HashMap<(i64, i64, i64), Vec<NavPoint>>,
+    len: usize,
+}
+
+impl NavPointIndex {
+    // 8 nav points wide.
+    pub const DEFAULT_CELL_SIZE: f64 = 128.0;
+
+    pub fn new() -> Self {
+        Self::with_cell_size(Self::DEFAULT_CELL_SIZE)
+    }
+
+    pub fn with_cell_size(cell_size: f64) -> Self {
+        NavPointIndex {
+            cell_size,
+            cells: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    fn cell(&self, position: &Vector3) -> (i64, i64, i64) {
+        (
+            (position.x / self.cell_size).floor() as i64,
+            (position.y / self.cell_size).floor() as i64,
+            (position.z / self.cell_size).floor() as i64,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, nav_point: NavPoint) {
+        self.cells.entry(self.cell(&nav_point.position)).or_default().push(nav_point);
+        self.len += 1;
+    }
+
+    /**
+     * Removes a nav point. The position has to be the one it was inserted with.
+     */
+    pub fn remove(&mut self, nav_point: &NavPoint) -> bool {
+        let cell = self.cell(&nav_point.position);
+        let Some(nav_points) = self.cells.get_mut(&cell) else {
+            return false;
+        };
+        let Some(index) = nav_points.iter().position(|other| other.id == nav_point.id) else {
+            return false;
+        };
+
+        nav_points.swap_remove(index);
+        if nav_points.is_empty() {
+            self.cells.remove(&cell);
+        }
+        self.len -= 1;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.len = 0;
+    }
+
+    /**
+     * Every nav point inside the box, inclusive on both ends. In no particular order.
+     */
+    pub fn within_bounds(&self, mins: &Vector3, maxs: &Vector3) -> Vec<&NavPoint> {
+        let (min_cell, max_cell) = (self.cell(mins), self.cell(maxs));
+        let mut found = Vec::new();
+
+        for x in min_cell.0..=max_cell.0 {
+            for y in min_cell.1..=max_cell.1 {
+                for z in min_cell.2..=max_cell.2 {
+                    let Some(nav_points) = self.cells.get(&(x, y, z)) else {
+                        continue;
+                    };
+                    found.extend(nav_points.iter().filter(|nav_point| {
+                        let position = &nav_point.position;
+                        position.x >= mins.x && position.x <= maxs.x
+                            && position.y >= mins.y && position.y <= maxs.y
+                            && position.z >= mins.z && position.z <= maxs.z
+                    }));
+                }
+            }
+        }
+
+        found
+    }
+
+    /**
+     * Every nav point within the radius, closest first.
+     */
+    pub fn within_radius(&self, position: &Vector3, radius: f64) -> Vec<&NavPoint> {
+        let extents = Vector3::new(radius, radius, radius);
+        let mut found: Vec<(&NavPoint, f64)> = self.within_bounds(&position.sub(&extents), &position.add(&extents))
+            .into_iter()
+            .map(|nav_point| (nav_point, nav_point.position.distance(position)))
+            .filter(|(_, distance)| *distance <= radius)
+            .collect();
+
+        found.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        found.into_iter().map(|(nav_point, _)| nav_point).collect()
+    }
+
+    /**
+     * The closest nav point, if there's one within `max_distance`.
+     */
+    pub fn nearest(&self, position: &Vector3, max_distance: f64) -> Option<&NavPoint> {
+        let extents = Vector3::new(max_distance, max_distance, max_distance);
+        self.within_bounds(&position.sub(&extents), &position.add(&extents))
+            .into_iter()
+            .map(|nav_point| (nav_point, nav_point.position.distance(position)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(nav_point, _)| nav_point)
+    }
+}
+
+impl Default for NavPointIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_builder::nav_builder::{NavPoint, NavPointStore, NavType};
+    use crate::nav_builder::nav_index::NavPointIndex;
+
+    #[test]
+    fn test_queries_match_scan() {
+        // A sloped 40x40 grid, crossing cell boundaries and negative coordinates.
+        let mut nav_points = Vec::new();
+        for x in -20..20 {
+            for y in -20..20 {
+                let id = nav_points.len() as u64;
+                let position = Vector3::new(x as f64 * 16.0, y as f64 * 16.0, x as f64 * 4.0);
+                nav_points.push(NavPoint { id, position, nav_type: NavType::Walkable, layer: NavPointStore::DEFAULT_LAYER.to_string() });
+            }
+        }
+
+        let mut index = NavPointIndex::new();
+        for nav_point in &nav_points {
+            index.insert(nav_point.clone());
+        }
+        assert_eq!(index.len(), 1600);
+
+        let scan_radius = |position: &Vector3, radius: f64| -> Vec<u64> {
+            let mut ids: Vec<u64> = nav_points.iter().filter(|nav_point| nav_point.position.distance(position) <= radius).map(|nav_point| nav_point.id).collect();
+            ids.sort();
+            ids
+        };
+
+        for (position, radius) in [(Vector3::new(0.0, 0.0, 0.0), 100.0), (Vector3::new(-130.0, 250.0, -30.0), 300.0), (Vector3::new(5000.0, 0.0, 0.0), 50.0)] {
+            let found = index.within_radius(&position, radius);
+            assert!(found.windows(2).all(|pair| pair[0].position.distance(&position) <= pair[1].position.distance(&position)));
+            let mut ids: Vec<u64> = found.iter().map(|nav_point| nav_point.id).collect();
+            ids.sort();
+            assert_eq!(ids, scan_radius(&position, radius));
+        }
+
+        let found = index.within_bounds(&Vector3::new(-16.0, -16.0, -100.0), &Vector3::new(16.0, 0.0, 100.0));
+        assert_eq!(found.len(), 6);
+
+        let nearest = index.nearest(&Vector3::new(-129.0, 33.0, -32.0), 16.0).unwrap();
+        assert_eq!(nearest.position, Vector3::new(-128.0, 32.0, -32.0));
+        assert!(index.nearest(&Vector3::new(-129.0, 33.0, 100.0), 16.0).is_none());
+
+        let removed = nearest.clone();
+        assert!(index.remove(&removed));
+        assert!(!index.remove(&removed));
+        assert_eq!(index.len(), 1599);
+        assert_ne!(index.nearest(&Vector3::new(-129.0, 33.0, -32.0), 16.0).unwrap().id, removed.id);
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::path::Path;
+use bsi_tools_lib::math::Vector3;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::nav_builder::history::EditGroup;
+use crate::nav_builder::nav_builder::{NavPoint, NavPointStore, NavStoreError, NavType};
+
+/**
+ * Bumped when the layout of exported files changes.
+ */
+pub const NAV_POINTS_FORMAT_VERSION: u32 = 2;
+
+const CSV_HEADER: &str = "x,y,z,type";
+// Version 1 files started every line with the nav point's id.
+const CSV_HEADER_V1: &str = "id,x,y,z,type";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NavPointFormat {
+    Json,
+    Csv,
+    // Export only. A small square per nav point, grouped by nav type, for viewing in Blender.
+    Obj,
+}
+
+impl NavPointFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(NavPointFormat::Json),
+            "csv" => Some(NavPointFormat::Csv),
+            "obj" => Some(NavPointFormat::Obj),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NavPointParseError {
+    Json(serde_json::Error),
+    Csv { line: usize, reason: String },
+    UnknownNavType { line: usize, name: String },
+    UnknownJsonNavType { index: usize, name: String },
+    UnsupportedVersion(u32),
+    ExportOnly(NavPointFormat),
+}
+
+impl fmt::Display for NavPointParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NavPointParseError::Json(source) => write!(f, "invalid nav point JSON: {}", source),
+            NavPointParseError::Csv { line, reason } => write!(f, "invalid nav point CSV on line {}: {}", line, reason),
+            NavPointParseError::UnknownNavType { line, name } => write!(f, "unknown nav type \"{}\" on line {}", name, line),
+            NavPointParseError::UnknownJsonNavType { index, name } => write!(f, "unknown nav type \"{}\" for nav point {}", name, index),
+            NavPointParseError::UnsupportedVersion(version) => write!(f, "nav point file version {} is newer than {}", version, NAV_POINTS_FORMAT_VERSION),
+            NavPointParseError::ExportOnly(format) => write!(f, "{:?} files can only be exported", format),
+        }
+    }
+}
+
+impl std::error::Error for NavPointParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NavPointParseError::Json(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Version 1 files also had an id, which is ignored.
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonNavPoint {
+    x: f64,
+    y: f64,
+    z: f64,
+    #[serde(rename = "type")]
+    nav_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonNavPoints {
+    version: u32,
+    nav_points: Vec<JsonNavPoint>,
+}
+
+/**
+ * How an import went. Points that land on a grid spot that already has one are skipped.
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub skipped: usize,
+}
+
+/**
+ * Nav points sorted by position, so the same set of points always exports the same way
+ * no matter what order they were placed in.
+ */
+fn sorted(nav_points: &[NavPoint]) -> Vec<&NavPoint> {
+    let mut sorted: Vec<&NavPoint> = nav_points.iter().collect();
+    sorted.sort_by(|a, b| {
+        a.position.x.total_cmp(&b.position.x)
+            .then(a.position.y.total_cmp(&b.position.y))
+            .then(a.position.z.total_cmp(&b.position.z))
+    });
+    sorted
+}
+
+/**
+ * Writes nav points out in the given format. JSON and CSV have one nav point per line to keep diffs small.
+ * Layers and ids aren't written, a file is meant to hold one layer and ids only mean something in the database.
+ */
+pub fn export_nav_points(nav_points: &[NavPoint], format: NavPointFormat) -> String {
+    match format {
+        NavPointFormat::Json => export_json(nav_points),
+        NavPointFormat::Csv => export_csv(nav_points),
+        NavPointFormat::Obj => export_obj(nav_points),
+    }
+}
+
+fn export_json(nav_points: &[NavPoint]) -> String {
+    let lines: Vec<String> = sorted(nav_points)
+        .into_iter()
+        .map(|nav_point| {
+            let json_nav_point = JsonNavPoint {
+                x: nav_point.position.x,
+                y: nav_point.position.y,
+                z: nav_point.position.z,
+                nav_type: nav_point.nav_type.name().to_string(),
+            };
+            format!("    {}", serde_json::to_string(&json_nav_point).unwrap())
+        })
+        .collect();
+
+    if lines.is_empty() {
+        return format!("{{\n  \"version\": {},\n  \"nav_points\": []\n}}\n", NAV_POINTS_FORMAT_VERSION);
+    }
+    format!("{{\n  \"version\": {},\n  \"nav_points\": [\n{}\n  ]\n}}\n", NAV_POINTS_FORMAT_VERSION, lines.join(",\n"))
+}
+
+fn export_csv(nav_points: &[NavPoint]) -> String {
+    let mut output = format!("{}\n", CSV_HEADER);
+    for nav_point in sorted(nav_points) {
+        let position = &nav_point.position;
+        output.push_str(&format!("{},{},{},{}\n", position.x, position.y, position.z, nav_point.nav_type.name()));
+    }
+    output
+}
+
+fn export_obj(nav_points: &[NavPoint]) -> String {
+    // Slightly smaller than a grid cell, so neighbouring squares don't merge together.
+    let half = NavPointStore::NAV_POINT_GRID_SIZE * 0.4;
+    let sorted = sorted(nav_points);
+
+    let mut output = String::from("# VTunnel nav points, in Source units.\n");
+    let mut vertex_count = 0;
+    for nav_type in [NavType::Walkable, NavType::Obstacle, NavType::Jump, NavType::Crouch, NavType::NoLiz] {
+        let of_type: Vec<&&NavPoint> = sorted.iter().filter(|nav_point| nav_point.nav_type.to_int() == nav_type.to_int()).collect();
+        if of_type.is_empty() {
+            continue;
+        }
+
+        output.push_str(&format!("o {}\n", nav_type.name()));
+        for nav_point in of_type {
+            let Vector3 { x, y, z } = nav_point.position;
+            for (corner_x, corner_y) in [(-half, -half), (half, -half), (half, half), (-half, half)] {
+                output.push_str(&format!("v {} {} {}\n", x + corner_x, y + corner_y, z));
+            }
+            output.push_str(&format!("f {} {} {} {}\n", vertex_count + 1, vertex_count + 2, vertex_count + 3, vertex_count + 4));
+            vertex_count += 4;
+        }
+    }
+    output
+}
+
+/**
+ * Reads nav points from an exported file. Files don't have ids, so every id is 0, and every point is on the default layer.
+ */
+pub fn parse_nav_points(text: &str, format: NavPointFormat) -> Result<Vec<NavPoint>, NavPointParseError> {
+    match format {
+        NavPointFormat::Json => parse_json(text),
+        NavPointFormat::Csv => parse_csv(text),
+        NavPointFormat::Obj => Err(NavPointParseError::ExportOnly(format)),
+    }
+}
+
+fn parse_json(text: &str) -> Result<Vec<NavPoint>, NavPointParseError> {
+    let file: JsonNavPoints = serde_json::from_str(text).map_err(NavPointParseError::Json)?;
+    if file.version > NAV_POINTS_FORMAT_VERSION {
+        return Err(NavPointParseError::UnsupportedVersion(file.version));
+    }
+
+    file.nav_points
+        .into_iter()
+        .enumerate()
+        .map(|(index, json_nav_point)| {
+            let nav_type = NavType::from_name(&json_nav_point.nav_type).ok_or_else(|| NavPointParseError::UnknownJsonNavType {
+                index,
+                name: json_nav_point.nav_type.clone(),
+            })?;
+            Ok(NavPoint {
+                id: 0,
+                position: Vector3::new(json_nav_point.x, json_nav_point.y, json_nav_point.z),
+                nav_type,
+                layer: NavPointStore::DEFAULT_LAYER.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn parse_csv(text: &str) -> Result<Vec<NavPoint>, NavPointParseError> {
+    let mut nav_points = Vec::new();
+    let mut has_ids = false;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == CSV_HEADER || line == CSV_HEADER_V1 {
+            has_ids = line == CSV_HEADER_V1;
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let expected = if has_ids { 5 } else { 4 };
+        if fields.len() != expected {
+            return Err(NavPointParseError::Csv { line: line_number, reason: format!("expected {} fields, found {}", expected, fields.len()) });
+        }
+
+        let fields = &fields[fields.len() - 4..];
+        let number = |field: &str| field.parse::<f64>().map_err(|err| NavPointParseError::Csv { line: line_number, reason: format!("\"{}\": {}", field, err) });
+        let position = Vector3::new(number(fields[0])?, number(fields[1])?, number(fields[2])?);
+        let nav_type = NavType::from_name(fields[3]).ok_or_else(|| NavPointParseError::UnknownNavType { line: line_number, name: fields[3].to_string() })?;
+
+        nav_points.push(NavPoint { id: 0, position, nav_type, layer: NavPointStore::DEFAULT_LAYER.to_string() });
+    }
+    Ok(nav_points)
+}
+
+/**
+ * Adds nav points to a layer of the store's current map as new points. Points on a grid spot the layer already has one on are
+ * skipped, so importing the same file twice doesn't change anything.
+ */
+pub fn import_nav_points(store: &mut NavPointStore, group: EditGroup, layer: &str, nav_points: &[NavPoint]) -> Result<ImportSummary, NavStoreError> {
+    let mut summary = ImportSummary::default();
+    for nav_point in nav_points {
+        match store.add_nav_point(group, &nav_point.position, nav_point.nav_type.clone(), layer)? {
+            Some(_) => summary.added += 1,
+            None => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use bsi_tools_lib::math::Vector3;
+    use crate::nav_builder::nav_builder::{NavPoint, NavPointStore, NavType};
+    use crate::nav_builder::nav_io::{export_nav_points, import_nav_points, parse_nav_points, ImportSummary, NavPointFormat, NavPointParseError};
+
+    #[test]
+    fn test_export_and_import() {
+        let nav_points = vec![
+            NavPoint { id: 7, position: Vector3::new(16.0, 0.0, 8.5), nav_type: NavType::NoLiz, layer: NavPointStore::DEFAULT_LAYER.to_string() },
+            NavPoint { id: 3, position: Vector3::new(-16.0, 32.0, 0.0), nav_type: NavType::Walkable, layer: NavPointStore::DEFAULT_LAYER.to_string() },
+            NavPoint { id: 5, position: Vector3::new(16.0, -16.0, 8.5), nav_type: NavType::Obstacle, layer: NavPointStore::DEFAULT_LAYER.to_string() },
+        ];
+
+        let csv = export_nav_points(&nav_points, NavPointFormat::Csv);
+        assert_eq!(csv, "x,y,z,type\n-16,32,0,walkable\n16,-16,8.5,obstacle\n16,0,8.5,no_liz\n");
+
+        let json = export_nav_points(&nav_points, NavPointFormat::Json);
+        assert!(json.contains("\n    {\"x\":16.0,\"y\":-16.0,\"z\":8.5,\"type\":\"obstacle\"},\n"));
+        // Same points in any order export the same.
+        let reversed: Vec<NavPoint> = nav_points.iter().rev().cloned().collect();
+        assert_eq!(export_nav_points(&reversed, NavPointFormat::Json), json);
+
+        for (text, format) in [(&csv, NavPointFormat::Csv), (&json, NavPointFormat::Json)] {
+            let parsed = parse_nav_points(text, format).unwrap();
+            assert_eq!(export_nav_points(&parsed, format), *text);
+        }
+
+        let obj = export_nav_points(&nav_points, NavPointFormat::Obj);
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 12);
+        assert!(obj.contains("o no_liz\n") && obj.contains("f 9 10 11 12\n"));
+        assert!(parse_nav_points(&obj, NavPointFormat::Obj).is_err());
+        assert!(parse_nav_points("x,y,z,type\n0,0,0,flying\n", NavPointFormat::Csv).is_err());
+        assert!(parse_nav_points("{\"version\": 99, \"nav_points\": []}", NavPointFormat::Json).is_err());
+        let bad_type = "{\"version\": 2, \"nav_points\": [{\"x\":0,\"y\":0,\"z\":0,\"type\":\"walkable\"}, {\"x\":0,\"y\":0,\"z\":0,\"type\":\"flying\"}]}";
+        assert!(matches!(parse_nav_points(bad_type, NavPointFormat::Json), Err(NavPointParseError::UnknownJsonNavType { index: 1, .. })));
+
+        // Files from before ids were left out still read.
+        let v1_csv = "id,x,y,z,type\n3,-16,32,0,walkable\n5,16,-16,8.5,obstacle\n7,16,0,8.5,no_liz\n";
+        assert_eq!(export_nav_points(&parse_nav_points(v1_csv, NavPointFormat::Csv).unwrap(), NavPointFormat::Csv), csv);
+        let v1_json = "{\"version\": 1, \"nav_points\": [{\"id\":5,\"x\":16.0,\"y\":-16.0,\"z\":8.5,\"type\":\"obstacle\"}]}";
+        assert!(matches!(parse_nav_points(v1_json, NavPointFormat::Json).unwrap()[0].nav_type, NavType::Obstacle));
+
+        // Importing keeps what's already there, and a second import is a no-op.
+        let mut store = NavPointStore::open(Path::new(":memory:")).unwrap();
+        let group = store.begin_edit_group(0);
+        store.add_nav_point(group, &Vector3::new(16.0, 0.0, 8.5), NavType::Walkable, NavPointStore::DEFAULT_LAYER).unwrap().unwrap();
+        let parsed = parse_nav_points(&csv, NavPointFormat::Csv).unwrap();
+        let group = store.begin_edit_group(0);
+        assert_eq!(import_nav_points(&mut store, group, NavPointStore::DEFAULT_LAYER, &parsed).unwrap(), ImportSummary { added: 2, skipped: 1 });
+        assert_eq!(import_nav_points(&mut store, group, NavPointStore::DEFAULT_LAYER, &parsed).unwrap(), ImportSummary { added: 0, skipped: 3 });
+        assert_eq!(store.nav_points().len(), 3);
+        assert!(store.nav_points().iter().any(|nav_point| matches!(nav_point.nav_type, NavType::Walkable) && nav_point.position.x == 16.0 && nav_point.position.y == 0.0));
+
+        // The whole import is one undo.
+        assert_eq!(store.undo(0).unwrap().len(), 2);
+        assert_eq!(store.nav_points().len(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use bsi_tools_lib::math::Vector3;
+use crate::nav_builder::nav_builder::NavPoint;
+use crate::vtunnel::{VTunnelDeserializable, VTunnelMessage, VTunnelMessageBatch, VTunnelSerializable};
+use crate::vtunnel_emitter::VTunnelEmitter;
+
+// Largest prime below 2^31. Keeps every step of the checksum well inside what a Lua number holds exactly.
+const CHECKSUM_MODULUS: i64 = 2_147_483_647;
+
+/**
+ * The script's answer to a `nav_sync_checksum` request.
+ */
+#[derive(Debug, Default, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "nav_sync_checksum_result", crate = "crate")]
+pub struct NavSyncChecksum {
+    pub count: u64,
+    pub checksum: u64,
+}
+
+impl NavSyncChecksum {
+    /**
+     * Checksum of a set of nav points, the same no matter what order they're in.
+     * Positions are rounded to whole units, so it doesn't depend on how floats make it through the console.
+     */
+    pub fn of<'a>(nav_points: impl IntoIterator<Item = (u64, &'a Vector3, u8)>) -> NavSyncChecksum {
+        let round = |value: f64| (value + 0.5).floor() as i64;
+        let mut result = NavSyncChecksum::default();
+        let mut sum = 0;
+        for (id, position, nav_type) in nav_points {
+            let parts = [id as i64, round(position.x), round(position.y), round(position.z), nav_type as i64];
+            let hash = parts.iter().fold(0, |hash, part| (hash * 31 + part.rem_euclid(CHECKSUM_MODULUS)) % CHECKSUM_MODULUS);
+            sum = (sum + hash) % CHECKSUM_MODULUS;
+            result.count += 1;
+        }
+        result.checksum = sum as u64;
+        result
+    }
+
+    fn of_nav_points<'a>(nav_points: impl IntoIterator<Item = &'a NavPoint>) -> NavSyncChecksum {
+        Self::of(nav_points.into_iter().map(|nav_point| (nav_point.id, &nav_point.position, nav_point.nav_type.to_int())))
+    }
+}
+
+struct NavSyncChecksumRequest;
+
+impl VTunnelSerializable for NavSyncChecksumRequest {
+    fn serialize(&self) -> VTunnelMessage {
+        VTunnelMessage::new("nav_sync_checksum".to_string())
+    }
+}
+
+/**
+ * What a sync sent to the script.
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SyncSummary {
+    // Nav points added or updated.
+    pub changed: usize,
+    pub removed: usize,
+    // Set when the script was sent every nav point instead of the changes.
+    pub full_resync: bool,
+}
+
+/**
+ * Keeps the script's copy of the nav points in step with VTunnel's, without sending all of them every time.
+ *
+ * `send_changes` sends what changed since the last time, without waiting on the script, so it's cheap
+ * enough to call after every edit. `verify` asks the script for a checksum of its copy, and if it doesn't
+ * match (a reloaded script, a dropped message), the script gets everything again. It waits on a reply,
+ * so it's meant for when nothing is being edited.
+ */
+pub struct NavPointSync {
+    // What the script should have, going by everything sent to it, by id.
+    sent: HashMap<u64, NavPoint>,
+}
+
+impl NavPointSync {
+    pub fn new() -> Self {
+        NavPointSync { sent: HashMap::new() }
+    }
+
+    /**
+     * Nav points that are new or changed since they were last sent, and the ids of ones that are gone.
+     */
+    pub fn diff<'a>(&self, nav_points: &'a [NavPoint]) -> (Vec<&'a NavPoint>, Vec<u64>) {
+        let changed = nav_points
+            .iter()
+            .filter(|nav_point| match self.sent.get(&nav_point.id) {
+                Some(sent) => sent.position != nav_point.position || sent.nav_type.to_int() != nav_point.nav_type.to_int(),
+                None => true,
+            })
+            .collect();
+
+        let current: HashMap<u64, ()> = nav_points.iter().map(|nav_point| (nav_point.id, ())).collect();
+        let mut removed: Vec<u64> = self.sent.keys().filter(|id| !current.contains_key(id)).copied().collect();
+        removed.sort();
+        (changed, removed)
+    }
+
+    /**
+     * Sends the script whatever it's missing of `nav_points`, without checking that it got there.
+     */
+    pub async fn send_changes(&mut self, emitter: &VTunnelEmitter, nav_points: Vec<NavPoint>) -> SyncSummary {
+        let (changed, removed) = self.diff(&nav_points);
+
+        // Past this point clearing and starting over is less to send.
+        if changed.len() + removed.len() > nav_points.len() {
+            return self.send_all(emitter, nav_points).await;
+        }
+
+        let summary = SyncSummary { changed: changed.len(), removed: removed.len(), full_resync: false };
+        let mut vmsg_batch = VTunnelMessageBatch::new();
+        for nav_point in changed {
+            vmsg_batch.add_message(nav_point.to_add_vmsg());
+        }
+        for id in removed {
+            vmsg_batch.add_message(NavPoint::to_remove_vmsg(id));
+        }
+        if !vmsg_batch.messages.is_empty() {
+            emitter.send_batch(vmsg_batch).await;
+        }
+
+        self.remember(nav_points);
+        summary
+    }
+
+    /**
+     * Sends any changes, then checks the script's copy against `nav_points` and sends everything again if it's off.
+     */
+    pub async fn verify(&mut self, emitter: &VTunnelEmitter, nav_points: Vec<NavPoint>) -> Result<SyncSummary, std::io::Error> {
+        let expected = NavSyncChecksum::of_nav_points(&nav_points);
+        let summary = self.send_changes(emitter, nav_points.clone()).await;
+        if Self::request_checksum(emitter).await? == expected {
+            return Ok(summary);
+        }
+
+        let summary = self.send_all(emitter, nav_points).await;
+        let checksum = Self::request_checksum(emitter).await?;
+        if checksum != expected {
+            // Nothing is known about the script's copy anymore, the next sync starts from scratch.
+            self.sent.clear();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("script has {} nav points with checksum {} after a full resync, expected {} with {}", checksum.count, checksum.checksum, expected.count, expected.checksum),
+            ));
+        }
+        Ok(summary)
+    }
+
+    async fn send_all(&mut self, emitter: &VTunnelEmitter, nav_points: Vec<NavPoint>) -> SyncSummary {
+        let mut vmsg_batch = VTunnelMessageBatch::new();
+        vmsg_batch.add_message(VTunnelMessage::new("clear_nav_points".to_string()));
+        for nav_point in &nav_points {
+            vmsg_batch.add_message(nav_point.to_add_vmsg());
+        }
+        emitter.send_batch(vmsg_batch).await;
+
+        let summary = SyncSummary { changed: nav_points.len(), removed: 0, full_resync: true };
+        self.remember(nav_points);
+        summary
+    }
+
+    fn remember(&mut self, nav_points: Vec<NavPoint>) {
+        self.sent = nav_points.into_iter().map(|nav_point| (nav_point.id, nav_point)).collect();
+    }
+
+    async fn request_checksum(emitter: &VTunnelEmitter) -> Result<NavSyncChecksum, std::io::Error> {
+        let vmsg = emitter.send_request(NavSyncChecksumRequest).await?;
+        let mut checksum = NavSyncChecksum::default();
+        checksum.apply_vtunnel_message(&vmsg).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        Ok(checksum)
+    }
+}
+
+impl Default for NavPointSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use bsi_tools_lib::math::Vector3;
+    use tokio::sync::mpsc;
+    use crate::mock_server::{MockScene, MockVConsole};
+    use crate::nav_builder::nav_builder::{NavPoint, NavPointStore, NavType};
+    use crate::nav_builder::nav_sync::{NavPointSync, NavSyncChecksum, SyncSummary};
+    use crate::vconsole::VConsolePacket;
+    use crate::vtunnel::parse_vtunnel_message;
+    use crate::vtunnel_emitter::EmitterHandle;
+
+    fn mock_emitter(mock: Arc<std::sync::Mutex<MockVConsole>>) -> EmitterHandle {
+        let (sender, mut receiver) = mpsc::channel(64);
+        let emitter = EmitterHandle::new(sender);
+        let reply_emitter = emitter.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = receiver.recv().await {
+                let replies = mock.lock().unwrap().handle_packet(VConsolePacket::from_packet(packet));
+                for reply in replies {
+                    if let VConsolePacket::Print(print) = reply {
+                        if let Ok(vmsg) = parse_vtunnel_message(print.text()) {
+                            reply_emitter.handle_reply(vmsg).await;
+                        }
+                    }
+                }
+            }
+        });
+        emitter
+    }
+
+    fn nav_point(id: u64, x: f64) -> NavPoint {
+        NavPoint { id, position: Vector3::new(x, -32.0, 4.5), nav_type: NavType::Walkable, layer: NavPointStore::DEFAULT_LAYER.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_sync_deltas_and_resync() {
+        let mock = Arc::new(std::sync::Mutex::new(MockVConsole::new(MockScene::default())));
+        let emitter = mock_emitter(mock.clone());
+        let sent = || mock.lock().unwrap().received().len();
+        let mut sync = NavPointSync::new();
+
+        let mut nav_points: Vec<NavPoint> = (1..=10).map(|id| nav_point(id, id as f64 * -16.0)).collect();
+        assert_eq!(sync.send_changes(&emitter, nav_points.clone()).await, SyncSummary { changed: 10, removed: 0, full_resync: false });
+        // The mock answers in the background, waiting on a checksum makes sure it has seen everything before counting.
+        assert_eq!(sync.verify(&emitter, nav_points.clone()).await.unwrap(), SyncSummary::default());
+
+        // Only the changes go out, and verifying an up to date copy is just the checksum request.
+        nav_points[3].nav_type = NavType::Obstacle;
+        nav_points.remove(7);
+        let before = sent();
+        assert_eq!(sync.send_changes(&emitter, nav_points.clone()).await, SyncSummary { changed: 1, removed: 1, full_resync: false });
+        assert_eq!(sync.verify(&emitter, nav_points.clone()).await.unwrap(), SyncSummary::default());
+        assert_eq!(sent() - before, 3);
+
+        // A reloaded script has nothing, so the checksum is off and everything is sent again.
+        mock.lock().unwrap().reload_script();
+        assert_eq!(sync.send_changes(&emitter, nav_points.clone()).await, SyncSummary::default());
+        let summary = sync.verify(&emitter, nav_points.clone()).await.unwrap();
+        assert_eq!(summary, SyncSummary { changed: 9, removed: 0, full_resync: true });
+        assert_eq!(mock.lock().unwrap().nav_sync_checksum(), NavSyncChecksum::of_nav_points(&nav_points));
+
+        // Order doesn't matter, but every field does.
+        let reversed: Vec<NavPoint> = nav_points.iter().rev().cloned().collect();
+        assert_eq!(NavSyncChecksum::of_nav_points(&reversed), NavSyncChecksum::of_nav_points(&nav_points));
+        nav_points[0].position.z += 1.0;
+        assert_ne!(NavSyncChecksum::of_nav_points(&reversed), NavSyncChecksum::of_nav_points(&nav_points));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use bytes::BytesMut;
+use tokio_util::codec::Encoder;
+use crate::vconsole::{decode_packet, Packet, PacketCodec, VConsolePacket};
+
+pub const RECORDING_MAGIC: &[u8; 6] = b"VTREC\0";
+pub const RECORDING_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    // Sent by the game.
+    Incoming,
+    // Sent by us.
+    Outgoing,
+}
+
+impl PacketDirection {
+    fn to_byte(self) -> u8 {
+        match self {
+            PacketDirection::Incoming => 0,
+            PacketDirection::Outgoing => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<PacketDirection> {
+        match byte {
+            0 => Some(PacketDirection::Incoming),
+            1 => Some(PacketDirection::Outgoing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedPacket {
+    pub direction: PacketDirection,
+    // Time since the recording started.
+    pub timestamp: Duration,
+    pub packet: VConsolePacket,
+}
+
+/**
+ * Writes packets to a recording file.
+ *
+ * File layout:
+ * 6 bytes - "VTREC" + NUL
+ * 1 byte - Version
+ * Then for every packet:
+ * 1 byte - Direction (0 incoming, 1 outgoing)
+ * 8 bytes - Microseconds since the recording started, big-endian u64
+ * N bytes - The packet exactly as it goes over the wire
+ */
+pub struct SessionRecorder<W: Write> {
+    writer: W,
+    start: Instant,
+    buffer: BytesMut,
+}
+
+impl SessionRecorder<BufWriter<File>> {
+    pub fn create(path: &Path) -> Result<Self, std::io::Error> {
+        SessionRecorder::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> SessionRecorder<W> {
+    pub fn new(mut writer: W) -> Result<Self, std::io::Error> {
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&[RECORDING_VERSION])?;
+
+        Ok(SessionRecorder {
+            writer,
+            start: Instant::now(),
+            buffer: BytesMut::new(),
+        })
+    }
+
+    pub fn record(&mut self, direction: PacketDirection, packet: &Packet) -> Result<(), std::io::Error> {
+        self.record_at(direction, self.start.elapsed(), packet)
+    }
+
+    pub fn record_at(&mut self, direction: PacketDirection, timestamp: Duration, packet: &Packet) -> Result<(), std::io::Error> {
+        self.buffer.clear();
+        PacketCodec.encode(packet.clone(), &mut self.buffer)?;
+
+        self.writer.write_all(&[direction.to_byte()])?;
+        self.writer.write_all(&(timestamp.as_micros() as u64).to_be_bytes())?;
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub fn read_recording_file(path: &Path) -> Result<Vec<RecordedPacket>, std::io::Error> {
+    read_recording(BufReader::new(File::open(path)?))
+}
+
+pub fn read_recording<R: Read>(mut reader: R) -> Result<Vec<RecordedPacket>, std::io::Error> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let mut header = [0u8; 7];
+    reader.read_exact(&mut header)?;
+    if &header[..6] != RECORDING_MAGIC {
+        return Err(invalid("not a vtunnel recording"));
+    }
+    if header[6] != RECORDING_VERSION {
+        return Err(invalid(&format!("unsupported recording version {}", header[6])));
+    }
+
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let mut src = BytesMut::from(&contents[..]);
+
+    let mut packets = Vec::new();
+    while !src.is_empty() {
+        if src.len() < 9 {
+            return Err(invalid("truncated recording entry"));
+        }
+
+        let direction = PacketDirection::from_byte(src[0]).ok_or_else(|| invalid("bad packet direction"))?;
+        let timestamp = Duration::from_micros(u64::from_be_bytes(src[1..9].try_into().unwrap()));
+        let _ = src.split_to(9);
+
+        let packet = decode_packet(&mut src)?.ok_or_else(|| invalid("truncated recorded packet"))?;
+        packets.push(RecordedPacket {
+            direction,
+            timestamp,
+            packet: VConsolePacket::from_packet(packet),
+        });
+    }
+
+    Ok(packets)
+}
+
+/**
+ * One line summary of a packet, meant for diffing traffic between runs.
+ */
+pub fn format_packet(packet: &VConsolePacket) -> String {
+    match packet {
+        VConsolePacket::Print(print) => format!("PRNT {}", print.text()),
+        VConsolePacket::Command(command) => format!("CMND {}", command.command),
+        VConsolePacket::Unknown(packet) => format!("{} ({} bytes)", packet.packet_type, packet.data.len()),
+        packet => format!("{:?}", packet),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::recording::{format_packet, read_recording, PacketDirection, SessionRecorder};
+    use crate::vconsole::{CommandPacket, FocusPacket, VConsolePacket};
+
+    #[test]
+    fn test_recording_round_trip() {
+        let command = CommandPacket::new("vtunnel_receive \"$vt!0!vtunnel_request_handshake!\"".to_string());
+        let focus = FocusPacket { focused: true };
+
+        let mut recorder = SessionRecorder::new(Vec::new()).unwrap();
+        recorder.record_at(PacketDirection::Outgoing, Duration::from_millis(5), &command.to_packet()).unwrap();
+        recorder.record_at(PacketDirection::Incoming, Duration::from_micros(1_500_250), &focus.to_packet()).unwrap();
+        let bytes = recorder.into_inner();
+
+        let packets = read_recording(&bytes[..]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].direction, PacketDirection::Outgoing);
+        assert_eq!(packets[0].timestamp, Duration::from_millis(5));
+        assert_eq!(packets[0].packet, VConsolePacket::Command(command));
+        assert_eq!(packets[1].direction, PacketDirection::Incoming);
+        assert_eq!(packets[1].timestamp, Duration::from_micros(1_500_250));
+        assert_eq!(packets[1].packet, VConsolePacket::Focus(focus));
+        assert_eq!(format_packet(&packets[0].packet), "CMND vtunnel_receive \"$vt!0!vtunnel_request_handshake!\"");
+
+        assert!(read_recording(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_recording(&b"nope"[..]).is_err());
+    }
+}
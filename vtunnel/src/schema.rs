@@ -0,0 +1,199 @@
+use crate::game::commands::{DrawDebugLine, DrawDebugSphere};
+use crate::game::elizabeth::Elizabeth;
+use crate::game::gadget::{GadgetActivated, GadgetDeactivated, GadgetSelectProgram, GadgetState};
+use crate::game::player::{Player, PlayerInput};
+use crate::game::trace::{BoxTrace, LineTrace, TraceResult};
+use crate::game::world::WorldState;
+use crate::nav_builder::auto_fill::NavFillProgress;
+use crate::nav_builder::history::NavEditHistory;
+use crate::nav_builder::nav_builder::NavLayerState;
+use crate::nav_builder::nav_sync::NavSyncChecksum;
+use crate::vtunnel::VTunnelDataType;
+
+/**
+ * Describes a single field of a VTunnel message, in the order it is written.
+ */
+#[derive(Debug, Clone)]
+pub struct VTunnelFieldSchema {
+    pub name: &'static str,
+    pub data_type: VTunnelDataType,
+    pub optional: bool,
+}
+
+/**
+ * Describes the layout of a VTunnel message so both sides can be generated from one place.
+ */
+#[derive(Debug, Clone)]
+pub struct VTunnelMessageSchema {
+    pub name: &'static str,
+    pub type_name: &'static str,
+    pub fields: Vec<VTunnelFieldSchema>,
+}
+
+/**
+ * Implemented by `#[derive(VTunnelMessage)]`.
+ */
+pub trait VTunnelSchema {
+    fn schema() -> VTunnelMessageSchema;
+}
+
+/**
+ * Every message schema known to VTunnel.
+ * Add new message structs here to have them show up in the generated TypeScript.
+ */
+pub fn registry() -> Vec<VTunnelMessageSchema> {
+    vec![
+        WorldState::schema(),
+        Elizabeth::schema(),
+        Player::schema(),
+        PlayerInput::schema(),
+        DrawDebugSphere::schema(),
+        DrawDebugLine::schema(),
+        LineTrace::schema(),
+        BoxTrace::schema(),
+        TraceResult::schema(),
+        GadgetState::schema(),
+        GadgetActivated::schema(),
+        GadgetDeactivated::schema(),
+        GadgetSelectProgram::schema(),
+        NavFillProgress::schema(),
+        NavEditHistory::schema(),
+        NavLayerState::schema(),
+        NavSyncChecksum::schema(),
+    ]
+}
+
+fn to_camel_case(name: &str) -> String {
+    let mut output = String::new();
+    let mut upper_next = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper_next = true;
+        } else if upper_next {
+            output.extend(c.to_uppercase());
+            upper_next = false;
+        } else {
+            output.push(c);
+        }
+    }
+
+    output
+}
+
+fn typescript_type(data_type: VTunnelDataType) -> &'static str {
+    match data_type {
+        VTunnelDataType::String => "string",
+        VTunnelDataType::Float => "number",
+        VTunnelDataType::Int => "number",
+        VTunnelDataType::Vector3 => "Vector",
+        VTunnelDataType::Bool => "boolean",
+    }
+}
+
+fn typescript_write_method(data_type: VTunnelDataType) -> &'static str {
+    match data_type {
+        VTunnelDataType::String => "writeString",
+        VTunnelDataType::Float => "writeFloat",
+        VTunnelDataType::Int => "writeInt",
+        VTunnelDataType::Vector3 => "writeVector",
+        VTunnelDataType::Bool => "writeBoolean",
+    }
+}
+
+fn typescript_read_method(data_type: VTunnelDataType) -> &'static str {
+    match data_type {
+        VTunnelDataType::String => "indexPartDataAsString",
+        VTunnelDataType::Float => "indexPartDataAsFloat",
+        VTunnelDataType::Int => "indexPartDataAsInt",
+        VTunnelDataType::Vector3 => "indexPartDataAsVector",
+        VTunnelDataType::Bool => "indexPartDataAsBoolean",
+    }
+}
+
+/**
+ * Generates TypeScript interfaces and encode/decode helpers for the given message schemas.
+ */
+pub fn generate_typescript(schemas: &[VTunnelMessageSchema]) -> String {
+    let mut output = String::new();
+    output.push_str("// CODE GENERATED VTUNNEL MESSAGE SCHEMAS. DO NOT EDIT.\n\n");
+    output.push_str("import {VTunnelMessage} from \"./VTunnel\";\n");
+
+    for schema in schemas {
+        let interface_name = format!("{}Message", schema.type_name);
+
+        output.push('\n');
+        output.push_str(&format!("export interface {} {{\n", interface_name));
+        for field in &schema.fields {
+            let optional = if field.optional { "?" } else { "" };
+            output.push_str(&format!("    {}{}: {};\n", to_camel_case(field.name), optional, typescript_type(field.data_type)));
+        }
+        output.push_str("}\n\n");
+
+        output.push_str(&format!(
+            "export function encode{}(id: number, data: {}, name: string = \"{}\"): VTunnelMessage {{\n",
+            schema.type_name, interface_name, schema.name,
+        ));
+        output.push_str("    const vmsg = new VTunnelMessage(id, name);\n");
+        // Optional fields are read back by position, so they stop at the first one that's missing, same as the derive.
+        let mut optional_checks = Vec::new();
+        for field in &schema.fields {
+            let field_name = to_camel_case(field.name);
+            let write_method = typescript_write_method(field.data_type);
+            if field.optional {
+                optional_checks.push(format!("data.{} !== undefined", field_name));
+                output.push_str(&format!("    if ({}) {{\n", optional_checks.join(" && ")));
+                output.push_str(&format!("        vmsg.{}(data.{});\n", write_method, field_name));
+                output.push_str("    }\n");
+            } else {
+                output.push_str(&format!("    vmsg.{}(data.{});\n", write_method, field_name));
+            }
+        }
+        output.push_str("    return vmsg;\n");
+        output.push_str("}\n\n");
+
+        output.push_str(&format!("export function decode{}(vmsg: VTunnelMessage): {} {{\n", schema.type_name, interface_name));
+        output.push_str("    return {\n");
+        for (index, field) in schema.fields.iter().enumerate() {
+            let field_name = to_camel_case(field.name);
+            let read_method = typescript_read_method(field.data_type);
+            if field.optional {
+                output.push_str(&format!(
+                    "        {}: vmsg.getPartCount() > {} ? vmsg.{}({}) : undefined,\n",
+                    field_name, index, read_method, index,
+                ));
+            } else {
+                output.push_str(&format!("        {}: vmsg.{}({}),\n", field_name, read_method, index));
+            }
+        }
+        output.push_str("    };\n");
+        output.push_str("}\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{generate_typescript, VTunnelFieldSchema, VTunnelMessageSchema};
+    use crate::vtunnel::VTunnelDataType;
+
+    #[test]
+    fn test_generate_typescript() {
+        let schema = VTunnelMessageSchema {
+            name: "liz_say",
+            type_name: "LizSay",
+            fields: vec![
+                VTunnelFieldSchema { name: "clip_name", data_type: VTunnelDataType::String, optional: false },
+                VTunnelFieldSchema { name: "volume", data_type: VTunnelDataType::Float, optional: true },
+            ],
+        };
+
+        let output = generate_typescript(&[schema]);
+        assert!(output.contains("export interface LizSayMessage {\n    clipName: string;\n    volume?: number;\n}\n"));
+        assert!(output.contains("export function encodeLizSay(id: number, data: LizSayMessage, name: string = \"liz_say\"): VTunnelMessage {\n"));
+        assert!(output.contains("    vmsg.writeString(data.clipName);\n"));
+        assert!(output.contains("    if (data.volume !== undefined) {\n        vmsg.writeFloat(data.volume);\n    }\n"));
+        assert!(output.contains("        clipName: vmsg.indexPartDataAsString(0),\n"));
+        assert!(output.contains("        volume: vmsg.getPartCount() > 1 ? vmsg.indexPartDataAsFloat(1) : undefined,\n"));
+    }
+}
@@ -1,20 +1,223 @@
+use std::fmt;
 use std::fmt::Write;
 use bsi_tools_lib::math::Vector3;
 
+/**
+ * Only one `Option` field is allowed, since a later one couldn't be read back while an earlier one is `None`.
+ *
+ * ```compile_fail
+ * #[derive(vtunnel::vtunnel::VTunnelMessage)]
+ * #[vtunnel(name = "two_optional")]
+ * struct TwoOptional {
+ *     first: Option<String>,
+ *     second: Option<u32>,
+ * }
+ * ```
+ */
+pub use vtunnel_derive::VTunnelMessage;
+
+#[allow(clippy::redundant_static_lifetimes)]
 const VTUNNEL_PREFIX: &'static str = "$vt!";
 const VTUNNEL_TYPE_PREFIX: char = ':';
 const VTUNNEL_TYPE_SUFFIX: char = '!';
 
 
 pub trait VTunnelDeserializable {
-    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage);
+    fn apply_vtunnel_message(&mut self, msg: &VTunnelMessage) -> Result<(), VTunnelMessageError>;
 }
 
 pub trait VTunnelSerializable {
     fn serialize(&self) -> VTunnelMessage;
 }
 
-#[derive(Debug, Clone)]
+/**
+ * Reasons a raw VTunnel message could not be parsed.
+ * Offsets are byte offsets into the raw message where the problem was found.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum VTunnelParseError {
+    /**
+     * Message does not start with the VTunnel prefix. Usually just a regular print.
+     */
+    BadPrefix,
+    BadId { offset: usize, value: String },
+    /**
+     * A type or value delimiter was expected but never found.
+     */
+    MissingDelimiter { offset: usize },
+    UnknownType { offset: usize, type_tag: String },
+    TruncatedString { offset: usize, expected: usize, available: usize },
+    BadNumber { offset: usize, value: String },
+    ShortVector { offset: usize, value: String },
+    /**
+     * Bools are sent as 0 or 1, anything else is a bad message.
+     */
+    BadBool { offset: usize, value: String },
+}
+
+impl VTunnelParseError {
+    pub fn offset(&self) -> usize {
+        match self {
+            VTunnelParseError::BadPrefix => 0,
+            VTunnelParseError::BadId { offset, .. } => *offset,
+            VTunnelParseError::MissingDelimiter { offset } => *offset,
+            VTunnelParseError::UnknownType { offset, .. } => *offset,
+            VTunnelParseError::TruncatedString { offset, .. } => *offset,
+            VTunnelParseError::BadNumber { offset, .. } => *offset,
+            VTunnelParseError::ShortVector { offset, .. } => *offset,
+            VTunnelParseError::BadBool { offset, .. } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for VTunnelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VTunnelParseError::BadPrefix => write!(f, "missing {} prefix", VTUNNEL_PREFIX),
+            VTunnelParseError::BadId { offset, value } => write!(f, "bad message id {:?} at byte {}", value, offset),
+            VTunnelParseError::MissingDelimiter { offset } => write!(f, "missing delimiter at byte {}", offset),
+            VTunnelParseError::UnknownType { offset, type_tag } => write!(f, "unknown data type {:?} at byte {}", type_tag, offset),
+            VTunnelParseError::TruncatedString { offset, expected, available } => {
+                write!(f, "string at byte {} expected {} bytes but only {} are left", offset, expected, available)
+            }
+            VTunnelParseError::BadNumber { offset, value } => write!(f, "bad number {:?} at byte {}", value, offset),
+            VTunnelParseError::ShortVector { offset, value } => write!(f, "vector {:?} at byte {} has less than 3 components", value, offset),
+            VTunnelParseError::BadBool { offset, value } => write!(f, "bad bool {:?} at byte {}, expected 0 or 1", value, offset),
+        }
+    }
+}
+
+impl std::error::Error for VTunnelParseError {}
+
+/**
+ * Errors from reading typed fields out of an already parsed VTunnel message.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum VTunnelMessageError {
+    MissingField { message: String, field: &'static str, index: usize },
+    WrongType { message: String, field: &'static str, index: usize, expected: VTunnelDataType },
+    OutOfRange { message: String, field: &'static str, index: usize },
+}
+
+impl fmt::Display for VTunnelMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VTunnelMessageError::MissingField { message, field, index } => {
+                write!(f, "{} is missing field {} (index {})", message, field, index)
+            }
+            VTunnelMessageError::WrongType { message, field, index, expected } => {
+                write!(f, "{} field {} (index {}) is not a {:?}", message, field, index, expected)
+            }
+            VTunnelMessageError::OutOfRange { message, field, index } => {
+                write!(f, "{} field {} (index {}) is out of range for its type", message, field, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VTunnelMessageError {}
+
+/**
+ * The types that can be sent in a VTunnel message.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTunnelDataType {
+    String,
+    Float,
+    Int,
+    Vector3,
+    Bool,
+}
+
+/**
+ * Types that map onto a single VTunnel data part.
+ * Used by `#[derive(VTunnelMessage)]` to (de)serialize struct fields.
+ */
+pub trait VTunnelField: Sized {
+    const DATA_TYPE: VTunnelDataType;
+
+    fn to_data_part(&self) -> VTunnelDataPart;
+
+    fn from_data_part(part: &VTunnelDataPart) -> Option<Self>;
+}
+
+impl VTunnelField for String {
+    const DATA_TYPE: VTunnelDataType = VTunnelDataType::String;
+
+    fn to_data_part(&self) -> VTunnelDataPart {
+        VTunnelDataPart::String(self.clone())
+    }
+
+    fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+        part.get_string().map(|val| val.to_string())
+    }
+}
+
+impl VTunnelField for Vector3 {
+    const DATA_TYPE: VTunnelDataType = VTunnelDataType::Vector3;
+
+    fn to_data_part(&self) -> VTunnelDataPart {
+        VTunnelDataPart::Vector3(self.clone())
+    }
+
+    fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+        part.get_vector3()
+    }
+}
+
+impl VTunnelField for bool {
+    const DATA_TYPE: VTunnelDataType = VTunnelDataType::Bool;
+
+    fn to_data_part(&self) -> VTunnelDataPart {
+        VTunnelDataPart::Bool(*self)
+    }
+
+    fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+        part.get_bool()
+    }
+}
+
+macro_rules! impl_vtunnel_field_int {
+    ($($ty:ty),*) => {
+        $(
+            impl VTunnelField for $ty {
+                const DATA_TYPE: VTunnelDataType = VTunnelDataType::Int;
+
+                fn to_data_part(&self) -> VTunnelDataPart {
+                    VTunnelDataPart::Int(*self as i64)
+                }
+
+                fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+                    part.get_int().and_then(|val| <$ty>::try_from(val).ok())
+                }
+            }
+        )*
+    };
+}
+
+impl_vtunnel_field_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+macro_rules! impl_vtunnel_field_float {
+    ($($ty:ty),*) => {
+        $(
+            impl VTunnelField for $ty {
+                const DATA_TYPE: VTunnelDataType = VTunnelDataType::Float;
+
+                fn to_data_part(&self) -> VTunnelDataPart {
+                    VTunnelDataPart::Float(*self as f64)
+                }
+
+                fn from_data_part(part: &VTunnelDataPart) -> Option<Self> {
+                    part.get_float().map(|val| val as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_vtunnel_field_float!(f32, f64);
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum VTunnelDataPart {
     String(String),
     Float(f64),
@@ -24,6 +227,16 @@ pub enum VTunnelDataPart {
 }
 
 impl VTunnelDataPart {
+    pub fn data_type(&self) -> VTunnelDataType {
+        match self {
+            VTunnelDataPart::String(_) => VTunnelDataType::String,
+            VTunnelDataPart::Float(_) => VTunnelDataType::Float,
+            VTunnelDataPart::Int(_) => VTunnelDataType::Int,
+            VTunnelDataPart::Vector3(_) => VTunnelDataType::Vector3,
+            VTunnelDataPart::Bool(_) => VTunnelDataType::Bool,
+        }
+    }
+
     pub fn get_string(&self) -> Option<&str> {
         if let VTunnelDataPart::String(val) = self {
             Some(val)
@@ -66,7 +279,7 @@ impl VTunnelDataPart {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VTunnelMessage {
     pub id: u64,
     pub name: String,
@@ -105,34 +318,55 @@ impl VTunnelMessage {
     pub fn add_bool(&mut self, data: bool) {
         self.data.push(VTunnelDataPart::Bool(data));
     }
+
+    /**
+     * Reads a required typed field from the message data.
+     */
+    pub fn get_field<T: VTunnelField>(&self, index: usize, field: &'static str) -> Result<T, VTunnelMessageError> {
+        let part = self.data.get(index).ok_or_else(|| VTunnelMessageError::MissingField {
+            message: self.name.clone(),
+            field,
+            index,
+        })?;
+
+        // The right type that still doesn't convert is a number that doesn't fit the field.
+        T::from_data_part(part).ok_or_else(|| match part.data_type() == T::DATA_TYPE {
+            true => VTunnelMessageError::OutOfRange { message: self.name.clone(), field, index },
+            false => VTunnelMessageError::WrongType { message: self.name.clone(), field, index, expected: T::DATA_TYPE },
+        })
+    }
+
+    /**
+     * Reads an optional typed field from the message data. Returns `None` if the message ends before `index`.
+     */
+    pub fn get_optional_field<T: VTunnelField>(&self, index: usize, field: &'static str) -> Result<Option<T>, VTunnelMessageError> {
+        if index >= self.data.len() {
+            return Ok(None);
+        }
+
+        self.get_field(index, field).map(Some)
+    }
 }
 
+/**
+ * A batch of messages to send together.
+ * The emitter packs as many messages as fit into each console command.
+ */
 #[derive(Debug)]
 pub struct VTunnelMessageBatch {
     pub messages: Vec<VTunnelMessage>,
-
-    /**
-     * The number of messages to batch together when sending.
-     */
-    pub batch_size: usize,
 }
 
 impl VTunnelMessageBatch {
-    pub const DEFAULT_BATCH_SIZE: usize = 5;
-
+    #[allow(clippy::new_without_default)]
     pub fn new() -> VTunnelMessageBatch {
         VTunnelMessageBatch {
             messages: Vec::new(),
-            batch_size: VTunnelMessageBatch::DEFAULT_BATCH_SIZE,
         }
     }
 
     pub fn new_from_vec(messages: Vec<VTunnelMessage>) -> VTunnelMessageBatch {
-        VTunnelMessageBatch { messages, batch_size: VTunnelMessageBatch::DEFAULT_BATCH_SIZE }
-    }
-
-    pub fn set_batch_size(&mut self, size: usize) {
-        self.batch_size = size;
+        VTunnelMessageBatch { messages }
     }
 
     pub fn add_message(&mut self, msg: VTunnelMessage) {
@@ -140,6 +374,20 @@ impl VTunnelMessageBatch {
     }
 }
 
+/**
+ * Piece of an encoded message that was too long to fit in a single console command.
+ * The script side collects all fragments with the same fragment_id and joins the data in index order,
+ * then handles the result like any other encoded message.
+ */
+#[derive(Debug, Clone, PartialEq, VTunnelMessage)]
+#[vtunnel(name = "vtunnel_fragment", crate = "crate")]
+pub struct VTunnelFragment {
+    pub fragment_id: u64,
+    pub index: u32,
+    pub count: u32,
+    pub data: String,
+}
+
 pub fn encode_vtunnel_message(vmsg: &VTunnelMessage) -> String {
     let mut data = String::new();
     data.write_str(VTUNNEL_PREFIX).unwrap();
@@ -155,6 +403,7 @@ pub fn encode_vtunnel_message(vmsg: &VTunnelMessage) -> String {
                 data.write_str(&val.len().to_string()).unwrap();
                 data.write_str(")").unwrap();
                 data.write_str(&VTUNNEL_TYPE_PREFIX.to_string()).unwrap();
+                #[allow(clippy::needless_borrow)]
                 data.write_str(&val).unwrap();
             }
             VTunnelDataPart::Float(val) => {
@@ -192,99 +441,115 @@ pub fn encode_vtunnel_message(vmsg: &VTunnelMessage) -> String {
     data
 }
 
-pub fn parse_vtunnel_message(raw_msg: &String) -> Option<VTunnelMessage> {
+fn find_type_suffix(raw_msg: &[u8], from: usize) -> Result<usize, VTunnelParseError> {
+    raw_msg[from..]
+        .iter()
+        .position(|&b| b == VTUNNEL_TYPE_SUFFIX as u8)
+        .map(|position| from + position)
+        .ok_or(VTunnelParseError::MissingDelimiter { offset: from })
+}
+
+fn parse_float(data: &str, offset: usize) -> Result<f64, VTunnelParseError> {
+    data.parse::<f64>().map_err(|_| VTunnelParseError::BadNumber { offset, value: data.to_string() })
+}
+
+fn parse_int(data: &str, offset: usize) -> Result<i64, VTunnelParseError> {
+    // Lua doesn't always print whole numbers as integers, so fall back to rounding a float.
+    data.parse::<i64>()
+        .or_else(|_| data.parse::<f64>().map(|val| val.round() as i64))
+        .map_err(|_| VTunnelParseError::BadNumber { offset, value: data.to_string() })
+}
+
+pub fn parse_vtunnel_message(raw_msg: &str) -> Result<VTunnelMessage, VTunnelParseError> {
     if !raw_msg.starts_with(VTUNNEL_PREFIX) {
-        return None;
+        return Err(VTunnelParseError::BadPrefix);
     }
 
-    let raw_msg_chars: Vec<_> = raw_msg.chars().collect();
+    let raw_bytes = raw_msg.as_bytes();
     let mut index = VTUNNEL_PREFIX.len(); // trim prefix
 
-    let id_index = raw_msg_chars[index..].iter().position(|c| *c == VTUNNEL_TYPE_SUFFIX).unwrap_or_default(); // id
-    let msg_id_str: String = raw_msg_chars[index..index + id_index].iter().collect();
-    let msg_id = msg_id_str.parse::<u64>().unwrap_or_default();
-    index += id_index + 1; // trim the delimiter after id
+    let id_end = find_type_suffix(raw_bytes, index)?; // id
+    let msg_id_str = &raw_msg[index..id_end];
+    let msg_id = msg_id_str.parse::<u64>()
+        .map_err(|_| VTunnelParseError::BadId { offset: index, value: msg_id_str.to_string() })?;
+    index = id_end + 1; // trim the delimiter after id
 
-    let name_index = raw_msg_chars[index..].iter().position(|c| *c == VTUNNEL_TYPE_SUFFIX).unwrap_or_default(); // name
-    let msg_name = raw_msg_chars[index..index + name_index].iter().collect();
-    index += name_index + 1; // trim the delimiter after name
-    let mut vmsg = VTunnelMessage::new(msg_name);
+    let name_end = find_type_suffix(raw_bytes, index)?; // name
+    let mut vmsg = VTunnelMessage::new(raw_msg[index..name_end].to_string());
     vmsg.set_id(msg_id);
+    index = name_end + 1; // trim the delimiter after name
+
+    while index < raw_bytes.len() {
+        let type_end = raw_bytes[index..]
+            .iter()
+            .position(|&b| b == VTUNNEL_TYPE_PREFIX as u8)
+            .map(|position| index + position)
+            .ok_or(VTunnelParseError::MissingDelimiter { offset: index })?;
+        let data_type = &raw_msg[index..type_end];
+        let data_index = type_end + 1; // trim the type delimiter
+
+        if let Some(str_len) = data_type.strip_prefix("s(").and_then(|t| t.strip_suffix(')')) {
+            let str_len = str_len.parse::<usize>()
+                .map_err(|_| VTunnelParseError::BadNumber { offset: index + 2, value: str_len.to_string() })?;
+            let available = raw_bytes.len() - data_index;
+            if str_len > available {
+                return Err(VTunnelParseError::TruncatedString { offset: data_index, expected: str_len, available });
+            }
 
-    let mut str_len = 0;
-    let mut data_type = String::new();
-    let mut data = String::new();
-    while index < raw_msg_chars.len() {
-        let c = raw_msg_chars[index];
-
-        if data_type == "" {
-            if c == VTUNNEL_TYPE_PREFIX {
-                data_type = data.clone();
-                if data_type.starts_with("s(") && data_type.ends_with(")") {
-                    str_len = data_type[2..data_type.len() - 1].parse::<usize>().unwrap_or_default();
-                    data_type = "s".to_string();
+            let data = String::from_utf8_lossy(&raw_bytes[data_index..data_index + str_len]).into_owned();
+            vmsg.data.push(VTunnelDataPart::String(data));
+            index = data_index + str_len;
+        } else {
+            let data_end = find_type_suffix(raw_bytes, data_index)?;
+            let data = &raw_msg[data_index..data_end];
+
+            match data_type {
+                "f" => vmsg.data.push(VTunnelDataPart::Float(parse_float(data, data_index)?)),
+                "i" => vmsg.data.push(VTunnelDataPart::Int(parse_int(data, data_index)?)),
+                "v3" => {
+                    let mut components = [0.0; 3];
+                    let mut component_index = data_index;
+                    let mut vec3_data = data.split(',');
+                    for component in components.iter_mut() {
+                        let component_data = vec3_data.next()
+                            .ok_or_else(|| VTunnelParseError::ShortVector { offset: data_index, value: data.to_string() })?;
+                        *component = parse_float(component_data, component_index)?;
+                        component_index += component_data.len() + 1;
+                    }
+
+                    if vec3_data.next().is_some() {
+                        return Err(VTunnelParseError::BadNumber { offset: data_index, value: data.to_string() });
+                    }
+
+                    vmsg.data.push(VTunnelDataPart::Vector3(Vector3::new(components[0], components[1], components[2])));
+                }
+                "b" => match data {
+                    "0" => vmsg.data.push(VTunnelDataPart::Bool(false)),
+                    "1" => vmsg.data.push(VTunnelDataPart::Bool(true)),
+                    _ => return Err(VTunnelParseError::BadBool { offset: data_index, value: data.to_string() }),
+                },
+                _ => {
+                    return Err(VTunnelParseError::UnknownType { offset: index, type_tag: data_type.to_string() });
                 }
-
-                data = String::new();
-                index += 1; // trim the type delimiter
-
-                continue;
             }
 
-            data.push(c);
-            index += 1;
-            continue;
+            index = data_end;
         }
 
-        match data_type.as_str() {
-            "s" => {
-                vmsg.data.push(VTunnelDataPart::String(raw_msg_chars[index..index + str_len].iter().collect()));
-                index += str_len;
-            }
-            "f" => {
-                let end_index = raw_msg_chars[index..].iter().position(|&b| b == VTUNNEL_TYPE_SUFFIX).unwrap_or_default();
-                data = raw_msg[index..index + end_index].to_string();
-                vmsg.data.push(VTunnelDataPart::Float(data.parse::<f64>().unwrap()));
-                index += end_index;
-            }
-            "i" => {
-                let end_index = raw_msg_chars[index..].iter().position(|&b| b == VTUNNEL_TYPE_SUFFIX).unwrap_or_default();
-                data = raw_msg[index..index + end_index].to_string();
-                vmsg.data.push(VTunnelDataPart::Int(data.parse::<i64>().unwrap()));
-                index += end_index;
-            }
-            "v3" => {
-                let end_index = raw_msg_chars[index..].iter().position(|&b| b == VTUNNEL_TYPE_SUFFIX).unwrap_or_default();
-                data = raw_msg[index..index + end_index].to_string();
-                let mut vec3_data = data.split(',');
-                let x = vec3_data.next().unwrap().parse::<f64>().unwrap();
-                let y = vec3_data.next().unwrap().parse::<f64>().unwrap();
-                let z = vec3_data.next().unwrap().parse::<f64>().unwrap();
-                vmsg.data.push(VTunnelDataPart::Vector3(Vector3::new(x, y, z)));
-                index += end_index;
-            }
-            "b" => {
-                let end_index = raw_msg_chars[index..].iter().position(|&b| b == VTUNNEL_TYPE_SUFFIX).unwrap_or_default();
-                data = raw_msg[index..index + end_index].to_string();
-                vmsg.data.push(VTunnelDataPart::Bool(data.parse::<u8>().unwrap() == 1));
-                index += end_index;
-            }
-            _ => {
-                eprintln!("Unknown VTunnel data type: {}", data_type);
-            }
+        if raw_bytes.get(index) != Some(&(VTUNNEL_TYPE_SUFFIX as u8)) {
+            return Err(VTunnelParseError::MissingDelimiter { offset: index });
         }
-
-        data_type = String::new();
-        data = String::new();
         index += 1; // trim the type delimiter
     }
 
-    Some(vmsg)
+    Ok(vmsg)
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant, clippy::bool_assert_comparison)]
 mod tests {
-    use crate::vtunnel::{encode_vtunnel_message, parse_vtunnel_message};
+    use bsi_tools_lib::math::Vector3;
+    use crate::vtunnel::{encode_vtunnel_message, parse_vtunnel_message, VTunnelDataType, VTunnelDeserializable, VTunnelMessage, VTunnelMessageError, VTunnelParseError, VTunnelSerializable};
 
     fn float_equal(a: f64, b: f64) -> bool {
         (a - b).abs() < 1e-6
@@ -295,7 +560,7 @@ mod tests {
         let test_payload = "$vt!69!test!s(5):hello!f:3.141592!i:8192!v3:3.14,5.92,0.314!s(0):!f:-3.14!i:-69!b:1!b:0!".to_string();
         let output = parse_vtunnel_message(&test_payload);
 
-        assert!(output.is_some());
+        assert!(output.is_ok());
         let output = output.unwrap();
         assert_eq!(output.id, 69);
         assert_eq!(output.name, "test");
@@ -320,7 +585,7 @@ mod tests {
         let test_payload = "$vt!0!test!".to_string();
         let output = parse_vtunnel_message(&test_payload);
 
-        assert!(output.is_some());
+        assert!(output.is_ok());
         let output = output.unwrap();
         assert_eq!(output.id, 0);
         assert_eq!(output.name, "test");
@@ -331,9 +596,110 @@ mod tests {
     fn test_decode_encode_vtunnel_message() {
         let input = "$vt!69!test!s(5):hello!f:3.141592!i:8192!v3:3.14,5.92,0.314!s(0):!f:-3.14!i:-69!b:1!b:0!".to_string();
         let decoded = parse_vtunnel_message(&input);
-        assert!(decoded.is_some());
+        assert!(decoded.is_ok());
         let encoded = encode_vtunnel_message(&decoded.unwrap());
         assert_eq!(input, encoded);
         println!("Matched: \n{}\n==\n{}", input, encoded);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_vtunnel_message_int_as_float() {
+        let output = parse_vtunnel_message("$vt!0!test!i:42.0!").unwrap();
+        assert_eq!(output.data[0].get_int().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_parse_vtunnel_message_errors() {
+        assert_eq!(parse_vtunnel_message("hello world"), Err(VTunnelParseError::BadPrefix));
+        assert_eq!(
+            parse_vtunnel_message("$vt!abc!test!"),
+            Err(VTunnelParseError::BadId { offset: 4, value: "abc".to_string() }),
+        );
+        assert_eq!(parse_vtunnel_message("$vt!0!test"), Err(VTunnelParseError::MissingDelimiter { offset: 6 }));
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!x:1!"),
+            Err(VTunnelParseError::UnknownType { offset: 11, type_tag: "x".to_string() }),
+        );
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!s(10):hello!"),
+            Err(VTunnelParseError::TruncatedString { offset: 17, expected: 10, available: 6 }),
+        );
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!f:1.5!f:pi!"),
+            Err(VTunnelParseError::BadNumber { offset: 19, value: "pi".to_string() }),
+        );
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!v3:1,2!"),
+            Err(VTunnelParseError::ShortVector { offset: 14, value: "1,2".to_string() }),
+        );
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!v3:1,y,3!"),
+            Err(VTunnelParseError::BadNumber { offset: 16, value: "y".to_string() }),
+        );
+        assert_eq!(parse_vtunnel_message("$vt!0!test!b:1"), Err(VTunnelParseError::MissingDelimiter { offset: 13 }));
+        assert_eq!(
+            parse_vtunnel_message("$vt!0!test!b:1!b:2!"),
+            Err(VTunnelParseError::BadBool { offset: 17, value: "2".to_string() }),
+        );
+    }
+
+    #[derive(Debug, Default, PartialEq, VTunnelMessage)]
+    #[vtunnel(name = "test_derive", crate = "crate")]
+    struct TestDerive {
+        position: Vector3,
+        label: String,
+        visible: bool,
+        count: u32,
+        scale: f64,
+        note: Option<String>,
+    }
+
+    #[test]
+    fn test_derive_vtunnel_message() {
+        let input = TestDerive {
+            position: Vector3::new(1.0, 2.0, 3.0),
+            label: "liz".to_string(),
+            visible: true,
+            count: 7,
+            scale: 0.5,
+            note: None,
+        };
+
+        let encoded = encode_vtunnel_message(&input.serialize());
+        assert_eq!(encoded, "$vt!0!test_derive!v3:1,2,3!s(3):liz!b:1!i:7!f:0.5!");
+
+        let mut output = TestDerive::default();
+        output.apply_vtunnel_message(&parse_vtunnel_message(&encoded).unwrap()).unwrap();
+        assert_eq!(output, input);
+
+        let with_note = parse_vtunnel_message("$vt!0!test_derive!v3:1,2,3!s(3):liz!b:1!i:7!f:0.5!s(2):hi!").unwrap();
+        output.apply_vtunnel_message(&with_note).unwrap();
+        assert_eq!(output.note, Some("hi".to_string()));
+    }
+
+    #[test]
+    fn test_derive_vtunnel_message_errors() {
+        let mut output = TestDerive::default();
+
+        let missing = parse_vtunnel_message("$vt!0!test_derive!v3:1,2,3!s(3):liz!").unwrap();
+        assert_eq!(
+            output.apply_vtunnel_message(&missing),
+            Err(VTunnelMessageError::MissingField { message: "test_derive".to_string(), field: "visible", index: 2 }),
+        );
+
+        let wrong_type = parse_vtunnel_message("$vt!0!test_derive!v3:1,2,3!s(3):liz!b:1!f:7!f:0.5!").unwrap();
+        assert_eq!(
+            output.apply_vtunnel_message(&wrong_type),
+            Err(VTunnelMessageError::WrongType { message: "test_derive".to_string(), field: "count", index: 3, expected: VTunnelDataType::Int }),
+        );
+
+        let out_of_range = parse_vtunnel_message("$vt!0!test_derive!v3:1,2,3!s(3):liz!b:1!i:-1!f:0.5!").unwrap();
+        assert_eq!(
+            output.apply_vtunnel_message(&out_of_range),
+            Err(VTunnelMessageError::OutOfRange { message: "test_derive".to_string(), field: "count", index: 3 }),
+        );
+
+        // Failed messages shouldn't partially apply.
+        assert_eq!(output, TestDerive::default());
+    }
+}
@@ -1,53 +1,71 @@
 use std::collections::HashMap;
+use std::ops::Deref;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration};
 use tokio::sync::{mpsc, Mutex, oneshot, RwLock};
 use tokio::time::timeout;
-use crate::vconsole;
-use crate::vconsole::Packet;
-use crate::vtunnel::{VTunnelMessage, VTunnelMessageBatch, VTunnelSerializable};
+use crate::vconsole::{Packet, VTunnelMessageBatchPacket, COMMAND_LENGTH_LIMIT};
+use crate::vtunnel::{encode_vtunnel_message, VTunnelFragment, VTunnelMessage, VTunnelMessageBatch, VTunnelSerializable};
 
 pub struct VTunnelEmitter {
     sender: mpsc::Sender<Packet>,
     request_id_sequence: Arc<Mutex<u64>>,
+    fragment_id_sequence: Arc<Mutex<u64>>,
     pending_requests: Arc<RwLock<HashMap<u64, oneshot::Sender<VTunnelMessage>>>>,
     default_timeout: Duration,
+    shutdown: AtomicBool,
 }
 
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl VTunnelEmitter {
     pub fn new(sender: mpsc::Sender<Packet>) -> VTunnelEmitter {
+        VTunnelEmitter::with_timeout(sender, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn with_timeout(sender: mpsc::Sender<Packet>, default_timeout: Duration) -> VTunnelEmitter {
         VTunnelEmitter {
             sender,
             request_id_sequence: Arc::new(Mutex::new(0)),
+            fragment_id_sequence: Arc::new(Mutex::new(0)),
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
-            default_timeout: Duration::from_secs(5),
+            default_timeout,
+            shutdown: AtomicBool::new(false),
+        }
+    }
+
+    async fn send_messages(&self, messages: Vec<VTunnelMessage>) {
+        let packets = {
+            let mut fragment_id_sequence = self.fragment_id_sequence.lock().await;
+            pack_vtunnel_messages(messages, &mut fragment_id_sequence)
+        };
+
+        for packet in packets {
+            // The connection side is gone, nothing left to deliver to.
+            if self.sender.send(packet).await.is_err() {
+                return;
+            }
         }
     }
 
     pub async fn send_vmsg(&self, vmsg: VTunnelMessage) {
-        self.sender.send(vconsole::VTunnelMessagePacket::new(vmsg).to_packet()).await.unwrap();
+        self.send_messages(vec![vmsg]).await;
     }
 
     pub async fn send<T: VTunnelSerializable + Send + 'static>(&self, msg: &T) {
-        let vmsg = msg.serialize();
-        self.sender.send(vconsole::VTunnelMessagePacket::new(vmsg).to_packet()).await.unwrap();
+        self.send_messages(vec![msg.serialize()]).await;
     }
 
     pub async fn send_batch(&self, batch: VTunnelMessageBatch) {
-        let chunk_size = 5; // Command length limit seems to be 512, this might not always work depending on the payload size.
-        let chunked_messages: Vec<Vec<VTunnelMessage>> = batch
-            .messages
-            .chunks(chunk_size)
-            .map(|chunk| chunk.to_vec())
-            .collect();
-
-        for (_, messages) in chunked_messages.iter().enumerate() {
-            let chunked_batch = VTunnelMessageBatch::new_from_vec(messages.to_vec());
-            self.sender.send(vconsole::VTunnelMessageBatchPacket::new(chunked_batch).to_packet()).await.unwrap();
-        }
+        self.send_messages(batch.messages).await;
     }
 
     pub async fn send_request<T: VTunnelSerializable + Send + 'static>(&self, msg: T) -> Result<VTunnelMessage, std::io::Error> {
+        if self.is_shutdown() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "Emitter has been shut down"));
+        }
+
         let mut vmsg = msg.serialize();
         let mut request_id = vmsg.id;
         if request_id == 0 {
@@ -63,14 +81,38 @@ impl VTunnelEmitter {
             pending_requests.insert(request_id, sender);
         }
 
-        self.sender.send(vconsole::VTunnelMessagePacket::new(vmsg).to_packet()).await.unwrap();
+        self.send_messages(vec![vmsg]).await;
 
         match timeout(self.default_timeout, receiver).await {
-            Ok(result) => result.map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Oneshot channel closed")),
-            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Request timed out")),
+            Ok(result) => result.map_err(|_| std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "Connection closed before reply")),
+            Err(_) => {
+                self.pending_requests.write().await.remove(&request_id);
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Request timed out"))
+            }
         }
     }
 
+    /**
+     * Fails every request still waiting on a reply.
+     * Used when the connection drops, since those replies are never coming.
+     */
+    pub async fn cancel_pending_requests(&self) {
+        let mut pending_requests = self.pending_requests.write().await;
+        pending_requests.clear();
+    }
+
+    /**
+     * Stops the emitter for good. Pending and future requests fail instead of waiting on a reply.
+     */
+    pub async fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.cancel_pending_requests().await;
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
     pub async fn handle_reply(&self, vmsg: VTunnelMessage) {
         let mut pending_requests = self.pending_requests.write().await;
         let sender = pending_requests.remove(&vmsg.id);
@@ -79,3 +121,215 @@ impl VTunnelEmitter {
         }
     }
 }
+
+/**
+ * Cheap cloneable reference to a shared emitter, handed to every program that needs to talk to the game.
+ */
+#[derive(Clone)]
+pub struct EmitterHandle {
+    emitter: Arc<VTunnelEmitter>,
+}
+
+impl EmitterHandle {
+    pub fn new(sender: mpsc::Sender<Packet>) -> EmitterHandle {
+        EmitterHandle {
+            emitter: Arc::new(VTunnelEmitter::new(sender)),
+        }
+    }
+
+    pub fn with_timeout(sender: mpsc::Sender<Packet>, default_timeout: Duration) -> EmitterHandle {
+        EmitterHandle {
+            emitter: Arc::new(VTunnelEmitter::with_timeout(sender, default_timeout)),
+        }
+    }
+}
+
+impl Deref for EmitterHandle {
+    type Target = VTunnelEmitter;
+
+    fn deref(&self) -> &VTunnelEmitter {
+        &self.emitter
+    }
+}
+
+/**
+ * Splits an encoded message into fragments that each fit in a console command on their own.
+ */
+fn fragment_vtunnel_message(encoded_msg: &str, fragment_id: u64) -> Vec<VTunnelMessage> {
+    let max_message_length = COMMAND_LENGTH_LIMIT - VTunnelMessageBatchPacket::base_command_length();
+
+    // Worst case overhead of the fragment message around its data. The index/count can't be larger than the message length.
+    let worst_case_fragment = VTunnelFragment {
+        fragment_id,
+        index: encoded_msg.len() as u32,
+        count: encoded_msg.len() as u32,
+        data: String::new(),
+    };
+    let overhead = VTunnelMessageBatchPacket::encoded_message_length(&encode_vtunnel_message(&worst_case_fragment.serialize()))
+        + max_message_length.to_string().len(); // string length prefix grows with the data
+    let chunk_size = max_message_length - overhead;
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < encoded_msg.len() {
+        let mut end = (start + chunk_size).min(encoded_msg.len());
+        while !encoded_msg.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        chunks.push(&encoded_msg[start..end]);
+        start = end;
+    }
+
+    let count = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| VTunnelFragment {
+            fragment_id,
+            index: index as u32,
+            count,
+            data: chunk.to_string(),
+        }.serialize())
+        .collect()
+}
+
+/**
+ * Packs messages into as few console commands as possible without going over the command length limit.
+ * Messages too long for a command of their own are split into fragments.
+ */
+pub fn pack_vtunnel_messages(messages: Vec<VTunnelMessage>, fragment_id_sequence: &mut u64) -> Vec<Packet> {
+    let base_length = VTunnelMessageBatchPacket::base_command_length();
+
+    let mut packets = Vec::new();
+    let mut batch = VTunnelMessageBatch::new();
+    let mut command_length = base_length;
+
+    for vmsg in messages {
+        let encoded_msg = encode_vtunnel_message(&vmsg);
+        let vmsgs = if base_length + VTunnelMessageBatchPacket::encoded_message_length(&encoded_msg) > COMMAND_LENGTH_LIMIT {
+            *fragment_id_sequence += 1;
+            fragment_vtunnel_message(&encoded_msg, *fragment_id_sequence)
+        } else {
+            vec![vmsg]
+        };
+
+        for vmsg in vmsgs {
+            let message_length = VTunnelMessageBatchPacket::encoded_message_length(&encode_vtunnel_message(&vmsg));
+            if command_length + message_length > COMMAND_LENGTH_LIMIT {
+                packets.push(VTunnelMessageBatchPacket::new(batch).to_packet());
+                batch = VTunnelMessageBatch::new();
+                command_length = base_length;
+            }
+
+            batch.add_message(vmsg);
+            command_length += message_length;
+        }
+    }
+
+    if !batch.messages.is_empty() {
+        packets.push(VTunnelMessageBatchPacket::new(batch).to_packet());
+    }
+
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vconsole::{Packet, PacketType, COMMAND_LENGTH_LIMIT, VTUNNEL_RECEIVE_COMMAND};
+    use crate::vtunnel::{encode_vtunnel_message, parse_vtunnel_message, VTunnelDeserializable, VTunnelFragment, VTunnelMessage};
+    use crate::vtunnel_emitter::{pack_vtunnel_messages, EmitterHandle};
+    use tokio::sync::mpsc;
+
+    /**
+     * Pulls the encoded messages back out of a command packet. Only works if the messages don't contain quotes.
+     */
+    fn command_messages(packet: &Packet) -> Vec<String> {
+        assert_eq!(packet.packet_type, PacketType::COMMAND);
+        assert_eq!(*packet.data.last().unwrap(), 0);
+        assert!(packet.data.len() - 2 <= COMMAND_LENGTH_LIMIT, "command is {} bytes", packet.data.len() - 2);
+
+        let command = String::from_utf8(packet.data[2..packet.data.len() - 1].to_vec()).unwrap();
+        let args = command.strip_prefix(VTUNNEL_RECEIVE_COMMAND).unwrap();
+        args.split('"').filter(|arg| !arg.trim().is_empty()).map(|arg| arg.to_string()).collect()
+    }
+
+    fn reassemble(fragments: &[String]) -> String {
+        let mut fragments: Vec<VTunnelFragment> = fragments
+            .iter()
+            .map(|encoded| {
+                let mut fragment = VTunnelFragment { fragment_id: 0, index: 0, count: 0, data: String::new() };
+                fragment.apply_vtunnel_message(&parse_vtunnel_message(encoded).unwrap()).unwrap();
+                fragment
+            })
+            .collect();
+
+        assert_eq!(fragments.len() as u32, fragments[0].count);
+        fragments.sort_by_key(|fragment| fragment.index);
+        fragments.into_iter().map(|fragment| fragment.data).collect()
+    }
+
+    #[test]
+    fn test_pack_small_messages() {
+        let messages: Vec<VTunnelMessage> = (0..100)
+            .map(|i| {
+                let mut vmsg = VTunnelMessage::new("draw_nav_point".to_string());
+                vmsg.add_int(i);
+                vmsg.add_vector3(bsi_tools_lib::math::Vector3::new(-1234.5, 678.25, 16.0));
+                vmsg.add_int(1);
+                vmsg
+            })
+            .collect();
+        let expected: Vec<String> = messages.iter().map(encode_vtunnel_message).collect();
+
+        let mut fragment_id_sequence = 0;
+        let packets = pack_vtunnel_messages(messages, &mut fragment_id_sequence);
+        let sent: Vec<String> = packets.iter().flat_map(command_messages).collect();
+
+        assert!(packets.len() < 100);
+        assert_eq!(sent, expected);
+        assert_eq!(fragment_id_sequence, 0);
+    }
+
+    #[test]
+    fn test_pack_oversized_message() {
+        let mut long_vmsg = VTunnelMessage::new("liz_say".to_string());
+        long_vmsg.add_string("Booker, are you afraid of God? ".repeat(60));
+        long_vmsg.add_string("ÆØÅ €€€ ".repeat(100));
+        let mut small_vmsg = VTunnelMessage::new("world_state".to_string());
+        small_vmsg.add_float(12.5);
+
+        let expected_long = encode_vtunnel_message(&long_vmsg);
+        let expected_small = encode_vtunnel_message(&small_vmsg);
+
+        let mut fragment_id_sequence = 0;
+        let packets = pack_vtunnel_messages(vec![long_vmsg, small_vmsg], &mut fragment_id_sequence);
+        let sent: Vec<String> = packets.iter().flat_map(command_messages).collect();
+
+        assert!(packets.len() > 1);
+        assert_eq!(fragment_id_sequence, 1);
+        assert_eq!(sent.last().unwrap(), &expected_small);
+        assert_eq!(reassemble(&sent[..sent.len() - 1]), expected_long);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_pending_requests() {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let emitter = EmitterHandle::new(sender);
+
+        let request_emitter = emitter.clone();
+        let request = tokio::spawn(async move {
+            request_emitter.send_request(VTunnelFragment { fragment_id: 1, index: 0, count: 1, data: String::new() }).await
+        });
+
+        // Wait for the request to go out so it's pending before shutting down.
+        receiver.recv().await.unwrap();
+        emitter.shutdown().await;
+
+        let err = request.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+
+        let err = emitter.send_request(VTunnelFragment { fragment_id: 2, index: 0, count: 1, data: String::new() }).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+    }
+}
@@ -2,9 +2,7 @@ import Tool from "../Tool";
 import TickDelta from "../../utils/TickDelta";
 import {VTunnel, VTunnelMessage, VTunnelSerializable} from "../../vconsole_tunnel/VTunnel";
 import {LineTrace} from "../../utils/Trace";
-
-const gadgetActivatedMessage = new VTunnelMessage(VTunnelMessage.NO_ID, "gadget_activated");
-const gadgetDeactivatedMessage = new VTunnelMessage(VTunnelMessage.NO_ID, "gadget_deactivated");
+import {decodeGadgetState, decodeNavEditHistory, decodeNavFillProgress, decodeNavLayerState, encodeGadgetActivated, encodeGadgetDeactivated} from "../../vconsole_tunnel/VTunnelMessages";
 
 /**
  * This tool captures detailed controller input for VTunnel extraction.
@@ -18,6 +16,7 @@ export default class VTunnelGadget extends Tool {
 
     private trace: LineTrace;
     private gadgetColor: Vector;
+    private statusText: string = "";
 
     constructor(entity: CDestinationsPropTool) {
         super(entity);
@@ -25,16 +24,51 @@ export default class VTunnelGadget extends Tool {
         this.gadgetColor = Vector(0, 0, 0);
 
         VTunnel.onMessage('vtunnel_connected',(msg: VTunnelMessage) => {
+            // Gadgets are tracked per player, so there's nothing to report for a gadget nobody is holding.
             if (this.isEquipped) {
-                VTunnel.send(gadgetActivatedMessage);
-            } else {
-                VTunnel.send(gadgetDeactivatedMessage);
+                VTunnel.send(encodeGadgetActivated(VTunnelMessage.NO_ID, {userId: this.player!.GetUserID()}));
             }
         });
 
         VTunnel.onMessage('gadget_state',(msg: VTunnelMessage) => {
-            // const state = msg.indexPartDataAsInt(0);
-            this.gadgetColor = msg.indexPartDataAsVector(1);
+            const state = decodeGadgetState(msg);
+            if (this.player !== null && state.userId === this.player.GetUserID()) {
+                this.gadgetColor = state.color;
+            }
+        });
+
+        VTunnel.onMessage('nav_fill_progress',(msg: VTunnelMessage) => {
+            const progress = decodeNavFillProgress(msg);
+            if (this.player === null || progress.userId !== this.player.GetUserID()) {
+                return;
+            }
+
+            if (progress.cancelled) {
+                this.statusText = `Fill cancelled, ${progress.added} added`;
+            } else if (progress.done) {
+                this.statusText = `Fill done, ${progress.added} added`;
+            } else {
+                this.statusText = `Filling... ${progress.added} added, ${progress.queued} queued`;
+            }
+        });
+
+        VTunnel.onMessage('nav_edit_history',(msg: VTunnelMessage) => {
+            const history = decodeNavEditHistory(msg);
+            if (this.player === null || history.userId !== this.player.GetUserID()) {
+                return;
+            }
+
+            this.statusText = `${history.lastAction} (${history.undoCount} undo, ${history.redoCount} redo)`;
+        });
+
+        VTunnel.onMessage('nav_layer_state',(msg: VTunnelMessage) => {
+            const layerState = decodeNavLayerState(msg);
+            if (this.player === null || layerState.userId !== this.player.GetUserID()) {
+                return;
+            }
+
+            const hidden = layerState.activeLayerHidden ? " (hidden)" : "";
+            this.statusText = `Layer ${layerState.activeLayer}${hidden} on ${layerState.mapName}, ${layerState.hiddenCount} hidden`;
         });
 
         VTunnel.onMessage('draw_nav_point',(msg: VTunnelMessage) => {
@@ -44,9 +78,15 @@ export default class VTunnelGadget extends Tool {
 
             let color: Vector;
             if (navType === 0) {
-                color = Vector(0, 255, 0);
+                color = Vector(0, 255, 0); // walkable
             } else if (navType === 1) {
-                color = Vector(255, 0, 0);
+                color = Vector(255, 0, 0); // obstacle
+            } else if (navType === 2) {
+                color = Vector(0, 128, 255); // jump
+            } else if (navType === 3) {
+                color = Vector(160, 0, 255); // crouch
+            } else if (navType === 4) {
+                color = Vector(255, 128, 0); // no Elizabeth
             } else {
                 color = Vector(0, 0, 0);
             }
@@ -63,7 +103,7 @@ export default class VTunnelGadget extends Tool {
         this.player = player;
 
         this.player.SetContentBrowserAllowed(false);
-        VTunnel.send(gadgetActivatedMessage);
+        VTunnel.send(encodeGadgetActivated(VTunnelMessage.NO_ID, {userId: player.GetUserID()}));
         EmitSoundOn('skyhook_equip', this.entity);
 
         return true;
@@ -71,6 +111,7 @@ export default class VTunnelGadget extends Tool {
 
     public drop(): boolean {
         this.player!.SetContentBrowserAllowed(true);
+        VTunnel.send(encodeGadgetDeactivated(VTunnelMessage.NO_ID, {userId: this.player!.GetUserID()}));
 
         this.isEquipped = false;
         this.hand = null;
@@ -78,8 +119,6 @@ export default class VTunnelGadget extends Tool {
         this.handAttachment = null;
         this.player = null;
 
-        VTunnel.send(gadgetDeactivatedMessage);
-
         return true;
     }
 
@@ -95,6 +134,9 @@ export default class VTunnelGadget extends Tool {
         if (traceResult.hasHit()) {
             DebugDrawLine(toolPos, traceResult.getHitPosition(), this.gadgetColor.x, this.gadgetColor.y, this.gadgetColor.z, false, 0);
         }
+        if (this.statusText !== "") {
+            DebugDrawText(toolPos, this.statusText, false, 0);
+        }
 
         const vmsg = new VTunnelMessage(VTunnelMessage.NO_ID, "player_input");
         vmsg.writeInt(this.player!.GetUserID());
@@ -1,13 +1,18 @@
 import {VTunnel, VTunnelMessage} from "./VTunnel";
 import {BoxTrace, LineTrace} from "../utils/Trace";
+import {decodeBoxTrace, decodeDrawDebugLine, decodeDrawDebugSphere, decodeLineTrace, encodeTraceResult} from "./VTunnelMessages";
+import {registerNavPointSyncHandlers} from "./NavPointSync";
 
 export function regigerDefaultVTunnelMessageHandlers() {
     VTunnel.onMessage("vtunnel_request_handshake", handleVTunnelRequestHandshake);
     VTunnel.onMessage("vtunnel_handshake", handleVTunnelHandshake);
     VTunnel.onMessage("vtunnel_connected", handleVTunnelConnected);
     VTunnel.onMessage("draw_debug_sphere", handleDrawDebugSphere);
+    VTunnel.onMessage("draw_debug_line", handleDrawDebugLine);
     VTunnel.onMessage("line_trace", handleLineTrace);
     VTunnel.onMessage("box_trace", handleBoxTrace);
+    VTunnel.onMessage("trace_batch", handleTraceBatch);
+    registerNavPointSyncHandlers();
 }
 
 /**
@@ -25,6 +30,7 @@ export function handleVTunnelRequestHandshake(vmsg: VTunnelMessage) {
  * The client here echos the session ID back to the server.
  */
 export function handleVTunnelHandshake(vmsg: VTunnelMessage) {
+    VTunnel.startSession(vmsg.getID());
     VTunnel.send(new VTunnelMessage(vmsg.getID(), "vtunnel_handshake"));
 }
 
@@ -37,22 +43,17 @@ export function handleVTunnelConnected(vmsg: VTunnelMessage) {
 }
 
 export function handleDrawDebugSphere(vmsg: VTunnelMessage){
-    const position = vmsg.indexPartDataAsVector(0);
-    const color= vmsg.indexPartDataAsVector(1);
-    const colorAlpha = vmsg.indexPartDataAsFloat(2);
-    const radius = vmsg.indexPartDataAsFloat(3);
-    const zTest = vmsg.indexPartDataAsBoolean(4);
-    const durationSeconds = vmsg.indexPartDataAsFloat(5);
-
-    DebugDrawSphere(position, color, colorAlpha, radius, zTest, durationSeconds);
+    const sphere = decodeDrawDebugSphere(vmsg);
+    DebugDrawSphere(sphere.position, sphere.color, sphere.colorAlpha, sphere.radius, sphere.zTest, sphere.durationSeconds);
+}
+
+export function handleDrawDebugLine(vmsg: VTunnelMessage){
+    const line = decodeDrawDebugLine(vmsg);
+    DebugDrawLine(line.startPosition, line.endPosition, line.color.x, line.color.y, line.color.z, line.zTest, line.durationSeconds);
 }
 
 export function handleLineTrace(vmsg: VTunnelMessage){
-    const startPosition = vmsg.indexPartDataAsVector(0);
-    const endPosition = vmsg.indexPartDataAsVector(1);
-    const mask = vmsg.indexPartDataAsInt(2);
-    const ignoreEntityID = vmsg.indexPartDataAsInt(3);
-    const drawDebug = vmsg.indexPartDataAsBoolean(4);
+    const {startPosition, endPosition, mask, ignoreEntityId: ignoreEntityID, drawDebug} = decodeLineTrace(vmsg);
 
     const trace = new LineTrace(startPosition, endPosition);
     trace.setMask(mask);
@@ -65,18 +66,18 @@ export function handleLineTrace(vmsg: VTunnelMessage){
     }
 
     const result = trace.run();
-    const vmsgResult = new VTunnelMessage(vmsg.getID(), "line_trace_result");
-    vmsgResult.writeBoolean(result.hasHit());
-    vmsgResult.writeVector(result.getHitPosition());
-    vmsgResult.writeVector(result.getHitNormal());
     let hitEntityID = 0;
     if (result.hasHit() && result.hasEntityHit()) {
         hitEntityID = result.getEntityHit()!.GetEntityIndex();
     }
-    vmsgResult.writeInt(hitEntityID);
-    vmsgResult.writeBoolean(result.didStartInSolid());
-    vmsgResult.writeFloat(result.getFraction());
-    VTunnel.send(vmsgResult);
+    VTunnel.send(encodeTraceResult(vmsg.getID(), {
+        hit: result.hasHit(),
+        hitPosition: result.getHitPosition(),
+        hitNormal: result.getHitNormal(),
+        hitEntityId: hitEntityID,
+        startInSolid: result.didStartInSolid(),
+        fraction: result.getFraction(),
+    }, "line_trace_result"));
 
     if (drawDebug) {
         DebugDrawLine(trace.getStartPosition(), result.getHitPosition(), 255, 0, 0, false, 5);
@@ -84,13 +85,7 @@ export function handleLineTrace(vmsg: VTunnelMessage){
 }
 
 export function handleBoxTrace(vmsg: VTunnelMessage){
-    const startPosition = vmsg.indexPartDataAsVector(0);
-    const endPosition = vmsg.indexPartDataAsVector(1);
-    const mins = vmsg.indexPartDataAsVector(2);
-    const maxs = vmsg.indexPartDataAsVector(3);
-    const mask = vmsg.indexPartDataAsInt(4);
-    const ignoreEntityID = vmsg.indexPartDataAsInt(5);
-    const drawDebug = vmsg.indexPartDataAsBoolean(6);
+    const {startPosition, endPosition, mins, maxs, mask, ignoreEntityId: ignoreEntityID, drawDebug} = decodeBoxTrace(vmsg);
 
     const trace = new BoxTrace(startPosition, endPosition, mins, maxs);
     trace.setMask(mask);
@@ -103,20 +98,66 @@ export function handleBoxTrace(vmsg: VTunnelMessage){
     }
 
     const result = trace.run();
-    const vmsgResult = new VTunnelMessage(vmsg.getID(), "box_trace_result");
-    vmsgResult.writeBoolean(result.hasHit());
-    vmsgResult.writeVector(result.getHitPosition());
-    vmsgResult.writeVector(result.getHitNormal());
     let hitEntityID = 0;
     if (result.hasHit() && result.hasEntityHit()) {
         hitEntityID = result.getEntityHit()!.GetEntityIndex();
     }
-    vmsgResult.writeInt(hitEntityID);
-    vmsgResult.writeBoolean(result.didStartInSolid());
-    vmsgResult.writeFloat(result.getFraction());
-    VTunnel.send(vmsgResult);
+    VTunnel.send(encodeTraceResult(vmsg.getID(), {
+        hit: result.hasHit(),
+        hitPosition: result.getHitPosition(),
+        hitNormal: result.getHitNormal(),
+        hitEntityId: hitEntityID,
+        startInSolid: result.didStartInSolid(),
+        fraction: result.getFraction(),
+    }, "box_trace_result"));
 
     if (drawDebug) {
         DebugDrawBox(trace.getStartPosition(), mins, maxs, 255, 0, 0, 1, 5);
     }
 }
+
+/**
+ * Runs every trace in a batch and sends all the results back in one message.
+ * Layout is documented on `TraceBatch` in vtunnel/src/game/trace.rs.
+ */
+export function handleTraceBatch(vmsg: VTunnelMessage) {
+    const mask = vmsg.indexPartDataAsInt(0);
+    const ignoreEntityID = vmsg.indexPartDataAsInt(1);
+    const ignoreEntity = ignoreEntityID !== 0 ? EntIndexToHScript(ignoreEntityID) : undefined;
+    const drawDebug = vmsg.indexPartDataAsBoolean(2);
+    const traceCount = Math.floor((vmsg.getPartCount() - 3) / 4);
+
+    const reply = new VTunnelMessage(vmsg.getID(), "trace_batch_result");
+    reply.writeInt(traceCount);
+    for (let i = 0; i < traceCount; i++) {
+        const startPosition = vmsg.indexPartDataAsVector(3 + i * 4);
+        const endPosition = vmsg.indexPartDataAsVector(4 + i * 4);
+        const mins = vmsg.indexPartDataAsVector(5 + i * 4);
+        const maxs = vmsg.indexPartDataAsVector(6 + i * 4);
+
+        const isLine = mins.Length() === 0 && maxs.Length() === 0;
+        const trace = isLine ? new LineTrace(startPosition, endPosition) : new BoxTrace(startPosition, endPosition, mins, maxs);
+        trace.setMask(mask);
+        if (ignoreEntity) {
+            trace.setIgnoreEntity(ignoreEntity);
+        }
+
+        const result = trace.run();
+        let hitEntityID = 0;
+        if (result.hasHit() && result.hasEntityHit()) {
+            hitEntityID = result.getEntityHit()!.GetEntityIndex();
+        }
+        reply.writeBoolean(result.hasHit());
+        reply.writeVector(result.getHitPosition());
+        reply.writeVector(result.getHitNormal());
+        reply.writeInt(hitEntityID);
+        reply.writeBoolean(result.didStartInSolid());
+        reply.writeFloat(result.getFraction());
+
+        if (drawDebug) {
+            DebugDrawLine(startPosition, result.getHitPosition(), 255, 0, 0, false, 5);
+        }
+    }
+
+    VTunnel.send(reply);
+}
@@ -0,0 +1,67 @@
+import {VTunnel, VTunnelMessage} from "./VTunnel";
+import {encodeNavSyncChecksum} from "./VTunnelMessages";
+
+// Same as CHECKSUM_MODULUS in vtunnel/src/nav_builder/nav_sync.rs.
+const CHECKSUM_MODULUS = 2147483647;
+
+interface NavPoint {
+    position: Vector;
+    navType: number;
+}
+
+/**
+ * The script's copy of the nav builder's nav points, by id.
+ * VTunnel only sends what changed and checks it against `nav_sync_checksum`, see `NavPointSync` in nav_sync.rs.
+ */
+const navPoints = new Map<number, NavPoint>();
+
+export function registerNavPointSyncHandlers() {
+    VTunnel.onMessage("clear_nav_points", handleClearNavPoints);
+    VTunnel.onMessage("add_nav_point", handleAddNavPoint);
+    VTunnel.onMessage("remove_nav_point", handleRemoveNavPoint);
+    VTunnel.onMessage("nav_sync_checksum", handleNavSyncChecksum);
+}
+
+export function getNavPoints(): ReadonlyMap<number, NavPoint> {
+    return navPoints;
+}
+
+export function handleClearNavPoints(vmsg: VTunnelMessage) {
+    navPoints.clear();
+}
+
+/**
+ * Adds a nav point, or updates the one with the same id.
+ */
+export function handleAddNavPoint(vmsg: VTunnelMessage) {
+    navPoints.set(vmsg.indexPartDataAsInt(0), {
+        position: vmsg.indexPartDataAsVector(1),
+        navType: vmsg.indexPartDataAsInt(2),
+    });
+}
+
+export function handleRemoveNavPoint(vmsg: VTunnelMessage) {
+    navPoints.delete(vmsg.indexPartDataAsInt(0));
+}
+
+function modulo(value: number): number {
+    return ((value % CHECKSUM_MODULUS) + CHECKSUM_MODULUS) % CHECKSUM_MODULUS;
+}
+
+/**
+ * Answers with the checksum of every nav point, computed the same way as `NavSyncChecksum::of`.
+ */
+export function handleNavSyncChecksum(vmsg: VTunnelMessage) {
+    let checksum = 0;
+    navPoints.forEach((navPoint, id) => {
+        const {position, navType} = navPoint;
+        const parts = [id, Math.floor(position.x + 0.5), Math.floor(position.y + 0.5), Math.floor(position.z + 0.5), navType];
+        let hash = 0;
+        for (const part of parts) {
+            hash = (hash * 31 + modulo(part)) % CHECKSUM_MODULUS;
+        }
+        checksum = (checksum + hash) % CHECKSUM_MODULUS;
+    });
+
+    VTunnel.send(encodeNavSyncChecksum(vmsg.getID(), {count: navPoints.size, checksum}));
+}
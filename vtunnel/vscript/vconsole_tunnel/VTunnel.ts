@@ -108,11 +108,15 @@ export abstract class VTunnel {
     public static readonly VTUNNEL_ENABLED_CONVAR = "vtunnel_enabled";
     public static readonly VTUNNEL_RECEIVE_COMMAND = "vtunnel_receive";
     public static readonly VTUNNEL_GAME_EVENT_NAME = "vtunnel_receive";
+    public static readonly VTUNNEL_FRAGMENT_NAME = "vtunnel_fragment";
 
     public static readonly VTUNNEL_PREFIX = "$vt!";
     public static readonly VTUNNEL_TYPE_PREFIX = ":";
     public static readonly VTUNNEL_TYPE_SUFFIX = "!";
 
+    private static sessionID = 0;
+    private static fragments: { [sessionFragmentID: string]: { count: number, received: number, parts: string[] } } = {};
+
     private constructor() {
     }
 
@@ -125,11 +129,64 @@ export abstract class VTunnel {
             }
 
             for (let arg of args) {
-                FireGameEvent(VTunnel.VTUNNEL_GAME_EVENT_NAME, { payload: arg });
+                const payload = VTunnel.reassemble(arg);
+                if (payload !== null) {
+                    FireGameEvent(VTunnel.VTUNNEL_GAME_EVENT_NAME, { payload });
+                }
             }
         }, "Receives a VTunnel message and forwards it to a game event", ConVarFlags.FCVAR_HIDDEN_AND_UNLOGGED);
     }
 
+    /**
+     * Messages too long for one console command are split into fragments by the server.
+     * Returns the payload once all fragments have arrived, or null while still waiting on the rest.
+     * Regular messages are returned as-is.
+     */
+    private static reassemble(payload: string): string | null {
+        const fragmentPrefix = VTunnel.VTUNNEL_PREFIX + VTunnelMessage.NO_ID + VTunnel.VTUNNEL_TYPE_SUFFIX +
+            VTunnel.VTUNNEL_FRAGMENT_NAME + VTunnel.VTUNNEL_TYPE_SUFFIX;
+        if (!payload.startsWith(fragmentPrefix)) {
+            return payload;
+        }
+
+        const vmsg = VTunnel.receive(payload);
+        if (!vmsg) {
+            return null;
+        }
+
+        // Fragment ids start over with every server session, so a restarted server can't complete an old message.
+        const fragmentID = VTunnel.sessionID + ":" + vmsg.indexPartDataAsInt(0);
+        const index = vmsg.indexPartDataAsInt(1);
+        const count = vmsg.indexPartDataAsInt(2);
+        const data = vmsg.indexPartDataAsString(3);
+
+        let fragment = VTunnel.fragments[fragmentID];
+        if (!fragment) {
+            fragment = { count, received: 0, parts: [] };
+            VTunnel.fragments[fragmentID] = fragment;
+        }
+
+        if (fragment.parts[index] === undefined) {
+            fragment.parts[index] = data;
+            fragment.received++;
+        }
+
+        if (fragment.received < fragment.count) {
+            return null;
+        }
+
+        delete VTunnel.fragments[fragmentID];
+        return fragment.parts.join('');
+    }
+
+    /**
+     * Starts a new server session on handshake. Fragments still pending from the last one are dropped.
+     */
+    public static startSession(sessionID: number): void {
+        VTunnel.sessionID = sessionID;
+        VTunnel.fragments = {};
+    }
+
     /**
      * If the VTunnel server is already running, we need to request a new handshake.
      */
@@ -0,0 +1,461 @@
+// CODE GENERATED VTUNNEL MESSAGE SCHEMAS. DO NOT EDIT.
+
+import {VTunnelMessage} from "./VTunnel";
+
+export interface WorldStateMessage {
+    serverTime: number;
+}
+
+export function encodeWorldState(id: number, data: WorldStateMessage, name: string = "world_state"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeFloat(data.serverTime);
+    return vmsg;
+}
+
+export function decodeWorldState(vmsg: VTunnelMessage): WorldStateMessage {
+    return {
+        serverTime: vmsg.indexPartDataAsFloat(0),
+    };
+}
+
+export interface ElizabethMessage {
+    position: Vector;
+    rotation: Vector;
+    currentState: string;
+}
+
+export function encodeElizabeth(id: number, data: ElizabethMessage, name: string = "liz_state"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeVector(data.position);
+    vmsg.writeVector(data.rotation);
+    vmsg.writeString(data.currentState);
+    return vmsg;
+}
+
+export function decodeElizabeth(vmsg: VTunnelMessage): ElizabethMessage {
+    return {
+        position: vmsg.indexPartDataAsVector(0),
+        rotation: vmsg.indexPartDataAsVector(1),
+        currentState: vmsg.indexPartDataAsString(2),
+    };
+}
+
+export interface PlayerMessage {
+    steamId: string;
+    userId: number;
+    name: string;
+    position: Vector;
+    rotation: Vector;
+}
+
+export function encodePlayer(id: number, data: PlayerMessage, name: string = "player_state"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeString(data.steamId);
+    vmsg.writeInt(data.userId);
+    vmsg.writeString(data.name);
+    vmsg.writeVector(data.position);
+    vmsg.writeVector(data.rotation);
+    return vmsg;
+}
+
+export function decodePlayer(vmsg: VTunnelMessage): PlayerMessage {
+    return {
+        steamId: vmsg.indexPartDataAsString(0),
+        userId: vmsg.indexPartDataAsInt(1),
+        name: vmsg.indexPartDataAsString(2),
+        position: vmsg.indexPartDataAsVector(3),
+        rotation: vmsg.indexPartDataAsVector(4),
+    };
+}
+
+export interface PlayerInputMessage {
+    userId: number;
+    hand: number;
+    handPosition: Vector;
+    handRotation: Vector;
+    traceHit: boolean;
+    traceFraction: number;
+    traceNormal: Vector;
+    tracePosition: Vector;
+    buttonsDown: number;
+    buttonsPressed: number;
+    buttonsReleased: number;
+    joystickX: number;
+    joystickY: number;
+    trackpadX: number;
+    trackpadY: number;
+    trigger: number;
+}
+
+export function encodePlayerInput(id: number, data: PlayerInputMessage, name: string = "player_input"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeInt(data.hand);
+    vmsg.writeVector(data.handPosition);
+    vmsg.writeVector(data.handRotation);
+    vmsg.writeBoolean(data.traceHit);
+    vmsg.writeFloat(data.traceFraction);
+    vmsg.writeVector(data.traceNormal);
+    vmsg.writeVector(data.tracePosition);
+    vmsg.writeInt(data.buttonsDown);
+    vmsg.writeInt(data.buttonsPressed);
+    vmsg.writeInt(data.buttonsReleased);
+    vmsg.writeFloat(data.joystickX);
+    vmsg.writeFloat(data.joystickY);
+    vmsg.writeFloat(data.trackpadX);
+    vmsg.writeFloat(data.trackpadY);
+    vmsg.writeFloat(data.trigger);
+    return vmsg;
+}
+
+export function decodePlayerInput(vmsg: VTunnelMessage): PlayerInputMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        hand: vmsg.indexPartDataAsInt(1),
+        handPosition: vmsg.indexPartDataAsVector(2),
+        handRotation: vmsg.indexPartDataAsVector(3),
+        traceHit: vmsg.indexPartDataAsBoolean(4),
+        traceFraction: vmsg.indexPartDataAsFloat(5),
+        traceNormal: vmsg.indexPartDataAsVector(6),
+        tracePosition: vmsg.indexPartDataAsVector(7),
+        buttonsDown: vmsg.indexPartDataAsInt(8),
+        buttonsPressed: vmsg.indexPartDataAsInt(9),
+        buttonsReleased: vmsg.indexPartDataAsInt(10),
+        joystickX: vmsg.indexPartDataAsFloat(11),
+        joystickY: vmsg.indexPartDataAsFloat(12),
+        trackpadX: vmsg.indexPartDataAsFloat(13),
+        trackpadY: vmsg.indexPartDataAsFloat(14),
+        trigger: vmsg.indexPartDataAsFloat(15),
+    };
+}
+
+export interface DrawDebugSphereMessage {
+    position: Vector;
+    color: Vector;
+    colorAlpha: number;
+    radius: number;
+    zTest: boolean;
+    durationSeconds: number;
+}
+
+export function encodeDrawDebugSphere(id: number, data: DrawDebugSphereMessage, name: string = "draw_debug_sphere"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeVector(data.position);
+    vmsg.writeVector(data.color);
+    vmsg.writeFloat(data.colorAlpha);
+    vmsg.writeFloat(data.radius);
+    vmsg.writeBoolean(data.zTest);
+    vmsg.writeFloat(data.durationSeconds);
+    return vmsg;
+}
+
+export function decodeDrawDebugSphere(vmsg: VTunnelMessage): DrawDebugSphereMessage {
+    return {
+        position: vmsg.indexPartDataAsVector(0),
+        color: vmsg.indexPartDataAsVector(1),
+        colorAlpha: vmsg.indexPartDataAsFloat(2),
+        radius: vmsg.indexPartDataAsFloat(3),
+        zTest: vmsg.indexPartDataAsBoolean(4),
+        durationSeconds: vmsg.indexPartDataAsFloat(5),
+    };
+}
+
+export interface DrawDebugLineMessage {
+    startPosition: Vector;
+    endPosition: Vector;
+    color: Vector;
+    zTest: boolean;
+    durationSeconds: number;
+}
+
+export function encodeDrawDebugLine(id: number, data: DrawDebugLineMessage, name: string = "draw_debug_line"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeVector(data.startPosition);
+    vmsg.writeVector(data.endPosition);
+    vmsg.writeVector(data.color);
+    vmsg.writeBoolean(data.zTest);
+    vmsg.writeFloat(data.durationSeconds);
+    return vmsg;
+}
+
+export function decodeDrawDebugLine(vmsg: VTunnelMessage): DrawDebugLineMessage {
+    return {
+        startPosition: vmsg.indexPartDataAsVector(0),
+        endPosition: vmsg.indexPartDataAsVector(1),
+        color: vmsg.indexPartDataAsVector(2),
+        zTest: vmsg.indexPartDataAsBoolean(3),
+        durationSeconds: vmsg.indexPartDataAsFloat(4),
+    };
+}
+
+export interface LineTraceMessage {
+    startPosition: Vector;
+    endPosition: Vector;
+    mask: number;
+    ignoreEntityId: number;
+    drawDebug: boolean;
+}
+
+export function encodeLineTrace(id: number, data: LineTraceMessage, name: string = "line_trace"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeVector(data.startPosition);
+    vmsg.writeVector(data.endPosition);
+    vmsg.writeInt(data.mask);
+    vmsg.writeInt(data.ignoreEntityId);
+    vmsg.writeBoolean(data.drawDebug);
+    return vmsg;
+}
+
+export function decodeLineTrace(vmsg: VTunnelMessage): LineTraceMessage {
+    return {
+        startPosition: vmsg.indexPartDataAsVector(0),
+        endPosition: vmsg.indexPartDataAsVector(1),
+        mask: vmsg.indexPartDataAsInt(2),
+        ignoreEntityId: vmsg.indexPartDataAsInt(3),
+        drawDebug: vmsg.indexPartDataAsBoolean(4),
+    };
+}
+
+export interface BoxTraceMessage {
+    startPosition: Vector;
+    endPosition: Vector;
+    mins: Vector;
+    maxs: Vector;
+    mask: number;
+    ignoreEntityId: number;
+    drawDebug: boolean;
+}
+
+export function encodeBoxTrace(id: number, data: BoxTraceMessage, name: string = "box_trace"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeVector(data.startPosition);
+    vmsg.writeVector(data.endPosition);
+    vmsg.writeVector(data.mins);
+    vmsg.writeVector(data.maxs);
+    vmsg.writeInt(data.mask);
+    vmsg.writeInt(data.ignoreEntityId);
+    vmsg.writeBoolean(data.drawDebug);
+    return vmsg;
+}
+
+export function decodeBoxTrace(vmsg: VTunnelMessage): BoxTraceMessage {
+    return {
+        startPosition: vmsg.indexPartDataAsVector(0),
+        endPosition: vmsg.indexPartDataAsVector(1),
+        mins: vmsg.indexPartDataAsVector(2),
+        maxs: vmsg.indexPartDataAsVector(3),
+        mask: vmsg.indexPartDataAsInt(4),
+        ignoreEntityId: vmsg.indexPartDataAsInt(5),
+        drawDebug: vmsg.indexPartDataAsBoolean(6),
+    };
+}
+
+export interface TraceResultMessage {
+    hit: boolean;
+    hitPosition: Vector;
+    hitNormal: Vector;
+    hitEntityId: number;
+    startInSolid: boolean;
+    fraction: number;
+}
+
+export function encodeTraceResult(id: number, data: TraceResultMessage, name: string = "trace_result"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeBoolean(data.hit);
+    vmsg.writeVector(data.hitPosition);
+    vmsg.writeVector(data.hitNormal);
+    vmsg.writeInt(data.hitEntityId);
+    vmsg.writeBoolean(data.startInSolid);
+    vmsg.writeFloat(data.fraction);
+    return vmsg;
+}
+
+export function decodeTraceResult(vmsg: VTunnelMessage): TraceResultMessage {
+    return {
+        hit: vmsg.indexPartDataAsBoolean(0),
+        hitPosition: vmsg.indexPartDataAsVector(1),
+        hitNormal: vmsg.indexPartDataAsVector(2),
+        hitEntityId: vmsg.indexPartDataAsInt(3),
+        startInSolid: vmsg.indexPartDataAsBoolean(4),
+        fraction: vmsg.indexPartDataAsFloat(5),
+    };
+}
+
+export interface GadgetStateMessage {
+    userId: number;
+    programName: string;
+    mode: number;
+    color: Vector;
+}
+
+export function encodeGadgetState(id: number, data: GadgetStateMessage, name: string = "gadget_state"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeString(data.programName);
+    vmsg.writeInt(data.mode);
+    vmsg.writeVector(data.color);
+    return vmsg;
+}
+
+export function decodeGadgetState(vmsg: VTunnelMessage): GadgetStateMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        programName: vmsg.indexPartDataAsString(1),
+        mode: vmsg.indexPartDataAsInt(2),
+        color: vmsg.indexPartDataAsVector(3),
+    };
+}
+
+export interface GadgetActivatedMessage {
+    userId: number;
+}
+
+export function encodeGadgetActivated(id: number, data: GadgetActivatedMessage, name: string = "gadget_activated"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    return vmsg;
+}
+
+export function decodeGadgetActivated(vmsg: VTunnelMessage): GadgetActivatedMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+    };
+}
+
+export interface GadgetDeactivatedMessage {
+    userId: number;
+}
+
+export function encodeGadgetDeactivated(id: number, data: GadgetDeactivatedMessage, name: string = "gadget_deactivated"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    return vmsg;
+}
+
+export function decodeGadgetDeactivated(vmsg: VTunnelMessage): GadgetDeactivatedMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+    };
+}
+
+export interface GadgetSelectProgramMessage {
+    userId: number;
+    programName: string;
+}
+
+export function encodeGadgetSelectProgram(id: number, data: GadgetSelectProgramMessage, name: string = "gadget_select_program"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeString(data.programName);
+    return vmsg;
+}
+
+export function decodeGadgetSelectProgram(vmsg: VTunnelMessage): GadgetSelectProgramMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        programName: vmsg.indexPartDataAsString(1),
+    };
+}
+
+export interface NavFillProgressMessage {
+    userId: number;
+    added: number;
+    visited: number;
+    queued: number;
+    done: boolean;
+    cancelled: boolean;
+}
+
+export function encodeNavFillProgress(id: number, data: NavFillProgressMessage, name: string = "nav_fill_progress"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeInt(data.added);
+    vmsg.writeInt(data.visited);
+    vmsg.writeInt(data.queued);
+    vmsg.writeBoolean(data.done);
+    vmsg.writeBoolean(data.cancelled);
+    return vmsg;
+}
+
+export function decodeNavFillProgress(vmsg: VTunnelMessage): NavFillProgressMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        added: vmsg.indexPartDataAsInt(1),
+        visited: vmsg.indexPartDataAsInt(2),
+        queued: vmsg.indexPartDataAsInt(3),
+        done: vmsg.indexPartDataAsBoolean(4),
+        cancelled: vmsg.indexPartDataAsBoolean(5),
+    };
+}
+
+export interface NavEditHistoryMessage {
+    userId: number;
+    undoCount: number;
+    redoCount: number;
+    lastAction: string;
+}
+
+export function encodeNavEditHistory(id: number, data: NavEditHistoryMessage, name: string = "nav_edit_history"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeInt(data.undoCount);
+    vmsg.writeInt(data.redoCount);
+    vmsg.writeString(data.lastAction);
+    return vmsg;
+}
+
+export function decodeNavEditHistory(vmsg: VTunnelMessage): NavEditHistoryMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        undoCount: vmsg.indexPartDataAsInt(1),
+        redoCount: vmsg.indexPartDataAsInt(2),
+        lastAction: vmsg.indexPartDataAsString(3),
+    };
+}
+
+export interface NavLayerStateMessage {
+    userId: number;
+    mapName: string;
+    activeLayer: string;
+    activeLayerHidden: boolean;
+    hiddenCount: number;
+}
+
+export function encodeNavLayerState(id: number, data: NavLayerStateMessage, name: string = "nav_layer_state"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.userId);
+    vmsg.writeString(data.mapName);
+    vmsg.writeString(data.activeLayer);
+    vmsg.writeBoolean(data.activeLayerHidden);
+    vmsg.writeInt(data.hiddenCount);
+    return vmsg;
+}
+
+export function decodeNavLayerState(vmsg: VTunnelMessage): NavLayerStateMessage {
+    return {
+        userId: vmsg.indexPartDataAsInt(0),
+        mapName: vmsg.indexPartDataAsString(1),
+        activeLayer: vmsg.indexPartDataAsString(2),
+        activeLayerHidden: vmsg.indexPartDataAsBoolean(3),
+        hiddenCount: vmsg.indexPartDataAsInt(4),
+    };
+}
+
+export interface NavSyncChecksumMessage {
+    count: number;
+    checksum: number;
+}
+
+export function encodeNavSyncChecksum(id: number, data: NavSyncChecksumMessage, name: string = "nav_sync_checksum_result"): VTunnelMessage {
+    const vmsg = new VTunnelMessage(id, name);
+    vmsg.writeInt(data.count);
+    vmsg.writeInt(data.checksum);
+    return vmsg;
+}
+
+export function decodeNavSyncChecksum(vmsg: VTunnelMessage): NavSyncChecksumMessage {
+    return {
+        count: vmsg.indexPartDataAsInt(0),
+        checksum: vmsg.indexPartDataAsInt(1),
+    };
+}
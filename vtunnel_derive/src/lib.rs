@@ -0,0 +1,177 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type};
+
+/**
+ * Derives `VTunnelSerializable`, `VTunnelDeserializable` and `VTunnelSchema` for a struct with named fields.
+ *
+ * Fields are written to the message in declaration order, so the order here must match the
+ * order the script side reads/writes them in. Every field type must implement `VTunnelField`.
+ * A struct can have one `Option` field, which must be the last one. It's left off the message when `None`.
+ * Fields are read back by position, so a second one couldn't be sent on its own and is a compile error.
+ *
+ * ```ignore
+ * #[derive(VTunnelMessage)]
+ * #[vtunnel(name = "player_input")]
+ * pub struct PlayerInput { ... }
+ * ```
+ *
+ * Inside the vtunnel crate itself use `#[vtunnel(name = "...", crate = "crate")]`,
+ * since the generated code refers to `::vtunnel` by default.
+ */
+#[proc_macro_derive(VTunnelMessage, attributes(vtunnel))]
+pub fn derive_vtunnel_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_vtunnel_message(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_vtunnel_message(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let (message_name, crate_path) = parse_attributes(input)?;
+    let vtunnel_path = quote! { #crate_path::vtunnel };
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(&input.ident, "VTunnelMessage can only be derived for structs with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(&input.ident, "VTunnelMessage can only be derived for structs")),
+    };
+
+    let mut serialize_parts = Vec::new();
+    let mut read_parts = Vec::new();
+    let mut assign_parts = Vec::new();
+    let mut schema_parts = Vec::new();
+    let mut seen_optional = false;
+
+    for (index, field) in fields.iter().enumerate() {
+        let field_ident = field.ident.as_ref().unwrap();
+        let field_name = field_ident.to_string();
+        let local_ident = syn::Ident::new(&format!("__{}", field_name), Span::call_site());
+
+        let optional = option_inner_type(&field.ty).is_some();
+        let data_type = option_inner_type(&field.ty).unwrap_or(&field.ty);
+        schema_parts.push(quote! {
+            #crate_path::schema::VTunnelFieldSchema {
+                name: #field_name,
+                data_type: <#data_type as #vtunnel_path::VTunnelField>::DATA_TYPE,
+                optional: #optional,
+            }
+        });
+
+        if let Some(inner_type) = option_inner_type(&field.ty) {
+            if seen_optional {
+                return Err(syn::Error::new_spanned(field_ident, "only one Option field is allowed, it can't be read back once an earlier one is None"));
+            }
+
+            seen_optional = true;
+            serialize_parts.push(quote! {
+                if let Some(val) = &self.#field_ident {
+                    vmsg.data.push(<#inner_type as #vtunnel_path::VTunnelField>::to_data_part(val));
+                }
+            });
+            read_parts.push(quote! {
+                let #local_ident = msg.get_optional_field::<#inner_type>(#index, #field_name)?;
+            });
+        } else {
+            if seen_optional {
+                return Err(syn::Error::new_spanned(field_ident, "Option fields must come after all required fields"));
+            }
+
+            let field_type = &field.ty;
+            serialize_parts.push(quote! {
+                vmsg.data.push(<#field_type as #vtunnel_path::VTunnelField>::to_data_part(&self.#field_ident));
+            });
+            read_parts.push(quote! {
+                let #local_ident = msg.get_field::<#field_type>(#index, #field_name)?;
+            });
+        }
+
+        assign_parts.push(quote! {
+            self.#field_ident = #local_ident;
+        });
+    }
+
+    let ident = &input.ident;
+    let type_name = ident.to_string();
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #vtunnel_path::VTunnelSerializable for #ident #type_generics #where_clause {
+            fn serialize(&self) -> #vtunnel_path::VTunnelMessage {
+                let mut vmsg = #vtunnel_path::VTunnelMessage::new(#message_name.to_string());
+                #(#serialize_parts)*
+                vmsg
+            }
+        }
+
+        impl #impl_generics #vtunnel_path::VTunnelDeserializable for #ident #type_generics #where_clause {
+            fn apply_vtunnel_message(&mut self, msg: &#vtunnel_path::VTunnelMessage) -> Result<(), #vtunnel_path::VTunnelMessageError> {
+                // Read everything first so a bad message doesn't leave the struct half updated.
+                #(#read_parts)*
+                #(#assign_parts)*
+                Ok(())
+            }
+        }
+
+        impl #impl_generics #crate_path::schema::VTunnelSchema for #ident #type_generics #where_clause {
+            fn schema() -> #crate_path::schema::VTunnelMessageSchema {
+                #crate_path::schema::VTunnelMessageSchema {
+                    name: #message_name,
+                    type_name: #type_name,
+                    fields: vec![#(#schema_parts),*],
+                }
+            }
+        }
+    })
+}
+
+fn parse_attributes(input: &DeriveInput) -> syn::Result<(LitStr, syn::Path)> {
+    let mut message_name = None;
+    let mut crate_path: syn::Path = syn::parse_quote!(::vtunnel);
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("vtunnel")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                message_name = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                crate_path = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unknown vtunnel attribute, expected `name` or `crate`"))
+            }
+        })?;
+    }
+
+    let message_name = message_name
+        .ok_or_else(|| syn::Error::new_spanned(&input.ident, "missing #[vtunnel(name = \"...\")] attribute"))?;
+
+    Ok((message_name, crate_path))
+}
+
+/**
+ * Returns `T` if the type is `Option<T>`.
+ */
+fn option_inner_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+
+    match args.args.first()? {
+        GenericArgument::Type(inner_type) => Some(inner_type),
+        _ => None,
+    }
+}